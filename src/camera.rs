@@ -1,5 +1,7 @@
 use crate::{GameState, net_control::NetControl, net_control::PlayerType};
 use crate::player::Player;
+use crate::spectator::Spectating;
+use crate::replay::Playback;
 use bevy::prelude::*;
 
 #[derive(Resource, Clone, Copy)]
@@ -18,7 +20,8 @@ impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Playing), setup_game_camera)
             .add_systems(OnExit(GameState::Playing), cleanup_game_camera)
-            .add_systems(Update, camera_follow.run_if(in_state(GameState::Playing)));
+            //A replay moves the camera itself
+            .add_systems(Update, camera_follow.run_if(in_state(GameState::Playing)).run_if(not(resource_exists::<Playback>)));
     }
 }
 
@@ -35,14 +38,19 @@ fn cleanup_game_camera(mut commands: Commands, query: Query<Entity, With<GameCam
 fn camera_follow(
     windows: Query<&Window>,
     map: Res<MapBounds>,
+    spectating: Res<Spectating>,
     player_q: Query<(&Transform, &mut NetControl), With<Player>>,
     mut cam_q: Query<&mut Transform, (With<Camera2d>, With<GameCamera>, Without<Player>)>,
 )  {
        
-    //This gets the transform of the local player
+    //This gets the transform of the local player, or whoever we are spectating
     let mut temp_tf = None;
     for (trans, control) in player_q {
-        if control.player_type == PlayerType::Local {
+        let followed = match spectating.target {
+            Some(target) => control.player_id == target,
+            None => control.player_type == PlayerType::Local,
+        };
+        if followed {
             temp_tf = Some(trans);
         }
     }
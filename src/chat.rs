@@ -0,0 +1,479 @@
+use crate::{
+    GameState,
+    camera::GameCamera,
+    lobby::LobbyRoster,
+    player::player_color,
+    protocol::{MAX_CHAT_LEN, truncate_text},
+};
+use bevy::input::ButtonState;
+use bevy::input::InputSystem;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use std::collections::{HashMap, VecDeque};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Text chat and ping markers. Enter opens the chat box, T drops a marker where the mouse is. What  ///
+///   we send goes out as ChatSent/MarkerSent, the server or client module passes it on and answers    ///
+///   with ChatReceived/MarkerReceived for everything that should be shown, ours included.             ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Nothing else in Playing may use it, see the test at the bottom
+const PING_KEY: KeyCode = KeyCode::KeyT;
+//Lines kept in the chat log
+const CHAT_HISTORY: usize = 8;
+//How long a line stays up while the chat box is closed
+const CHAT_LINE_SECS: f32 = 10.0;
+const MARKER_SECS: f32 = 6.0;
+//Shortest time between two of our own markers
+const MARKER_COOLDOWN: f32 = 0.5;
+//Markers off screen stick to the edge this far in
+const MARKER_EDGE: f32 = 24.0;
+//How many ids per player are remembered to spot repeated copies
+const SEEN_IDS: usize = 64;
+
+//Typed by us, sent on by the server or client module
+#[derive(Event, Debug, Clone)]
+pub struct ChatSent {
+    pub text: String,
+}
+//A place in the world we pinged
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MarkerSent {
+    pub position: Vec2,
+}
+//A line or marker to show, from us or relayed by the host
+#[derive(Event, Debug, Clone)]
+pub struct ChatReceived {
+    pub player_id: u8,
+    pub text: String,
+}
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MarkerReceived {
+    pub player_id: u8,
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatItem {
+    Line(String),
+    Marker(Vec2),
+}
+
+//Chat bookkeeping for one session, kept by the host as a resource and by a client in ClientMetrics.
+//Lines and markers go out several times to ride out packet loss, so each gets an id and the copies
+//after the first are dropped here.
+#[derive(Resource)]
+pub struct ChatState {
+    next_id: u16,
+    seen: HashMap<u8, VecDeque<u16>>,
+    //(player_id, item) off the network that hasn't been handled yet
+    pub incoming: Vec<(u8, ChatItem)>,
+}
+
+impl Default for ChatState {
+    fn default() -> Self {
+        Self {
+            //Random so a player who rejoins doesn't reuse ids the others still remember
+            next_id: rand::random(),
+            seen: HashMap::new(),
+            incoming: Vec::new(),
+        }
+    }
+}
+
+impl ChatState {
+    pub fn next_id(&mut self) -> u16 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+
+    //Queues the item unless it is a copy of one already queued
+    pub fn receive(&mut self, player_id: u8, id: u16, item: ChatItem) {
+        let seen = self.seen.entry(player_id).or_default();
+        if seen.contains(&id) {
+            return;
+        }
+        if seen.len() == SEEN_IDS {
+            seen.pop_front();
+        }
+        seen.push_back(id);
+        self.incoming.push((player_id, item));
+    }
+}
+
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ChatSent>()
+            .add_event::<MarkerSent>()
+            .add_event::<ChatReceived>()
+            .add_event::<MarkerReceived>()
+            .init_resource::<ChatBox>()
+            .add_systems(OnEnter(GameState::Playing), setup_chat)
+            .add_systems(OnExit(GameState::Playing), cleanup_chat)
+            //Keys typed into the chat box shouldn't also move or shoot
+            .add_systems(
+                PreUpdate,
+                hold_game_keys
+                    .after(InputSystem)
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(|chat: Res<ChatBox>| chat.open),
+            )
+            .add_systems(
+                Update,
+                (type_chat, place_marker, show_chat, show_markers, move_markers)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+struct ChatBox {
+    open: bool,
+    typed: String,
+    //Newest last, with when each arrived
+    lines: VecDeque<(u8, String, f32)>,
+}
+
+#[derive(Component)]
+struct ChatRoot;
+
+#[derive(Component)]
+struct ChatLog;
+
+#[derive(Component)]
+struct ChatInput;
+
+#[derive(Component)]
+struct Marker {
+    player_id: u8,
+    position: Vec2,
+    expires: f32,
+}
+
+fn setup_chat(mut commands: Commands, mut chat: ResMut<ChatBox>) {
+    *chat = ChatBox::default();
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                width: Val::Px(480.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ChatRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                ChatLog,
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 18.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+                Visibility::Hidden,
+                ChatInput,
+            ));
+        });
+}
+
+fn cleanup_chat(mut commands: Commands, roots: Query<Entity, Or<(With<ChatRoot>, With<Marker>)>>) {
+    for entity in &roots {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn hold_game_keys(mut keys: ResMut<ButtonInput<KeyCode>>) {
+    keys.reset_all();
+}
+
+fn type_chat(
+    mut keys: EventReader<KeyboardInput>,
+    mut chat: ResMut<ChatBox>,
+    mut sent: EventWriter<ChatSent>,
+    mut input: Query<(&mut Text, &mut Visibility), With<ChatInput>>,
+) {
+    let was_open = chat.open;
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        match (&key.logical_key, chat.open) {
+            (Key::Enter, false) => chat.open = true,
+            (Key::Enter, true) => {
+                let text = chat.typed.trim().to_string();
+                if !text.is_empty() {
+                    sent.write(ChatSent { text });
+                }
+                chat.typed.clear();
+                chat.open = false;
+            }
+            (Key::Escape, true) => {
+                chat.typed.clear();
+                chat.open = false;
+            }
+            (Key::Backspace, true) => {
+                chat.typed.pop();
+            }
+            (Key::Space, true) => push_typed(&mut chat.typed, " "),
+            (Key::Character(chars), true) => push_typed(&mut chat.typed, chars),
+            _ => {}
+        }
+    }
+    if !chat.open && !was_open {
+        return;
+    }
+    if let Ok((mut text, mut visibility)) = input.single_mut() {
+        text.0 = format!("> {}_", chat.typed);
+        *visibility = if chat.open { Visibility::Inherited } else { Visibility::Hidden };
+    }
+}
+
+//Printable characters only, and no more than fits in a packet
+fn push_typed(typed: &mut String, chars: &str) {
+    typed.extend(chars.chars().filter(|c| !c.is_control()));
+    *typed = truncate_text(typed, MAX_CHAT_LEN);
+}
+
+fn place_marker(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    mut last: Local<Option<f32>>,
+    mut sent: EventWriter<MarkerSent>,
+) {
+    if !keys.just_pressed(PING_KEY) || last.is_some_and(|t| time.elapsed_secs() - t < MARKER_COOLDOWN) {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.single(), camera.single()) else {
+        return;
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return;
+    };
+    if let Ok(position) = camera.viewport_to_world_2d(camera_transform, cursor) {
+        *last = Some(time.elapsed_secs());
+        sent.write(MarkerSent { position });
+    }
+}
+
+pub fn player_name(roster: &LobbyRoster, player_id: u8) -> String {
+    roster
+        .players
+        .iter()
+        .find(|p| p.player_id == player_id)
+        .map_or_else(|| format!("Player {}", player_id + 1), |p| p.name.clone())
+}
+
+fn show_chat(
+    mut commands: Commands,
+    time: Res<Time>,
+    roster: Res<LobbyRoster>,
+    mut received: EventReader<ChatReceived>,
+    mut chat: ResMut<ChatBox>,
+    log: Query<Entity, With<ChatLog>>,
+    mut shown: Local<usize>,
+) {
+    let now = time.elapsed_secs();
+    for line in received.read() {
+        if chat.lines.len() == CHAT_HISTORY {
+            chat.lines.pop_front();
+        }
+        chat.lines.push_back((line.player_id, line.text.clone(), now));
+    }
+    //Everything while typing, otherwise just the recent lines
+    let visible = if chat.open {
+        chat.lines.len()
+    } else {
+        chat.lines.iter().filter(|(_, _, at)| now - at < CHAT_LINE_SECS).count()
+    };
+    if !chat.is_changed() && visible == *shown {
+        return;
+    }
+    *shown = visible;
+    let Ok(log) = log.single() else {
+        return;
+    };
+    commands.entity(log).despawn_related::<Children>().with_children(|parent| {
+        for (player_id, text, _) in chat.lines.iter().skip(chat.lines.len() - visible) {
+            parent
+                .spawn((
+                    Text::new(format!("{}: ", player_name(&roster, *player_id))),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(player_color(*player_id)),
+                ))
+                .with_child((
+                    TextSpan::new(text.clone()),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                ));
+        }
+    });
+}
+
+fn show_markers(
+    mut commands: Commands,
+    time: Res<Time>,
+    roster: Res<LobbyRoster>,
+    mut received: EventReader<MarkerReceived>,
+    markers: Query<(Entity, &Marker)>,
+) {
+    let now = time.elapsed_secs();
+    for marker in received.read() {
+        //One marker per player, a new one replaces the last
+        for (entity, old) in &markers {
+            if old.player_id == marker.player_id {
+                commands.entity(entity).despawn();
+            }
+        }
+        let color = player_color(marker.player_id);
+        commands
+            .spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                //Hidden until move_markers has put it in place
+                Visibility::Hidden,
+                Marker { player_id: marker.player_id, position: marker.position, expires: now + MARKER_SECS },
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    Text::new(player_name(&roster, marker.player_id)),
+                    TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                    TextColor(color),
+                ));
+                parent.spawn((
+                    Node {
+                        width: Val::Px(14.0),
+                        height: Val::Px(14.0),
+                        border: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    BackgroundColor(color.with_alpha(0.5)),
+                    BorderColor(Color::WHITE),
+                    Transform::from_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+                ));
+            });
+    }
+}
+
+//Markers are UI so the dark doesn't hide them, they follow their spot in the world each frame
+fn move_markers(
+    mut commands: Commands,
+    time: Res<Time>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    mut markers: Query<(Entity, &Marker, &mut Node, &mut Visibility, &ComputedNode)>,
+) {
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.single(), camera.single()) else {
+        return;
+    };
+    let now = time.elapsed_secs();
+    for (entity, marker, mut node, mut visibility, computed) in &mut markers {
+        if now >= marker.expires {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let Ok(screen) = camera.world_to_viewport(camera_transform, marker.position.extend(0.)) else {
+            continue;
+        };
+        let size = computed.size() * computed.inverse_scale_factor();
+        let max = (window.size() - Vec2::splat(MARKER_EDGE)).max(Vec2::splat(MARKER_EDGE));
+        //The diamond at the bottom of the marker points at the spot
+        let at = screen.clamp(Vec2::splat(MARKER_EDGE), max) - Vec2::new(size.x / 2., size.y);
+        node.left = Val::Px(at.x);
+        node.top = Val::Px(at.y);
+        *visibility = Visibility::Inherited;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_key_is_free_in_game() {
+        //Every other key read while Playing
+        let mut taken = vec![KeyCode::KeyW, KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD, KeyCode::KeyO, KeyCode::Enter];
+        taken.extend(crate::menu::QUIT_KEYS);
+        taken.extend(crate::spectator::NEXT_KEYS);
+        taken.extend([
+            crate::spectator::PREVIOUS_KEY,
+            crate::example_scene::TOGGLE_KEY,
+            crate::net_overlay::TOGGLE_KEY,
+            crate::netsim::TOGGLE_KEY,
+        ]);
+        assert!(!taken.contains(&PING_KEY));
+    }
+
+    #[test]
+    fn repeated_copies_are_dropped() {
+        let mut chat = ChatState::default();
+        chat.receive(1, 5, ChatItem::Line("hi".to_string()));
+        chat.receive(1, 5, ChatItem::Line("hi".to_string()));
+        //Same id from someone else is a different message, and late copies still count as seen
+        chat.receive(2, 5, ChatItem::Marker(Vec2::ONE));
+        chat.receive(1, 6, ChatItem::Line("there".to_string()));
+        chat.receive(1, 5, ChatItem::Line("hi".to_string()));
+        assert_eq!(
+            chat.incoming,
+            vec![
+                (1, ChatItem::Line("hi".to_string())),
+                (2, ChatItem::Marker(Vec2::ONE)),
+                (1, ChatItem::Line("there".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_recent_ids_are_remembered() {
+        let mut chat = ChatState::default();
+        for id in 0..=SEEN_IDS as u16 {
+            chat.receive(0, id, ChatItem::Marker(Vec2::ZERO));
+        }
+        chat.receive(0, 0, ChatItem::Marker(Vec2::ZERO));
+        chat.receive(0, SEEN_IDS as u16, ChatItem::Marker(Vec2::ZERO));
+        assert_eq!(chat.incoming.len(), SEEN_IDS + 2);
+    }
+
+    #[test]
+    fn ids_count_up_and_wrap() {
+        let mut chat = ChatState { next_id: u16::MAX - 1, ..default() };
+        assert_eq!([chat.next_id(), chat.next_id()], [u16::MAX, 0]);
+    }
+
+    #[test]
+    fn typing_is_limited_to_what_fits() {
+        let mut typed = String::new();
+        push_typed(&mut typed, "a\tb\u{7f}");
+        assert_eq!(typed, "ab");
+        push_typed(&mut typed, &"é".repeat(MAX_CHAT_LEN));
+        assert!(typed.len() <= MAX_CHAT_LEN);
+        assert!(typed.starts_with("abé"));
+    }
+}
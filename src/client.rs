@@ -4,19 +4,112 @@ use crate::{
     collectible::PlayerInventory,
     enemy::Enemy,
     components::Health,
+    protocol::{DoorState, HOST_ID, MAX_INPUTS_PER_PACKET, Message, NONCE_LEN, PlayerStatus, ResumeState},
+    secure::{AuthError, Dropped, SecureChannel, Side, open_datagram},
+    config::Config,
+    snapshot::SnapshotReceiver,
+    interpolation::InterpolationBuffer,
+    prediction::{MoveState, Prediction},
+    player::{Velocity, movement_step, static_shapes},
+    camera::MapBounds,
+    components::{Collectible as OldCollectible, Dead, KinematicCollider, NetId, Sanity, StaticCollider},
+    collectible::Collectible,
+    wall::Door,
+    netsim::{NetSimulator, NetSocket},
+    net_thread::{NetThread, Sealer},
+    enemy::EnemyType,
+    events::{EnemyDamagedEvent, EnemyKilledEvent, EnemySpawnedEvent},
+    lobby::LobbyRoster,
+    netstats::NetStats,
+    chat::{ChatItem, ChatReceived, ChatSent, ChatState, MarkerReceived, MarkerSent},
+    spectator::Spectating,
+    level::HostLevel,
+    sim::TICK_SECS,
 };
 use bevy::prelude::*;
 use bevy::time::Stopwatch;
 use std::time::Duration;
-use std::net::UdpSocket;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
 use std::collections::HashMap;
-use std::env;
+use std::sync::{Arc, Mutex};
+
+//How often we ping the host while we have nothing else to send
+const HEARTBEAT_INTERVAL: f32 = 1.0;
+//How often we ping the host to measure the round trip for the connection stats
+const PING_INTERVAL: f32 = 0.25;
+//Our chat lines, markers and who we spectate go out this many ticks in a row, the host ignores the copies
+const CHAT_REPEATS: u8 = 3;
+//How long the host can stay silent before we give up on it
+const HOST_TIMEOUT: f32 = 5.0;
+//How long we keep trying to get back into a match after losing the host, the host holds our slot longer
+const REJOIN_TIMEOUT: f32 = 30.0;
+
+//The socket itself lives on the network thread, see net_thread.rs
+#[derive(Resource)]
+pub struct SocketResource {
+    net: NetThread,
+    secure: Arc<Mutex<ClientSecurity>>,
+}
+impl SocketResource {
+    //Sealed and sent from the network thread. The address is the one client_connect resolved.
+    fn send(&self, msg: &Message, addr: &str) {
+        match addr.parse() {
+            Ok(addr) => self.net.send(msg, addr),
+            Err(_) => warn!("Could not send to {}: not a resolved address", addr),
+        }
+    }
+
+    fn has_session(&self) -> bool {
+        self.secure.lock().unwrap().channel.is_some()
+    }
 
-const IP_CONST: &str = "0.0.0.0:";
+    fn hello(&self) -> Message {
+        Message::Hello { nonce: self.secure.lock().unwrap().nonce }
+    }
+
+    //Makes the session keys from the host's Challenge, a repeat of the same one changes nothing
+    fn accept_challenge(&self, host_nonce: [u8; NONCE_LEN], encrypted: bool) {
+        let mut secure = self.secure.lock().unwrap();
+        if secure.channel.as_ref().is_some_and(|(nonce, _)| *nonce == host_nonce) {
+            return;
+        }
+        let channel = SecureChannel::new(&secure.code, &secure.nonce, &host_nonce, encrypted, Side::Client);
+        secure.channel = Some((host_nonce, channel));
+    }
+}
+
+//Our half of secure.rs: the code we were given, our handshake nonce and the session once the host
+//has answered, along with the host's nonce it came from
+struct ClientSecurity {
+    code: String,
+    nonce: [u8; NONCE_LEN],
+    channel: Option<([u8; NONCE_LEN], SecureChannel)>,
+}
 
+impl Sealer for ClientSecurity {
+    fn open(&mut self, datagram: &[u8], _: SocketAddr) -> Result<Message, Dropped> {
+        open_datagram(self.channel.as_mut().map(|(_, channel)| channel), datagram)
+    }
+
+    //Sealed with the session key unless it is part of the handshake
+    fn seal(&mut self, msg: &Message, _: SocketAddr) -> Option<Vec<u8>> {
+        if msg.is_open() {
+            return Some(msg.to_bytes());
+        }
+        //The host would only drop it
+        let (_, channel) = self.channel.as_mut()?;
+        Some(channel.seal(&msg.to_bytes()))
+    }
+}
+
+//Our place in a match, kept when the connection drops so we can take the same player back
 #[derive(Resource)]
-pub struct SocketResource {
-    socket: UdpSocket,
+pub struct Session {
+    //Resolved host address the token is for
+    pub endpoint: String,
+    pub token: u64,
+    //Lost the host mid match and trying to get back in
+    pub rejoining: bool,
 }
 
 //Server IP
@@ -38,16 +131,17 @@ impl Plugin for ClientPlugin {
         app.add_systems(
             OnEnter(GameState::Joining),
             (
-                client_init.before(client_start),
-                client_start.before(client_connect),
-                client_connect,
+                client_init.before(client_connect),
+                //Skipped when the bind failed and we are on our way back to the menu
+                client_connect.run_if(resource_exists::<SocketResource>),
             ),
         )
         .add_systems(
             FixedUpdate,
             client_run
                 .run_if(in_state(GameState::Joining))
-                .run_if(type_equals_client),
+                .run_if(type_equals_client)
+                .run_if(resource_exists::<SocketResource>),
         )
         .add_systems(
             FixedUpdate,
@@ -61,9 +155,43 @@ impl Plugin for ClientPlugin {
                 .run_if(in_state(GameState::Playing))
                 .run_if(type_equals_client),
         )
+        .add_systems(
+            Update,
+            (apply_resume, apply_player_status, apply_world_state, exchange_chat, send_spectate)
+                .chain()
+                .run_if(in_state(GameState::Playing))
+                .run_if(type_equals_client),
+        )
+        .add_systems(
+            FixedUpdate,
+            (client_heartbeat, client_timeout, send_ping, send_outgoing, collect_traffic)
+                .after(client_run)
+                .run_if(in_state(GameState::Joining).or(in_state(GameState::Playing)))
+                .run_if(type_equals_client)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            FixedUpdate,
+            send_ready
+                .after(client_heartbeat)
+                .run_if(in_state(GameState::Joining))
+                .run_if(type_equals_client)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            Update,
+            apply_net_sim
+                .run_if(type_equals_client)
+                .run_if(resource_exists::<SocketResource>)
+                .run_if(resource_changed::<NetSimulator>),
+        )
         .add_systems(
             OnExit(GameState::Playing),
             client_close.run_if(type_equals_client),
+        )
+        .add_systems(
+            OnTransition { exited: GameState::Joining, entered: GameState::Menu },
+            client_close.run_if(type_equals_client),
         );
     }
 }
@@ -72,296 +200,732 @@ fn type_equals_client(game_type: Res<LogicType>) -> bool {
     return game_type.l_type == AssignedType::Client;
 }
 
-fn client_init(mut commands: Commands) {
+fn client_init(
+    mut commands: Commands,
+    config: Res<Config>,
+    sim: Res<NetSimulator>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
     info!("In client init");
-    //The random port is so I can hard code it and run more than one client at a time.
-    let newIP = IP_CONST.to_owned() + &rand::random_range(25000..25999).to_string();
-    commands.insert_resource(SocketResource {
-        socket: UdpSocket::bind(newIP).expect("ERROR"),
-    });
+    //Without a fixed port a random one is used so more than one client can run on a machine.
+    //An IPv4 socket can't reach an IPv6 host, so bind in the host's family. An address that doesn't
+    //resolve is reported by client_connect.
+    let any = match config.server_endpoint().to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+        Some(SocketAddr::V6(_)) => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+        _ => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+    };
+    let addr = SocketAddr::new(any, config.client_port());
     commands.insert_resource(ClientMetrics::default());
+    commands.insert_resource(NetStats::default());
+    //A new nonce every time, so every attempt to join gets fresh keys
+    let secure = Arc::new(Mutex::new(ClientSecurity {
+        code: config.join_code.clone().unwrap_or_default(),
+        nonce: rand::random(),
+        channel: None,
+    }));
+    let net = UdpSocket::bind(addr)
+        .and_then(|socket| NetThread::spawn("client network", NetSocket::new(socket, sim.active()), secure.clone()));
+    match net {
+        Ok(net) => commands.insert_resource(SocketResource { net, secure }),
+        Err(e) => {
+            error!("Could not open client socket on {}: {}", addr, e);
+            next_state.set(GameState::Menu);
+        }
+    }
     commands.insert_resource(ServerIP::new("0.0.0.0".to_string()));
 }
 
-fn client_start(socket: ResMut<SocketResource>) {
-    info!("In client start");
-    //This makes it so the game doesn't wait to receive a message, before going to the next frame
-    socket.socket.set_nonblocking(true);
+//F8 or the config changed the simulated network
+fn apply_net_sim(sim: Res<NetSimulator>, socket: Res<SocketResource>) {
+    socket.net.set_conditions(sim.active());
 }
 
-fn client_close(mut commands: Commands) {
+fn client_close(
+    mut commands: Commands,
+    socket: Option<Res<SocketResource>>,
+    session: Option<Res<Session>>,
+    cm: Res<ClientMetrics>,
+    serverip: Res<ServerIP>,
+) {
+    //On the way to rejoin the host keeps our slot, so don't give it up
+    let rejoining = session.is_some_and(|s| s.rejoining);
+    //Tell the host we are gone so it doesn't have to wait for the timeout
+    if let (Some(socket), Some(player_id), false) = (socket, cm.player_id, rejoining) {
+        socket.send(&Message::Leave { player_id }, &serverip.serverIP);
+        commands.remove_resource::<Session>();
+    }
     commands.remove_resource::<SocketResource>();
 }
 
+//Carries our session token when we have one for this host
+fn join_message(config: &Config, session: Option<&Session>, endpoint: &str) -> Message {
+    let token = session.filter(|s| s.endpoint == endpoint).map_or(0, |s| s.token);
+    Message::Join { name: config.player_name.clone(), token }
+}
+
 fn client_connect(
+    time: Res<Time<Real>>,
+    config: Res<Config>,
+    session: Option<Res<Session>>,
     socket: ResMut<SocketResource>,
     mut serverip: ResMut<ServerIP>,
     mut cm: ResMut<ClientMetrics>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
     info!("In client connect, Starting Timer");
-    let args: Vec<String> = env::args().collect();
-    let mut newIP = "".to_string();;
-    if args.len() > 1 {
-        newIP = args[1].to_owned() + ":2525";
-        serverip.serverIP = newIP.clone();
-    }
-    else
-    {
-        newIP = "127.0.0.1:2525".to_string();
-        serverip.serverIP = newIP.clone();
-    }
+    //Resolve once up front so a typo in the address is reported instead of failing every send
+    let endpoint = config.server_endpoint();
+    let newIP = match endpoint.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr.to_string(),
+        Ok(None) | Err(_) => {
+            error!("Could not resolve server address {}", endpoint);
+            next_state.set(GameState::Menu);
+            return;
+        }
+    };
+    serverip.serverIP = newIP.clone();
     //info!("{}", newIP);
     let mut buf = [0];
     cm.sw.tick(Duration::from_millis(1));
     cm.sw.reset();
-    socket.socket.send_to(&[255], newIP.clone()).expect("couldn't send data");
+    //Give the host the full timeout to answer
+    cm.last_heard = time.elapsed_secs();
+    socket.send(&socket.hello(), &newIP);
 }
 
 fn client_run(
     mut commands: Commands,
-    input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time<Real>>,
     socket: ResMut<'_, SocketResource>,
-    mut p_loc: Query<(&mut NetControl, &mut Transform, &mut PlayerInventory), With<NetControl>>,
-    mut enemy: Query<(&mut Enemy, &mut Transform), (With <Enemy>, Without<NetControl>)>,
+    mut p_loc: Query<(Entity, &mut NetControl, Option<(&mut PlayerInventory, &mut Player)>, Option<&mut InterpolationBuffer>), With<NetControl>>,
+    mut enemy: Query<(&Enemy, &mut InterpolationBuffer), (With <Enemy>, Without<NetControl>)>,
     mut next_state: ResMut<NextState<GameState>>,
     mut cm: ResMut<ClientMetrics>,
+    mut spawned: EventWriter<EnemySpawnedEvent>,
+    mut damaged: EventWriter<EnemyDamagedEvent>,
+    mut killed: EventWriter<EnemyKilledEvent>,
+    mut roster: ResMut<LobbyRoster>,
+    mut session: Option<ResMut<Session>>,
+    state: Res<State<GameState>>,
+    serverip: Res<ServerIP>,
+    mut stats: ResMut<NetStats>,
+    mut host_level: EventWriter<HostLevel>,
 ) {
-    let mut buf = [0; 321];
-    //Fake packet loss option
-    if !input.pressed(KeyCode::KeyP) {
-        for l in 1..20 {
-            match socket.socket.recv_from(&mut buf) {
-                Ok((amt, src)) => {
-                    //info!("{:?} + {:?} + {:?}", amt, src, buf);
-                    match buf[0] {
-                        //Code 0 -> Game Started. Send player counts for NetControl initialization.
-                        0 => {
-                            for i in 0..buf[1] {
-                                if i == buf[2] {
-                                    commands.spawn(
-                                        (NetControl::new(false, PlayerType::Local, i, None),
-                                        Local,
-                                    )
-                                );
-                                    info!("I am player: {}", i);
-                                } else {
-                                    commands.spawn((NetControl::new(
-                                        false,
-                                        PlayerType::Network,
-                                        i,
-                                        None,
-                                    ),
-                                    Network,
-                                    )
-                                );
-                                    info!("Created net player: {}", i);
-                                }
-                            }
-                            //Start the game
-                            info!("PLAY STATE");
-                            next_state.set(GameState::Playing);
-                        }
+    //Everything the network thread has read since last tick
+    while let Some(received) = socket.net.try_recv() {
+        //Interpolation and round trips go by when a datagram arrived, not when we got to it
+        let (src, arrived) = (received.from, received.arrived(&time));
+        let msg = match received.msg {
+            Ok(msg) => msg,
+            Err(Dropped::Malformed(e)) => {
+                //Malformed or wrong version packets are dropped here
+                warn!("Dropped packet from {:?}: {}", src, e);
+                continue;
+            }
+            Err(Dropped::Unauthenticated(e)) => {
+                if e == AuthError::Replayed {
+                    cm.replayed += 1;
+                } else {
+                    cm.forged += 1;
+                }
+                debug!("Dropped packet from {:?}: {}", src, e);
+                continue;
+            }
+        };
+        //Anything from the host counts as a heartbeat
+        cm.last_heard = time.elapsed_secs();
+        match msg {
+            //Host let us in, the id may change while we wait in the lobby if someone leaves
+            Message::Accept { player_id, player_count, token } => {
+                cm.rtt = cm.sw.elapsed();
+                if cm.player_id != Some(player_id) {
+                    info!("Joined as player {} of {}", player_id, player_count);
+                }
+                cm.player_id = Some(player_id);
+                commands.insert_resource(Session { endpoint: serverip.serverIP.clone(), token, rejoining: false });
+            }
 
-                        //Code 1 -> Player position/angle update.
-                        1 => {
-                            cm.rtt = cm.sw.elapsed();
-                            //info!("Ping: {:?}", cm.rtt);
-                            for (mut control, mut trans, mut inv) in p_loc.iter_mut() {
-                                //The first check hard limits us to 4 players (pid 0 to 3) as I started packing shooting into the same byte.
-                                //The second check prevents server from overwriting active player info. Will need to add 'else' to handle rollback system
-                                if control.player_id == (buf[1] & 3) && control.player_type == PlayerType::Network {
-                                    let mut inp_pack = [0; 11];
-                                    inp_pack.copy_from_slice(&buf[0..11]); 
-                                    control.set_player_state(inp_pack);
-                                //Sends the updated info except angle, used for rollback
-                                } else if control.player_id == (buf[1] & 3) && control.player_type == PlayerType::Local {
-                                    //info!("{:08b}", buf[1]);
-                                    let mut inp_pack = [0; 11];
-                                    inp_pack.copy_from_slice(&buf[0..11]);
-                                    control.set_player_state_limited(inp_pack);
-                                }
-                            }
-                        }
-                        //Code 2 -> Clock Sync
-                        2 => {
-                            cm.rtt = cm.sw.elapsed();
-                            //info!("Ping: {:?}", cm.rtt);
-                        }
+            //Host is ready for a sealed Join, which the heartbeat sends straight away
+            Message::Challenge { nonce, encrypted } => {
+                if cm.player_id.is_none() {
+                    socket.accept_challenge(nonce, encrypted);
+                    let interval = cm.heartbeat.duration();
+                    cm.heartbeat.set_elapsed(interval);
+                }
+            }
 
-                        //Request input history
-                        3 => {
-                            cm.send_history = true;
-                            for (mut control, mut trans, mut inv) in p_loc.iter_mut() {
-                                if control.player_id == (buf[1] & 3) {
-                                    let mut inp_pack = [0; 11];
-                                    inp_pack.copy_from_slice(&buf[0..11]);
-                                    control.set_player_state_limited(inp_pack);
-                                    //HISTORY DEBUG
-                                    //info!("Ideal Final Rollback Position = {:?}", trans.translation);
-                                }
-                            }
-                        }
+            //Host turned us away. Rejects aren't sealed, so once we are in they can't be trusted.
+            Message::Reject(_) if cm.player_id.is_some() => {}
+            Message::Reject(reason) => {
+                warn!("Host rejected join: {:?}", reason);
+                commands.remove_resource::<Session>();
+                next_state.set(GameState::Menu);
+            }
 
-                        //Enemy Packet update
-                        4 => {
-                            //info!("ENEMY: {:?}", buf);
-                            let mut start = 1;
-
-                            let mut enemy_list = HashMap::new();
-                            while start < 321 {
-                                if buf[start] != 0 {
-                                    let mut unpack_x: [u8; 2] = [0; 2];
-                                    let mut unpack_y: [u8; 2] = [0; 2];
-                                    unpack_x.copy_from_slice(&buf[(start+1 as usize)..=(start+2 as usize)]);
-                                    unpack_y.copy_from_slice(&buf[(start+3 as usize)..=(start+4 as usize)]);
-                                    let x = i16::from_ne_bytes(unpack_x);
-                                    let y = i16::from_ne_bytes(unpack_y);
-                                    enemy_list.insert(buf[start], (x,y));
-                                }
-                                start += 5;
-                            }
-
-                            for (mut enemy, mut enemy_trans) in enemy.iter_mut() {
-                                match enemy_list.get(&enemy.enemy_id) {
-                                    Some(out_tup) => {
-                                        enemy_trans.translation.x = out_tup.0 as f32;
-                                        enemy_trans.translation.y = out_tup.1 as f32;
-                                        //info!("Enemy {} -> {:?}", enemy.enemy_id, enemy_trans.translation);
-                                    }
-                                    None => {}
-                                }
-                            }
+            //Host shut down, or another player dropped out
+            Message::Leave { player_id } => {
+                if player_id == HOST_ID {
+                    warn!("Host closed the game");
+                    if let Some(session) = session.as_mut() {
+                        session.rejoining = false;
+                    }
+                    commands.remove_resource::<Session>();
+                    next_state.set(GameState::Menu);
+                } else {
+                    for (entity, control, _, _) in p_loc.iter() {
+                        if control.player_id == player_id && control.player_type == PlayerType::Network {
+                            commands.entity(entity).despawn();
+                            info!("Player {} left", player_id);
                         }
+                    }
+                }
+            }
 
-                        //Inventory Packet update
-                        5 => {
-                            for (mut control, mut trans, mut inv) in p_loc.iter_mut() {
-                                if control.player_id == buf[1] {
-                                    //Update Inventory
-                                    let mut inv_pack:[u8;2] = [0;2];
-                                    inv_pack[0]=buf[2];
-                                    inv_pack[1]=buf[3];
-                                    inv.inv_from_bytes(inv_pack);
-                                }
-                            }
-                        }
+            Message::Heartbeat => {}
+
+            //Who else is waiting, shown by the lobby screen
+            Message::LobbyState(players) => {
+                roster.players = players;
+            }
+
+            //Host measuring our ping, send its clock straight back
+            Message::Ping { time_ms } => {
+                socket.send(&Message::Pong { time_ms }, &serverip.serverIP);
+            }
+
+            //Answer to one of our pings, time_ms is our own clock from when it went out
+            Message::Pong { time_ms } => {
+                let ping = (arrived.as_millis() as u32).wrapping_sub(time_ms);
+                stats.peer(HOST_ID).record_rtt(ping as f32);
+            }
+
+            //Game Started. Send player counts for NetControl initialization.
+            Message::Start { player_count, player_id, level_seed } => {
+                //The host repeats Start to a rejoining client until it stops asking
+                if *state.get() != GameState::Joining {
+                    continue;
+                }
+                cm.player_id = Some(player_id);
+                if let Some(session) = session.as_mut() {
+                    session.rejoining = false;
+                }
+                //A fresh start on the host's side too, it has to hear who we watch again
+                cm.sent_spectate = None;
+                if player_id >= player_count {
+                    info!("Match already under way, spectating");
+                }
+                for i in 0..player_count {
+                    if i == player_id {
+                        commands.spawn(
+                            (NetControl::new(false, PlayerType::Local, i, None),
+                            Local,
+                        )
+                    );
+                        info!("I am player: {}", i);
+                    } else {
+                        commands.spawn((NetControl::new(
+                            false,
+                            PlayerType::Network,
+                            i,
+                            None,
+                        ),
+                        Network,
+                        InterpolationBuffer::default(),
+                        )
+                    );
+                        info!("Created net player: {}", i);
+                    }
+                }
+                //Whatever level we had loaded, the world is built from the host's
+                host_level.write(HostLevel { seed: level_seed });
+                //Start the game
+                info!("PLAY STATE");
+                next_state.set(GameState::Playing);
+            }
 
-                        _ => {
-                            info!("{:?} + {:?} + {:?}", amt, src, buf);
+            //Player position/angle update.
+            Message::PlayerState(state) => {
+                cm.rtt = cm.sw.elapsed();
+                //info!("Ping: {:?}", cm.rtt);
+                for (_, mut control, _, buffer) in p_loc.iter_mut() {
+                    //The check prevents server from overwriting active player info, our own player is reconciled in input_converter
+                    if control.player_id == state.player_id && control.player_type == PlayerType::Network {
+                        control.set_player_state(state);
+                        if let Some(mut buffer) = buffer {
+                            let pos = control.get_p_pos().truncate();
+                            buffer.push(arrived.as_secs_f64(), pos, Some(control.get_angle()));
                         }
-                        
+                    //Everything but angle, the position is checked against our prediction next tick
+                    } else if control.player_id == state.player_id && control.player_type == PlayerType::Local {
+                        control.set_player_state_limited(state);
+                        cm.authoritative = Some((state.ack, control.get_p_pos().truncate()));
                     }
                 }
-                Err(e) => {
-                    //info!("ERROR");
+            }
+            //Clock Sync
+            Message::ClockSync { .. } => {
+                cm.rtt = cm.sw.elapsed();
+                //info!("Ping: {:?}", cm.rtt);
+            }
+
+            //World snapshot, only complete once every fragment is in
+            Message::Snapshot(snapshot) => {
+                //One goes out every tick, so gaps in the ticks are lost packets
+                stats.peer(HOST_ID).record_seq(snapshot.tick as u16);
+                let Some((tick, enemy_list)) = cm.snapshots.receive(snapshot) else {
+                    continue;
+                };
+                stats.peer(HOST_ID).record_snapshot(arrived.as_secs_f64());
+                //Enemies outside our area of interest aren't in the snapshot and just stay put
+                //Positions go into each enemy's buffer and are drawn a little in the past
+                for (enemy, mut buffer) in enemy.iter_mut() {
+                    if let Some(&(x, y)) = enemy_list.get(&enemy.enemy_id) {
+                        buffer.push(arrived.as_secs_f64(), Vec2::new(x as f32, y as f32), None);
+                    }
+                }
+                socket.send(&Message::SnapshotAck { tick }, &serverip.serverIP);
+            }
+
+            //Enemy set changes, applied by the enemy plugin
+            Message::EnemySpawns(spawns) => {
+                for spawn in spawns {
+                    let Some(enemy_type) = EnemyType::from_byte(spawn.kind) else {
+                        warn!("Unknown enemy kind {} for enemy {}", spawn.kind, spawn.enemy_id);
+                        continue;
+                    };
+                    spawned.write(EnemySpawnedEvent {
+                        enemy_id: spawn.enemy_id,
+                        enemy_type,
+                        position: Vec2::new(spawn.x as f32, spawn.y as f32),
+                        health: spawn.health as i32,
+                    });
+                }
+            }
+            Message::EnemyDamage(hits) => {
+                for hit in hits {
+                    damaged.write(EnemyDamagedEvent { enemy_id: hit.enemy_id, health: hit.health as i32 });
+                }
+            }
+            Message::EnemyDeaths(ids) => {
+                for enemy_id in ids {
+                    killed.write(EnemyKilledEvent { enemy_id });
                 }
             }
+
+            //We rejoined, picked up by apply_resume once the world is built
+            Message::Resume(state) => {
+                cm.resume = Some(state);
+            }
+            //Applied by apply_world_state, oldest first so the newest state wins
+            Message::DoorStates(doors) => {
+                cm.doors.extend(doors);
+            }
+            Message::CollectiblesTaken(ids) => {
+                cm.taken.extend(ids);
+            }
+
+            //Newest wins, applied by apply_player_status
+            Message::PlayerStatus(players) => {
+                cm.status = players;
+            }
+
+            //Everyone's chat, ours included, shown by exchange_chat
+            Message::Chat { player_id, id, text } => {
+                cm.chat.receive(player_id, id, ChatItem::Line(text));
+            }
+            Message::Marker { player_id, id, x, y } => {
+                cm.chat.receive(player_id, id, ChatItem::Marker(Vec2::new(x as f32, y as f32)));
+            }
+
+            //Inventory Packet update
+            Message::Inventory { player_id, inventory } => {
+                for (_, control, owned, _) in p_loc.iter_mut() {
+                    //Players don't have an inventory until the world is built
+                    let Some((mut inv, mut player)) = owned else {
+                        continue;
+                    };
+                    if control.player_id == player_id {
+                        //Update Inventory
+                        inv.apply_state(&inventory);
+                        player.charge = inventory.charge as i32;
+                    }
+                }
+            }
+
+            _ => {
+                info!("Unexpected message from {:?}: {:?}", src, msg);
+            }
+
         }
     }
 }
 
-pub fn input_converter(
+fn client_heartbeat(
     time: Res<Time>,
-    input: Res<ButtonInput<KeyCode>>,
-    mouse_button_io: Res<ButtonInput<MouseButton>>,
-    socket: ResMut<SocketResource>,
-    mut pl_cont: Query<(&mut NetControl, &mut Transform, &PlayerInventory), (With<NetControl>, With<Local>)>,
+    config: Res<Config>,
+    session: Option<Res<Session>>,
+    socket: Res<SocketResource>,
     mut cm: ResMut<ClientMetrics>,
-    serverip: ResMut<ServerIP>,
-)   {
-        let mut input_result: u8 = 0;
-        let (mut player, mut transform, inventory) = pl_cont.single_mut().expect("Client Player not found");
-        //WASD00L0
-        if input.pressed(KeyCode::KeyW) {
-            input_result += 128;
+    serverip: Res<ServerIP>,
+) {
+    if !cm.heartbeat.tick(time.delta()).just_finished() {
+        return;
+    }
+    //Until the host answers, keep asking. Rejoining clients get Start instead of Accept.
+    if cm.player_id.is_none() && !socket.has_session() {
+        socket.send(&socket.hello(), &serverip.serverIP);
+    } else if cm.player_id.is_none() {
+        socket.send(&join_message(&config, session.as_deref(), &serverip.serverIP), &serverip.serverIP);
+    } else {
+        socket.send(&Message::Heartbeat, &serverip.serverIP);
+    }
+}
+
+//The host sends its own clock straight back, the difference is our round trip
+fn send_ping(
+    time: Res<Time>,
+    real_time: Res<Time<Real>>,
+    socket: Res<SocketResource>,
+    mut cm: ResMut<ClientMetrics>,
+    serverip: Res<ServerIP>,
+) {
+    if !cm.ping.tick(time.delta()).just_finished() || cm.player_id.is_none() {
+        return;
+    }
+    socket.send(&Message::Ping { time_ms: real_time.elapsed().as_millis() as u32 }, &serverip.serverIP);
+}
+
+//Everything on this socket is to or from the host
+fn collect_traffic(socket: Res<SocketResource>, mut stats: ResMut<NetStats>) {
+    for traffic in socket.net.take_traffic().into_values() {
+        stats.peer(HOST_ID).add_traffic(traffic);
+    }
+}
+
+fn client_timeout(
+    time: Res<Time<Real>>,
+    cm: Res<ClientMetrics>,
+    state: Res<State<GameState>>,
+    session: Option<ResMut<Session>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let rejoining = session.as_ref().is_some_and(|s| s.rejoining);
+    let timeout = if rejoining { REJOIN_TIMEOUT } else { HOST_TIMEOUT };
+    if time.elapsed_secs() - cm.last_heard <= timeout {
+        return;
+    }
+    match (state.get(), session) {
+        //Joining again opens a fresh socket and asks for our old slot back
+        (GameState::Playing, Some(mut session)) => {
+            warn!("Connection to host lost, trying to rejoin");
+            session.rejoining = true;
+            next_state.set(GameState::Joining);
         }
+        (_, session) => {
+            warn!("Connection to host lost");
+            //The token is kept, joining the same host again from the menu still gets our slot back
+            if let Some(mut session) = session {
+                session.rejoining = false;
+            }
+            next_state.set(GameState::Menu);
+        }
+    }
+}
 
-        if input.pressed(KeyCode::KeyA) {
-            input_result += 64;
+//Puts our player back the way the host had it when we rejoined. The world is built the frame after
+//Start, so this waits until our player exists.
+fn apply_resume(
+    mut commands: Commands,
+    mut cm: ResMut<ClientMetrics>,
+    mut players: Query<
+        (Entity, &mut NetControl, &mut Transform, &mut Velocity, &mut Health, &mut Sanity, &mut Player, &mut PlayerInventory),
+        With<Local>,
+    >,
+) {
+    if let Some(state) = cm.resume {
+        if let Ok((entity, mut control, mut transform, mut velocity, mut health, mut sanity, mut player, mut inventory)) =
+            players.single_mut()
+        {
+            cm.resume = None;
+            let pos = Vec2::new(state.x as f32, state.y as f32);
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+            control.set_pos_x(pos.x);
+            control.set_pos_y(pos.y);
+            **velocity = Vec2::ZERO;
+            health.current = state.health as i32;
+            if health.is_dead() {
+                commands.entity(entity).insert(Dead);
+            }
+            sanity.current = state.sanity_tenths as f32 / 10.;
+            player.charge = state.inventory.charge as i32;
+            inventory.apply_state(&state.inventory);
+            info!("Resumed at {:?} with {} health", pos, health.current);
         }
+    }
+}
 
-        if input.pressed(KeyCode::KeyS) {
-            input_result += 32;
+//The host decides who is hurt, mad or dead, so the HUD, revives and the lose check all go by its numbers
+fn apply_player_status(
+    mut commands: Commands,
+    mut cm: ResMut<ClientMetrics>,
+    mut players: Query<(Entity, &NetControl, &mut Health, &mut Sanity, &mut Player, &mut PlayerInventory, Has<Dead>)>,
+) {
+    if cm.status.is_empty() {
+        return;
+    }
+    //Kept until our players exist, the first status can beat the world being built
+    if players.is_empty() {
+        return;
+    }
+    for status in cm.status.drain(..) {
+        for (entity, control, mut health, mut sanity, mut player, mut inventory, dead) in players.iter_mut() {
+            if control.player_id != status.player_id {
+                continue;
+            }
+            health.current = status.health as i32;
+            sanity.current = status.sanity_tenths as f32 / 10.;
+            player.charge = status.charge as i32;
+            inventory.has_flashlight = status.flashlight;
+            if status.dead && !dead {
+                commands.entity(entity).insert(Dead);
+            } else if !status.dead && dead {
+                commands.entity(entity).remove::<Dead>();
+            }
         }
+    }
+}
 
-        if input.pressed(KeyCode::KeyD) {
-            input_result += 16;
+//Doors and collectibles as the host has them. Kept until the world is built, which happens the frame
+//after Start.
+fn apply_world_state(
+    mut commands: Commands,
+    mut cm: ResMut<ClientMetrics>,
+    players: Query<(), With<Player>>,
+    mut doors: Query<(&mut Door, &NetId)>,
+    collectibles: Query<(Entity, &NetId), Or<(With<Collectible>, With<OldCollectible>)>>,
+) {
+    if players.is_empty() {
+        return;
+    }
+    for state in cm.doors.drain(..) {
+        if let Some((mut door, _)) = doors.iter_mut().find(|(_, id)| id.0 == state.door_id) {
+            //Only touched when it flips, door_visuals redraws on change
+            if door.open != state.open {
+                door.open = state.open;
+            }
+        }
+    }
+    for taken in cm.taken.drain(..) {
+        //Repeats and items we already removed just don't match anything
+        for (entity, id) in &collectibles {
+            if id.0 == taken {
+                commands.entity(entity).try_despawn();
+            }
         }
+    }
+}
 
-        //Ideally we would still send the click signal always, but this is easier if we aren't sending ammo info
-        if mouse_button_io.pressed(MouseButton::Left) && inventory.has_available_ammo() {
-            input_result += 2;
+//Our own lines and markers are shown straight away and queued for the host, which relays them to
+//everyone else. The copies the host sends back to us are skipped.
+fn exchange_chat(
+    mut cm: ResMut<ClientMetrics>,
+    mut sent: EventReader<ChatSent>,
+    mut markers_sent: EventReader<MarkerSent>,
+    mut lines: EventWriter<ChatReceived>,
+    mut markers: EventWriter<MarkerReceived>,
+) {
+    let Some(own_id) = cm.player_id else {
+        return;
+    };
+    for ChatSent { text } in sent.read() {
+        let id = cm.chat.next_id();
+        lines.write(ChatReceived { player_id: own_id, text: text.clone() });
+        cm.outgoing.push((CHAT_REPEATS, Message::Chat { player_id: own_id, id, text: text.clone() }));
+    }
+    for MarkerSent { position } in markers_sent.read() {
+        let id = cm.chat.next_id();
+        markers.write(MarkerReceived { player_id: own_id, position: *position });
+        let (x, y) = (position.x as i32, position.y as i32);
+        cm.outgoing.push((CHAT_REPEATS, Message::Marker { player_id: own_id, id, x, y }));
+    }
+    for (player_id, item) in std::mem::take(&mut cm.chat.incoming) {
+        match item {
+            _ if player_id == own_id => {}
+            ChatItem::Line(text) => {
+                lines.write(ChatReceived { player_id, text });
+            }
+            ChatItem::Marker(position) => {
+                markers.write(MarkerReceived { player_id, position });
+            }
         }
+    }
+}
 
-        cm.sw.reset();
+//Snapshots are centered on whoever we watch, so the host needs to know who that is
+fn send_spectate(spectating: Res<Spectating>, mut cm: ResMut<ClientMetrics>) {
+    let Some(target) = spectating.target.filter(|_| spectating.active) else {
+        return;
+    };
+    if cm.sent_spectate != Some(target) {
+        cm.sent_spectate = Some(target);
+        cm.outgoing.push((CHAT_REPEATS, Message::Spectate { player_id: target }));
+    }
+}
 
-        let seq = cm.seq_num;
-        cm.input_history[seq as usize] = input_result;
-        /*//Save Input
-        cm.input_history[seq as usize] = [input_result, player.net_angle, up_seq, low_seq];
-        if input.pressed(KeyCode::KeyO) {
-            info!("{:?}", cm.input_history);
-        }*/
-
-        //Fake packet loss option
-        if !input.pressed(KeyCode::KeyP) {
-
-        if !cm.send_history {
-            //Send Input
-            socket
-                .socket
-                .send_to(&[input_result, player.net_angle, cm.seq_num], serverip.serverIP.clone())
-                .expect("couldn't send data");
-
-            } else {
-            cm.send_history = false;
-            cm.input_history[256] = seq;
-            socket
-                .socket
-                .send_to(&cm.input_history, serverip.serverIP.clone())
-                .expect("couldn't send data");
-            }
-        } else {
-            //HISTORY DEBUG
-            //info!("Seq: {} -> {:?}", cm.seq_num, transform.translation);
-        }
+fn send_outgoing(socket: Res<SocketResource>, mut cm: ResMut<ClientMetrics>, serverip: Res<ServerIP>) {
+    for (repeats, msg) in cm.outgoing.iter_mut() {
+        socket.send(msg, &serverip.serverIP);
+        *repeats -= 1;
+    }
+    cm.outgoing.retain(|(repeats, _)| *repeats > 0);
+}
+
+//Tells the host when we ready up or back out, and repeats it with every heartbeat in case it was lost
+fn send_ready(
+    socket: Res<SocketResource>,
+    roster: Res<LobbyRoster>,
+    mut cm: ResMut<ClientMetrics>,
+    serverip: Res<ServerIP>,
+) {
+    if cm.player_id.is_none() {
+        return;
+    }
+    if cm.sent_ready == Some(roster.ready) && !cm.heartbeat.just_finished() {
+        return;
+    }
+    cm.sent_ready = Some(roster.ready);
+    socket.send(&Message::Ready { ready: roster.ready }, &serverip.serverIP);
+}
 
-        if cm.seq_num == 255 {
-            cm.seq_num = 0;
-        } else {
-            cm.seq_num += 1;
+//Predicts the local player one tick ahead of the host. First any correction from the host is applied,
+//then this tick's input is run through the same movement step the host uses and sent off.
+pub fn input_converter(
+    input: Res<ButtonInput<KeyCode>>,
+    mouse_button_io: Res<ButtonInput<MouseButton>>,
+    socket: ResMut<SocketResource>,
+    mut pl_cont: Query<
+        (&mut NetControl, &mut Transform, &mut Velocity, &KinematicCollider, &PlayerInventory),
+        (With<NetControl>, With<Local>, With<Player>),
+    >,
+    statics: Query<(&StaticCollider, &Transform), (Without<KinematicCollider>, Without<Door>)>,
+    map: Res<MapBounds>,
+    mut cm: ResMut<ClientMetrics>,
+    serverip: ResMut<ServerIP>,
+)   {
+        let Ok((mut player, mut transform, mut velocity, collider, inventory)) = pl_cont.single_mut() else {
+            return;
+        };
+        let shapes = static_shapes(&statics);
+        let step = |state: MoveState, bits: u8| movement_step(state, bits, TICK_SECS, &collider.shape, &shapes, &map);
+
+        //Rewind and replay only when the host disagrees with what we predicted
+        let mut state = MoveState { pos: transform.translation.truncate(), velocity: **velocity };
+        if let Some((ack, pos)) = cm.authoritative.take() {
+            if let Some(corrected) = cm.prediction.reconcile(ack, pos, step) {
+                state = corrected;
+            }
         }
 
-    //info!("WASD");
-    //info!("{:08b}", input_result);
+        //WASD00L0
+        let mut input_result = NetControl::keyboard_u8(&input);
 
-    /*for i in input.get_pressed() {
-        info!("(KEYBOARD) {:?} is pressed.", i);
-    }
+        //Ideally we would still send the click signal always, but this is easier if we aren't sending ammo info
+        if mouse_button_io.pressed(MouseButton::Left) && inventory.has_available_ammo() {
+            input_result += 2;
+        }
 
-    for i in mouse_button_io.get_pressed(){
-        info!("(MOUSE) {:?} is pressed.", i);
-    }*/
+        cm.sw.reset();
+
+        let seq = cm.prediction.next_seq();
+        state = step(state, input_result);
+        cm.prediction.record(seq, input_result, state);
+        **velocity = state.velocity;
+        transform.translation.x = state.pos.x;
+        transform.translation.y = state.pos.y;
+        player.set_pos_x(state.pos.x);
+        player.set_pos_y(state.pos.y);
+
+        //Send this input along with the ones before it, in case those were lost
+        socket.send(
+            &Message::Input {
+                angle: player.net_angle,
+                seq,
+                inputs: cm.prediction.recent_inputs(MAX_INPUTS_PER_PACKET),
+            },
+            &serverip.serverIP,
+        );
 }
 
 #[derive(Resource)]
 pub struct ClientMetrics {
-    pub seq_num: u8,
     pub sw: Stopwatch,
     pub rtt: Duration,
-    pub input_history: [u8;257],
 
-    pub send_history: bool,
+    //Our own inputs the host hasn't acknowledged, and the newest host position to check them against
+    pub prediction: Prediction,
+    pub authoritative: Option<(u16, Vec2)>,
+
+    //Assigned by the host in Accept/Start
+    pub player_id: Option<u8>,
+    //Time<Real>.elapsed_secs() of the last datagram from the host
+    pub last_heard: f32,
+    pub heartbeat: Timer,
+    pub ping: Timer,
+    //Last ready flag sent to the host
+    pub sent_ready: Option<bool>,
+
+    pub snapshots: SnapshotReceiver,
+
+    //Sent by the host when we rejoin, applied once the world is back
+    pub resume: Option<ResumeState>,
+    //Door states, sent by the host whenever one flips
+    pub doors: Vec<DoorState>,
+    //NetIds of collectibles the host says are gone
+    pub taken: Vec<u16>,
+
+    //Latest health, sanity, charge and death of every player from the host
+    pub status: Vec<PlayerStatus>,
+
+    //Chat lines and markers from the host, and ours still being repeated to it
+    pub chat: ChatState,
+    pub outgoing: Vec<(u8, Message)>,
+    //Last player we told the host we are watching
+    pub sent_spectate: Option<u8>,
+
+    //Datagrams dropped for a missing or bad seal, and for reusing a sequence number
+    pub forged: u32,
+    pub replayed: u32,
 }
 impl Default for ClientMetrics {
     fn default() -> Self {
         Self {
-           seq_num: 0,
            sw: Stopwatch::new(),
            rtt: Duration::ZERO,
-           input_history: [0;257],
 
-           send_history: false,
+           prediction: Prediction::default(),
+           authoritative: None,
+
+           player_id: None,
+           last_heard: 0.,
+           heartbeat: Timer::from_seconds(HEARTBEAT_INTERVAL, TimerMode::Repeating),
+           ping: Timer::from_seconds(PING_INTERVAL, TimerMode::Repeating),
+           sent_ready: None,
+
+           snapshots: SnapshotReceiver::default(),
+
+           resume: None,
+           doors: Vec::new(),
+           taken: Vec::new(),
+
+           status: Vec::new(),
+
+           chat: ChatState::default(),
+           outgoing: Vec::new(),
+           sent_spectate: None,
+
+           forged: 0,
+           replayed: 0,
         }
     }
 }
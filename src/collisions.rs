@@ -1,12 +1,12 @@
 use crate::{GameState, components::KinematicCollider, components::StaticCollider, player::Player, projectile::Projectile,
-            wall::Door,
+            wall::Door, sim::SimSet,
 };
 use bevy::{math::bounding::Aabb2d, math::bounding::IntersectsVolume, prelude::*};
 
 pub struct CollisionsPlugin;
 impl Plugin for CollisionsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, do_collisions.run_if(in_state(GameState::Playing)));
+        app.add_systems(FixedUpdate, do_collisions.in_set(SimSet::Collisions));
     }
 }
 
@@ -69,6 +69,11 @@ impl Collectible {
     }
 }
 
+//Same on every peer for things built from the level, so the host can say which door or item it means.
+//Doors and collectibles each have their own range.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetId(pub u16);
+
 // Colliders
 // Should make a general collision shape interface later
 #[derive(Component, Debug, Clone, Copy)]
@@ -0,0 +1,372 @@
+use crate::netsim::NetConditions;
+use crate::secure::{MAX_JOIN_CODE_LEN, normalize_join_code};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+pub const DEFAULT_PORT: u16 = 2525;
+//Clients without a fixed port pick one from here so several can run on one machine
+pub const CLIENT_PORT_RANGE: std::ops::Range<u16> = 25000..25999;
+//The player vectors in ServerMetrics, the flashlights and the shader's light array are sized for this many
+pub const MAX_PLAYERS_LIMIT: u8 = 8;
+pub const DEFAULT_MAX_PLAYERS: u8 = 4;
+
+const USAGE: &str = "\
+Usage: untitled-twinstick [OPTIONS] [ADDRESS]
+
+  ADDRESS                 Server address to join, also used as the bind address when hosting
+  --config <FILE>         Read settings from a RON file, flags given after it override the file
+  --bind <IP>             Address the host listens on (default 0.0.0.0)
+  --server <HOST>         Address the client joins (default 127.0.0.1)
+  --port <PORT>           Host port (default 2525)
+  --client-port <PORT>    Fixed client port instead of a random one
+  --name <NAME>           Player name shown to others
+  --max-players <N>       Players the host accepts, 1 to 8 (default 4)
+  --dedicated             Run a headless host with no window
+  --no-shadows            Turn off SDF shadows
+  --no-vsync              Turn off vsync
+  --interp-delay <MS>     How far behind the host remote entities are drawn (default 100)
+  --max-extrapolation <MS>
+                          How long remote entities keep moving without updates (default 250)
+  --sim-latency <MS>      Simulated one way latency, F8 toggles the simulator in game
+  --sim-jitter <MS>       Simulated latency variation
+  --sim-loss <PERCENT>    Simulated packet loss
+  --sim-duplicate <PERCENT>
+                          Simulated duplicate packets
+  --sim-reorder <PERCENT> Simulated out of order packets
+  --net-stats <FILE>      Append connection stats to a CSV file once a second, F3 shows them in game
+  --join-code <CODE>      Code the host asks for, or the one to join with (a host makes one up without it)
+  --encrypt               Host encrypts game traffic as well as authenticating it
+  --record <FILE>         Host writes every match to a replay file
+  --replay <FILE>         Play back a replay file instead of going to the menu
+  --level-seed <SEED>     Host a level generated from this number instead of the warehouse,
+                          clients are sent the seed when the match starts
+  --help                  Print this message";
+
+//Everything the game reads from the command line or a config file. Parsed once in main before the
+//App is built, then inserted as a resource for the server, client and graphics plugins.
+#[derive(Resource, Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub server_address: String,
+    pub port: u16,
+    pub client_port: Option<u16>,
+    pub player_name: String,
+    pub max_players: u8,
+    pub dedicated: bool,
+    pub shadows: bool,
+    pub vsync: bool,
+    //Milliseconds, see interpolation.rs
+    pub interpolation_delay: u32,
+    pub max_extrapolation: u32,
+    //Simulated bad network, see netsim.rs
+    pub net_sim: NetConditions,
+    //CSV file for the connection stats, see netstats.rs
+    pub net_stats_csv: Option<PathBuf>,
+    //See secure.rs
+    pub join_code: Option<String>,
+    pub encrypt: bool,
+    //See replay.rs
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    //See levelgen.rs
+    pub level_seed: Option<u64>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::from([0, 0, 0, 0]),
+            server_address: "127.0.0.1".to_string(),
+            port: DEFAULT_PORT,
+            client_port: None,
+            player_name: "Player".to_string(),
+            max_players: DEFAULT_MAX_PLAYERS,
+            dedicated: false,
+            shadows: true,
+            vsync: true,
+            interpolation_delay: 100,
+            max_extrapolation: 250,
+            net_sim: NetConditions::default(),
+            net_stats_csv: None,
+            join_code: None,
+            encrypt: false,
+            record: None,
+            replay: None,
+            level_seed: None,
+        }
+    }
+}
+
+//What main should do after parsing
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Config),
+    Help,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingValue(String),
+    BadValue { flag: String, value: String },
+    UnknownFlag(String),
+    Io(PathBuf, std::io::Error),
+    Ron(PathBuf, ron::error::SpannedError),
+    Invalid(String),
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::BadValue { flag, value } => write!(f, "'{}' is not a valid value for {}", value, flag),
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown option {}", flag),
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Ron(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    //Reads the process arguments, printing usage and exiting on --help or bad input
+    pub fn from_env() -> Self {
+        match Config::parse(std::env::args().skip(1)) {
+            Ok(Command::Run(config)) => config,
+            Ok(Command::Help) => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    //Arguments without the program name. Flags apply in order, so a --config file only sets
+    //whatever is not overridden by a later flag.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, ConfigError> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| ConfigError::MissingValue(flag.to_string()));
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(Command::Help),
+                "--config" => {
+                    let path = PathBuf::from(value("--config")?);
+                    config = Config::from_file(&path)?;
+                }
+                "--bind" => config.bind_address = parse_value("--bind", value("--bind")?)?,
+                "--server" => config.server_address = value("--server")?,
+                "--port" => config.port = parse_value("--port", value("--port")?)?,
+                "--client-port" => config.client_port = Some(parse_value("--client-port", value("--client-port")?)?),
+                "--name" => config.player_name = value("--name")?,
+                "--max-players" => config.max_players = parse_value("--max-players", value("--max-players")?)?,
+                "--dedicated" => config.dedicated = true,
+                "--no-shadows" => config.shadows = false,
+                "--no-vsync" => config.vsync = false,
+                "--interp-delay" => config.interpolation_delay = parse_value("--interp-delay", value("--interp-delay")?)?,
+                "--max-extrapolation" => {
+                    config.max_extrapolation = parse_value("--max-extrapolation", value("--max-extrapolation")?)?
+                }
+                "--sim-latency" => config.net_sim.latency_ms = parse_value("--sim-latency", value("--sim-latency")?)?,
+                "--sim-jitter" => config.net_sim.jitter_ms = parse_value("--sim-jitter", value("--sim-jitter")?)?,
+                "--sim-loss" => config.net_sim.loss = parse_value("--sim-loss", value("--sim-loss")?)?,
+                "--sim-duplicate" => config.net_sim.duplicate = parse_value("--sim-duplicate", value("--sim-duplicate")?)?,
+                "--sim-reorder" => config.net_sim.reorder = parse_value("--sim-reorder", value("--sim-reorder")?)?,
+                "--net-stats" => config.net_stats_csv = Some(PathBuf::from(value("--net-stats")?)),
+                "--join-code" => config.join_code = Some(value("--join-code")?),
+                "--encrypt" => config.encrypt = true,
+                "--record" => config.record = Some(PathBuf::from(value("--record")?)),
+                "--replay" => config.replay = Some(PathBuf::from(value("--replay")?)),
+                "--level-seed" => config.level_seed = Some(parse_value("--level-seed", value("--level-seed")?)?),
+                flag if flag.starts_with('-') => return Err(ConfigError::UnknownFlag(flag.to_string())),
+                //Old style: a bare address means "join this host" and "host on this interface"
+                address => {
+                    config.server_address = address.to_string();
+                    if let Ok(ip) = address.parse() {
+                        config.bind_address = ip;
+                    }
+                }
+            }
+        }
+        config.validate()?;
+        Ok(Command::Run(config))
+    }
+
+    pub fn from_file(path: &PathBuf) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        ron::from_str(&text).map_err(|e| ConfigError::Ron(path.clone(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_players == 0 || self.max_players > MAX_PLAYERS_LIMIT {
+            return Err(ConfigError::Invalid(format!(
+                "max players must be between 1 and {}, got {}",
+                MAX_PLAYERS_LIMIT, self.max_players
+            )));
+        }
+        if self.port == 0 {
+            return Err(ConfigError::Invalid("port 0 is not allowed".to_string()));
+        }
+        if self.server_address.is_empty() {
+            return Err(ConfigError::Invalid("server address is empty".to_string()));
+        }
+        if self.interpolation_delay > 1000 || self.max_extrapolation > 1000 {
+            return Err(ConfigError::Invalid(
+                "interpolation delay and max extrapolation are limited to 1000 ms".to_string(),
+            ));
+        }
+        let sim = &self.net_sim;
+        if [sim.loss, sim.duplicate, sim.reorder].iter().any(|p| !(0.0..=100.0).contains(p)) {
+            return Err(ConfigError::Invalid("simulated network percentages must be between 0 and 100".to_string()));
+        }
+        if self.player_name.trim().is_empty() {
+            return Err(ConfigError::Invalid("player name is empty".to_string()));
+        }
+        if let Some(code) = &self.join_code {
+            let len = normalize_join_code(code).len();
+            if len == 0 || len > MAX_JOIN_CODE_LEN {
+                return Err(ConfigError::Invalid(format!(
+                    "join code must have 1 to {} letters or digits",
+                    MAX_JOIN_CODE_LEN
+                )));
+            }
+        }
+        if self.replay.is_some() && (self.dedicated || self.record.is_some()) {
+            return Err(ConfigError::Invalid("a replay plays in a window and can't be recorded again".to_string()));
+        }
+        Ok(())
+    }
+
+    //host:port string for the client to send to, an IPv6 host goes in brackets
+    pub fn server_endpoint(&self) -> String {
+        if self.server_address.contains(':') {
+            format!("[{}]:{}", self.server_address, self.port)
+        } else {
+            format!("{}:{}", self.server_address, self.port)
+        }
+    }
+
+    pub fn client_port(&self) -> u16 {
+        self.client_port
+            .unwrap_or_else(|| rand::random_range(CLIENT_PORT_RANGE))
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::BadValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+    fn parse(args: &[&str]) -> Result<Command, ConfigError> {
+        Config::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    fn run(args: &[&str]) -> Config {
+        match parse(args) {
+            Ok(Command::Run(config)) => config,
+            other => panic!("expected a config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_args_is_default() {
+        assert_eq!(run(&[]), Config::default());
+    }
+
+    #[test]
+    fn flags_are_read() {
+        let config = run(&[
+            "--bind", "127.0.0.1", "--server", "example.com", "--port", "3000", "--client-port", "3001",
+            "--name", "Gordon", "--max-players", "2", "--dedicated", "--no-shadows", "--no-vsync",
+            "--interp-delay", "50", "--max-extrapolation", "0", "--sim-latency", "80", "--sim-loss", "2.5",
+            "--net-stats", "net.csv", "--join-code", "abc-234", "--encrypt", "--record", "match.replay",
+            "--level-seed", "1234",
+        ]);
+        assert_eq!(config.bind_address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(config.server_endpoint(), "example.com:3000");
+        assert_eq!(config.client_port, Some(3001));
+        assert_eq!(config.player_name, "Gordon");
+        assert_eq!(config.max_players, 2);
+        assert!(config.dedicated);
+        assert!(!config.shadows);
+        assert!(!config.vsync);
+        assert_eq!(config.interpolation_delay, 50);
+        assert_eq!(config.max_extrapolation, 0);
+        assert_eq!(config.net_sim, NetConditions { latency_ms: 80, loss: 2.5, ..default() });
+        assert_eq!(config.net_stats_csv, Some(PathBuf::from("net.csv")));
+        assert_eq!(config.join_code.as_deref(), Some("abc-234"));
+        assert!(config.encrypt);
+        assert_eq!(config.record, Some(PathBuf::from("match.replay")));
+        assert_eq!(config.level_seed, Some(1234));
+        assert_eq!(run(&["--replay", "match.replay"]).replay, Some(PathBuf::from("match.replay")));
+    }
+
+    #[test]
+    fn bare_address_sets_both_ends() {
+        let config = run(&["192.168.1.5"]);
+        assert_eq!(config.server_address, "192.168.1.5");
+        assert_eq!(config.bind_address, IpAddr::from([192, 168, 1, 5]));
+    }
+
+    #[test]
+    fn ipv6_endpoint_resolves() {
+        let config = run(&["::1", "--port", "4000"]);
+        assert_eq!(config.server_endpoint(), "[::1]:4000");
+        let addr = config.server_endpoint().to_socket_addrs().unwrap().next().unwrap();
+        assert_eq!(addr, SocketAddr::from((Ipv6Addr::LOCALHOST, 4000)));
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse(&["--help"]).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert!(matches!(parse(&["--port"]), Err(ConfigError::MissingValue(_))));
+        assert!(matches!(parse(&["--port", "http"]), Err(ConfigError::BadValue { .. })));
+        assert!(matches!(parse(&["--bind", "nowhere"]), Err(ConfigError::BadValue { .. })));
+        assert!(matches!(parse(&["--max-players", "9"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--max-players", "0"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--interp-delay", "5000"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--sim-loss", "101"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--join-code", "--"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--replay", "a", "--dedicated"]), Err(ConfigError::Invalid(_))));
+        assert!(matches!(parse(&["--level-seed", "-1"]), Err(ConfigError::BadValue { .. })));
+        assert!(matches!(parse(&["--fast"]), Err(ConfigError::UnknownFlag(_))));
+    }
+
+    #[test]
+    fn file_then_flags() {
+        let path = std::env::temp_dir().join(format!("config_test_{}.ron", std::process::id()));
+        std::fs::write(&path, "(port: 4000, player_name: \"Amyia\", shadows: false, net_sim: (jitter_ms: 20))").unwrap();
+        let config = run(&["--config", path.to_str().unwrap(), "--port", "4001"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.port, 4001);
+        assert_eq!(config.player_name, "Amyia");
+        assert!(!config.shadows);
+        assert!(config.vsync);
+        assert_eq!(config.net_sim.jitter_ms, 20);
+    }
+
+    #[test]
+    fn unknown_file_field_is_an_error() {
+        let path = std::env::temp_dir().join(format!("config_bad_{}.ron", std::process::id()));
+        std::fs::write(&path, "(prot: 4000)").unwrap();
+        let result = parse(&["--config", path.to_str().unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(ConfigError::Ron(..))));
+    }
+}
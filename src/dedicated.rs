@@ -0,0 +1,131 @@
+use crate::{
+    AssignedType, GameState, LogicType,
+    collectible, collisions, enemy, level, light_manager, netsim, netstats, pickup_system, player, projectile, replay,
+    sanity, sdf_shadows, server, sim, tiling, wall, winloss,
+    events::DamagePlayerEvent,
+    chat::{ChatReceived, ChatSent, MarkerReceived, MarkerSent},
+    level::{clear_world, level_ready},
+    lobby::PlayerInfo,
+    player_material::PlayerBaseMaterial,
+    server::ServerMetrics,
+};
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::image::TextureAtlasPlugin;
+use bevy::input::InputPlugin;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::state::app::StatesPlugin;
+use std::time::Duration;
+
+//How long everyone has to stay ready before the match starts, so a late joiner can still get in
+const START_DELAY: f32 = 3.0;
+
+//Marker resource, present only when running without a window
+#[derive(Resource)]
+pub struct Dedicated;
+
+#[derive(Resource)]
+struct LobbyCountdown {
+    timer: Timer,
+}
+impl Default for LobbyCountdown {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(START_DELAY, TimerMode::Once),
+        }
+    }
+}
+
+//Runs the host simulation with MinimalPlugins. Rendering plugins are left out, but the asset
+//types the gameplay setup systems write into are still registered so they don't need a GPU.
+pub struct DedicatedPlugin;
+impl Plugin for DedicatedPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            //Nothing is drawn, so there is no vsync to pace the loop. One frame per fixed tick keeps
+            //each frame to a single FixedUpdate run
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1. / sim::TICK_RATE))),
+            LogPlugin::default(),
+            StatesPlugin,
+            TransformPlugin,
+            InputPlugin,
+            AssetPlugin::default(),
+            ImagePlugin::default(),
+            MeshPlugin,
+            TextureAtlasPlugin,
+        ))
+        .init_asset::<PlayerBaseMaterial>()
+        //The host goes straight to the lobby, there is no menu to click through
+        .insert_state(GameState::Lobby)
+        .insert_resource(LogicType {
+            l_type: AssignedType::Host,
+        })
+        .insert_resource(Dedicated)
+        .init_resource::<LobbyCountdown>()
+        .add_event::<DamagePlayerEvent>()
+        //Nobody types on a dedicated host, but it still relays everyone else's chat
+        .add_event::<ChatSent>()
+        .add_event::<MarkerSent>()
+        .add_event::<ChatReceived>()
+        .add_event::<MarkerReceived>()
+        .add_plugins((
+            level::LevelPlugin,
+            player::PlayerPlugin,
+            light_manager::LightSourcePlugin,
+            sdf_shadows::SdfShadowsPlugin,
+            tiling::TilingPlugin,
+            projectile::ProjectilePlugin,
+            enemy::EnemyPlugin,
+            collectible::CollectiblePlugin,
+        ))
+        .add_plugins((
+            pickup_system::PickupPlugin,
+            wall::WallPlugin,
+            collisions::CollisionsPlugin,
+            server::ServerPlugin,
+            netsim::NetSimPlugin,
+            netstats::NetStatsPlugin,
+            sanity::SanityPlugin,
+            winloss::WinLossPlugin,
+            sim::SimPlugin,
+            replay::ReplayPlugin,
+        ))
+        .add_systems(
+            Update,
+            start_when_ready
+                .run_if(in_state(GameState::Lobby))
+                .run_if(resource_exists::<ServerMetrics>)
+                .run_if(level_ready),
+        )
+        .add_systems(OnEnter(GameState::GameOver), (clear_world, reset_match))
+        .add_systems(OnEnter(GameState::Credits), (clear_world, reset_match));
+    }
+}
+
+//Nobody is at the host to press start, so the match begins once every client has readied up and
+//stayed ready for the countdown. Anyone joining or backing out restarts it.
+fn start_when_ready(
+    time: Res<Time>,
+    sm: Res<ServerMetrics>,
+    players: Query<&PlayerInfo>,
+    mut countdown: ResMut<LobbyCountdown>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if sm.player_count == 0 || players.iter().any(|info| !info.ready) {
+        countdown.timer.reset();
+        return;
+    }
+    countdown.timer.tick(time.delta());
+    if countdown.timer.finished() {
+        info!("Starting match with {} players", sm.player_count);
+        countdown.timer.reset();
+        next_state.set(GameState::Playing);
+    }
+}
+
+//Nobody is around to click through the game over screen, so reopen the lobby once the world is cleared
+fn reset_match(mut next_state: ResMut<NextState<GameState>>) {
+    info!("Match over, reopening lobby");
+    next_state.set(GameState::Lobby);
+}
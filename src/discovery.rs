@@ -0,0 +1,181 @@
+use crate::protocol::{MAX_DATAGRAM, Message, ServerInfo};
+use bevy::prelude::*;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   LAN discovery. The server browser broadcasts a DiscoveryQuery to the game port and every host    ///
+///   on the network answers on its game socket with a DiscoveryReply. The query is also sent to       ///
+///   loopback so a host on the same machine shows up even where broadcast isn't allowed.              ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Hosts that stop answering drop off the list after this long
+pub const SERVER_EXPIRY: f64 = 3.0;
+
+//Socket the server browser searches with
+pub struct DiscoveryClient {
+    socket: UdpSocket,
+}
+
+impl DiscoveryClient {
+    pub fn bind() -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    //Asks every host listening on port to answer
+    pub fn query(&self, port: u16) {
+        let query = Message::DiscoveryQuery.to_bytes();
+        for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+            //Broadcast fails on machines without a network, loopback still works there
+            if let Err(e) = self.socket.send_to(&query, SocketAddrV4::new(ip, port)) {
+                debug!("Discovery query to {} failed: {}", ip, e);
+            }
+        }
+    }
+
+    //Every reply that has arrived since the last call
+    pub fn receive(&self) -> Vec<(SocketAddr, ServerInfo)> {
+        let mut buf = [0; MAX_DATAGRAM];
+        let mut replies = Vec::new();
+        while let Ok((amt, src)) = self.socket.recv_from(&mut buf) {
+            match Message::from_bytes(&buf[..amt]) {
+                Ok(Message::DiscoveryReply(info)) => replies.push((src, info)),
+                Ok(_) => {}
+                Err(e) => debug!("Dropped discovery reply from {:?}: {}", src, e),
+            }
+        }
+        replies
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundServer {
+    pub addr: SocketAddr,
+    pub info: ServerInfo,
+    //Seconds, same clock as the now passed to ServerList
+    pub last_seen: f64,
+}
+
+//Hosts the browser has heard from recently, oldest find first so the list doesn't jump around
+#[derive(Resource, Default)]
+pub struct ServerList {
+    pub servers: Vec<FoundServer>,
+}
+
+impl ServerList {
+    //Returns true when something the browser shows changed
+    pub fn update(&mut self, addr: SocketAddr, info: ServerInfo, now: f64) -> bool {
+        if let Some(found) = self.servers.iter_mut().find(|s| s.addr == addr) {
+            found.last_seen = now;
+            let changed = found.info != info;
+            found.info = info;
+            return changed;
+        }
+        self.servers.push(FoundServer { addr, info, last_seen: now });
+        true
+    }
+
+    //Drops hosts that have gone quiet, returns true if any did
+    pub fn expire(&mut self, now: f64) -> bool {
+        let before = self.servers.len();
+        self.servers.retain(|s| now - s.last_seen <= SERVER_EXPIRY);
+        self.servers.len() != before
+    }
+}
+
+//Reads "host", "host:port" or "[v6]:port" from the address field
+pub fn parse_address(text: &str, default_port: u16) -> Option<(String, u16)> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Some(rest) = text.strip_prefix('[') {
+        let (host, port) = rest.split_once(']')?;
+        let port = match port {
+            "" => default_port,
+            port => port.strip_prefix(':')?.parse().ok()?,
+        };
+        return (!host.is_empty()).then(|| (host.to_string(), port));
+    }
+    match text.split_once(':') {
+        //More than one colon is a bare IPv6 address
+        Some((host, port)) if !port.contains(':') => {
+            let port = port.parse().ok().filter(|p| *p != 0)?;
+            (!host.is_empty()).then(|| (host.to_string(), port))
+        }
+        _ => Some((text.to_string(), default_port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn info(name: &str, player_count: u8) -> ServerInfo {
+        ServerInfo {
+            name: name.to_string(),
+            player_count,
+            max_players: 4,
+            in_progress: false,
+            map: "warehouse".to_string(),
+        }
+    }
+
+    #[test]
+    fn addresses_parse_with_and_without_ports() {
+        assert_eq!(parse_address(" 10.0.0.2 ", 2525), Some(("10.0.0.2".to_string(), 2525)));
+        assert_eq!(parse_address("example.com:4000", 2525), Some(("example.com".to_string(), 4000)));
+        assert_eq!(parse_address("[::1]:4000", 2525), Some(("::1".to_string(), 4000)));
+        assert_eq!(parse_address("::1", 2525), Some(("::1".to_string(), 2525)));
+        assert_eq!(parse_address("", 2525), None);
+        assert_eq!(parse_address("host:port", 2525), None);
+        assert_eq!(parse_address("host:0", 2525), None);
+        assert_eq!(parse_address(":4000", 2525), None);
+    }
+
+    #[test]
+    fn list_updates_in_place_and_expires() {
+        let a: SocketAddr = "10.0.0.2:2525".parse().unwrap();
+        let b: SocketAddr = "10.0.0.3:2525".parse().unwrap();
+        let mut list = ServerList::default();
+        assert!(list.update(a, info("A", 1), 0.0));
+        assert!(list.update(b, info("B", 1), 1.0));
+        //Same answer again only refreshes the timestamp
+        assert!(!list.update(a, info("A", 1), 2.0));
+        assert!(list.update(a, info("A", 2), 2.5));
+        assert_eq!(list.servers[0].info.player_count, 2);
+
+        assert!(!list.expire(4.0));
+        assert!(list.expire(4.5));
+        assert_eq!(list.servers.len(), 1);
+        assert_eq!(list.servers[0].addr, a);
+    }
+
+    //Plays the host's side of discovery over loopback
+    #[test]
+    fn finds_a_host_on_loopback() {
+        let host = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let client = DiscoveryClient::bind().unwrap();
+        client.query(host.local_addr().unwrap().port());
+
+        let mut buf = [0; MAX_DATAGRAM];
+        let (amt, src) = host.recv_from(&mut buf).unwrap();
+        assert_eq!(Message::from_bytes(&buf[..amt]), Ok(Message::DiscoveryQuery));
+        host.send_to(&Message::DiscoveryReply(info("Gordon", 3)).to_bytes(), src).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let replies = loop {
+            let replies = client.receive();
+            if !replies.is_empty() || Instant::now() > deadline {
+                break replies;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(replies, vec![(host.local_addr().unwrap(), info("Gordon", 3))]);
+    }
+}
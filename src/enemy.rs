@@ -1,8 +1,11 @@
 use crate::{
     GameState, components::Health, components::KinematicCollider, events::DamagePlayerEvent,
+    events::EnemyDamagedEvent, events::EnemyKilledEvent, events::EnemySpawnedEvent,
     light_manager::Lights, player::Player, player_material::PlayerBaseMaterial,
-    projectile::Projectile, server::type_equals_host,
+    projectile::Projectile, server::type_equals_host, level::CurrentLevel, level::Level,
+    interpolation::InterpolationBuffer, sim::{SimSet, TICK_SECS},
 };
+use bevy::ecs::system::SystemParam;
 use bevy::math::bounding::Aabb2d;
 use bevy::{prelude::*, render::render_resource::DownlevelFlags};
 use std::f32::consts;
@@ -24,38 +27,48 @@ const ACCEL_RATE: f32 = 10000.;
 pub struct EnemyPlugin;
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Playing), setup_enemy)
+        app.add_event::<EnemySpawnedEvent>()
+            .add_event::<EnemyDamagedEvent>()
+            .add_event::<EnemyKilledEvent>()
+            .add_systems(OnEnter(GameState::Playing), setup_enemy.run_if(type_equals_host))
             .add_systems(
-                Update,
-                enemy_chase_velocity.run_if(in_state(GameState::Playing)),
+                FixedUpdate,
+                (
+                    enemy_chase_velocity,
+                    (enemy_cram_velocity, enemy_velocity_apply).run_if(type_equals_host),
+                )
+                    .chain()
+                    .in_set(SimSet::Enemies),
             )
+            //Hits are only decided on the host, clients apply what the host replicates
+            .add_systems(FixedUpdate, enemy_damage.in_set(SimSet::Combat).run_if(type_equals_host))
+            //Spawns can arrive while the client is still switching from Joining to Playing
             .add_systems(
                 Update,
-                enemy_cram_velocity.run_if(in_state(GameState::Playing)).run_if(type_equals_host),
+                (spawn_replicated_enemies, apply_replicated_damage, apply_replicated_deaths)
+                    .chain()
+                    .run_if(in_state(GameState::Joining).or(in_state(GameState::Playing)))
+                    .run_if(not(type_equals_host)),
             )
-            .add_systems(
-                Update,
-                enemy_velocity_apply.run_if(in_state(GameState::Playing)).run_if(type_equals_host),
-            )
-            .add_systems(Update, enemy_damage.run_if(in_state(GameState::Playing)))
             .add_systems(
                 Update,
                 all_enemies_defeated.run_if(in_state(GameState::Playing)),
             )
-            .add_systems(Update, enemy_attack.run_if(in_state(GameState::Playing)));
+            .add_systems(FixedUpdate, enemy_attack.in_set(SimSet::Combat));
     }
 }
 
 #[derive(Component)]
 pub struct Enemy {
-    pub enemy_id: u8,
+    //Network id, shared with the snapshot and enemy event messages
+    pub enemy_id: u16,
     enemy_type: EnemyType,
     enemy_speed: f32,
     pub awake: bool,
 }
 
 impl Enemy {
-    fn new(id: u8, enemy_type: EnemyType) -> Enemy {
+    fn new(id: u16, enemy_type: EnemyType) -> Enemy {
         let enemy_speed = match enemy_type {
             EnemyType::Normal => NORMAL_SPEED,
             EnemyType::Strong => STRONG_SPEED,
@@ -68,14 +81,73 @@ impl Enemy {
             awake: false,
         }
     }
+
+    pub fn enemy_type(&self) -> EnemyType {
+        self.enemy_type
+    }
 }
 
-enum EnemyType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnemyType {
     Normal,
     Strong,
     Fast,
 }
 
+impl EnemyType {
+    //Codes used in the level's enemy layer
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            3 => Some(EnemyType::Normal),
+            4 => Some(EnemyType::Fast),
+            5 => Some(EnemyType::Strong),
+            _ => None,
+        }
+    }
+
+    //Wire value for protocol::EnemySpawn
+    pub fn to_byte(self) -> u8 {
+        match self {
+            EnemyType::Normal => 0,
+            EnemyType::Strong => 1,
+            EnemyType::Fast => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(EnemyType::Normal),
+            1 => Some(EnemyType::Strong),
+            2 => Some(EnemyType::Fast),
+            _ => None,
+        }
+    }
+
+    pub fn max_health(self) -> i32 {
+        match self {
+            EnemyType::Normal => NORMAL_HEALTH,
+            EnemyType::Strong => STRONG_HEALTH,
+            EnemyType::Fast => FAST_HEALTH,
+        }
+    }
+
+    fn size(self) -> f32 {
+        match self {
+            EnemyType::Normal => 64.,
+            EnemyType::Strong => 80.,
+            EnemyType::Fast => 48.,
+        }
+    }
+
+    fn texture(self) -> &'static str {
+        match self {
+            EnemyType::Normal => "enemy/enemy_standard_albedo.png",
+            EnemyType::Strong => "enemy/enemy_fast_albedo.png",
+            EnemyType::Fast => "enemy/enemy_strong_albedo.png",
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Awake {
 }
@@ -94,223 +166,94 @@ impl Velocity {
     }
 }
 
+//Everything needed to build an enemy's phong-lit sprite, shared by the host's level setup and the
+//client's replicated spawns
+#[derive(SystemParam)]
+pub struct EnemySpawner<'w> {
+    asset_server: Res<'w, AssetServer>,
+    materials: ResMut<'w, Assets<PlayerBaseMaterial>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    lights: Res<'w, Lights>,
+    sdf_texture: Res<'w, crate::sdf_shadows::SdfTexture>,
+}
+
+impl EnemySpawner<'_> {
+    pub fn spawn(
+        &mut self,
+        commands: &mut Commands,
+        id: u16,
+        enemy_type: EnemyType,
+        pos: Vec2,
+        health: i32,
+    ) -> Entity {
+        let size = enemy_type.size();
+        let mut enemy_health = Health::new(enemy_type.max_health());
+        enemy_health.current = health;
+        commands
+            .spawn((
+                // See player.rs for more info about the phong-lit material.
+                Mesh2d(self.meshes.add(Rectangle::default())),
+                MeshMaterial2d(self.materials.add(PlayerBaseMaterial {
+                    color: LinearRgba::BLUE,
+                    texture: Some(self.asset_server.load(enemy_type.texture())),
+                    lighting: crate::player_material::Lighting {
+                        ambient_reflection_coefficient: 0.0,
+                        ambient_light_intensity: 0.0,
+                        diffuse_reflection_coefficient: 1.0,
+                        shininess: 40.0,
+                    },
+                    lights: self.lights.lights,
+                    normal: Some(self.asset_server.load("enemy/enemy_standard_normal.png")),
+                    mesh_rotation: 0.0,
+                    sdf_texture: Some(self.sdf_texture.texture.clone()),
+                })),
+                Transform::from_xyz(pos.x, pos.y, 5.).with_scale(Vec3::splat(size)),
+                Velocity::new(),
+                Enemy::new(id, enemy_type),
+                KinematicCollider {
+                    shape: Aabb2d {
+                        min: Vec2 { x: 0., y: 0. },
+                        max: Vec2 { x: size, y: size },
+                    },
+                },
+                enemy_health,
+            ))
+            .id()
+    }
+}
+
+//Host only, clients get their enemies from EnemySpawnedEvents sent over the network
 pub fn setup_enemy(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<PlayerBaseMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    lights: Res<Lights>,
-    sdf_texture: Res<crate::sdf_shadows::SdfTexture>,
+    mut spawner: EnemySpawner,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    mut spawned: EventWriter<EnemySpawnedEvent>,
 ) {
-    let enemy_map = [
-[2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,2,2,2,2,2,2,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,4,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,3,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,0,0,0,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,3,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,3,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,1,0,0,0,1,2,2,2,2,2,2,2,2,2,2,2,2,2,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,3,0,1,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,3,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,3,0,0,0,0,3,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,0,0,0,1,1,1,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,0,0,1,0,0,0,1,0,0,5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,1,1,0,0,0,0,0,0,0,1,1,1,0,0,0,0,0,0,2],
-[1,3,0,1,0,0,1,0,3,1,3,0,1,0,0,0,1,0,3,0,0,0,0,0,0,0,1,0,0,0,1,0,3,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[1,0,3,1,0,0,1,3,0,1,0,3,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1,1,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,3,0,0,0,0,0,1,1,1,0,0,0,0,0,0,0,1,1,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,4,0,0,4,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,3,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,3,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,1,3,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,4,0,0,4,0,0,0,0,0,0,0,0,0,1,0,0,3,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,1,1,1,1,1,1,1,1,0,0,5,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,3,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,0,0,0,3,0,0,0,0,0,3,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,2,2,2,2,2,2,2,2,2,2,2,2,2,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,2],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,3,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,2,2,2,2,2,2,2],
-[1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,5,0,0,0,0,0,1,0,0,0,0,0,0,0],
-[2,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,3,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0],
-[2,3,3,0,0,2,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0],
-[2,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0],
-[2,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,3,0,0,0,0,0,0,0,0,1,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0],
-[1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,2,0,0,0,0,0,0,0],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,3,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,2,0,0,0,0,0,0,0],
-[1,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,3,0,0,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0],
-[1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,1,0,0,0,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],
-[1,0,3,0,0,0,0,1,0,0,0,1,0,0,1,1,0,0,1,0,0,0,1,0,0,1,0,3,0,0,1,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,1,0,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,1,1,1,0,0,0,1,0,0,0,0,0,0,1,0,0,0,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,0,0,1,0,0,0,1,0,0,0,5,0,0,1,0,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,0,1,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,1,0,0,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,3,0,1,0,0,0,1,1,0,0,0,0,1,1,0,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1],
-[1,0,0,3,0,0,0,1,0,0,0,0,1,1,1,1,1,1,0,0,0,0,1,0,0,0,0,0,0,0,1,0,3,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,1,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,5,0,1],
-[1,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,1,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,1,1,1,0,0,0,0,0,0,0,0,1,0,3,0,0,3,0,3,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,4,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,0,0,1,1,0,0,1,1,0,0,1,1,4,0,1,1,4,0,1,1,1,1,1,1,1,1,1,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,1,0,0,1,1,0,0,1,1,4,0,1,1,4,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,1,1,0,0,0,2,0,0,0,1],
-[1,0,0,3,0,0,1,0,0,0,3,0,0,1,0,0,0,3,0,0,1,0,0,0,3,0,0,1,0,0,0,0,0,4,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,2,0,5,0,1],
-[1,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,0,1,1,1,0,0,0,0,0,0,3,0,0,0,0,1,0,0,1,0,0,1,1,0,0,1,1,0,0,1,1,4,0,1,1,4,0,1,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,0,0,1,1,0,0,1,1,0,0,1,1,4,0,1,1,4,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[2,0,0,0,0,0,4,0,0,0,0,0,0,0,4,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,2,0,0,0,1],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,1,1,1,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,4,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,2,0,0,0,1],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,2,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,1],
-[2,0,4,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,4,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,4,0,0,0,0,0,0,0,0,0,0,0,0,4,0,2,0,0,0,1],
-[2,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,4,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,1],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,2,0,0,0,0,0,0,0,0,0,0,5,0,0,0,5,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1,1,1,1,0,0,0,1,0,0,1,1,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,0,0,0,1],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,2,0,0,3,0,0,0,0,1,0,0,0,0,0,0,0,0,1,1,1,1,0,0,1,1,1,1,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1,1,1,1,0,3,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,5,0,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1,1,1,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,0,3,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,2,0,0,0,0,3,0,0,1,1,1,1,1,1,1,1,1,1,0,0,0,3,0,0,0,0,2,0,0,4,0,0,0,0,4,0,1,0,0,0,3,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1],
-[2,0,4,0,1,0,4,0,0,0,0,0,0,0,0,1,0,0,0,2,2,2,2,0,0,0,0,1,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,1,1,1,1,1,0,0,1,1,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],
-[2,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,0,0,0,0,0,0,0,0,0,3,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,2],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,5,0,0,0,0,0,0,0,0,0,1,0,3,0,1,0,3,0,1,0,0,0,2],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,1,1,1,1,1,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,5,0,0,0,0,0,0,0,0,0,0,0,0,4,4,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,4,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,3,0,1,0,3,0,1,0,0,0,2],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,0,3,0,0,1,1,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,5,0,1,1,1,1,1,1,0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,2],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,0,0,0,2],
-[2,0,4,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,3,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,3,0,0,1,1,1,1,1,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,1,1,1,1,0,0,0,2],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,2,2,2,2,2,2,2,2,2,2,2,1,0,0,0,1,0,0,0,0,1,1,1,0,0,0,1,1,1,0,0,1,1,1,0,0,1,0,0,5,0,0,0,0,0,0,0,5,0,0,1,0,0,1,1,1,1,1,1,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,1,1,1,1,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,1,1,1,1,0,0,0,2],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,0,0,1,0,0,0,0,1,0,0,4,0,0,0,0,1,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,1,1,1,1,1,1,0,0,0,2],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,3,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,1,1,1,0,0,0,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,4,0,1,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,0,1,0,0,0,1,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,2,2,2,2,2,2,2,2,2,2,2,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,0,1,1,1,1,1,0,0,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,3,0,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,0,0,0,0,0,3,0,1,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,1,0,0,5,0,0,0,0,2,0,0,0,0,0,0,0,1,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,2,2,2,2,2,2,0,0,1,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,0,0,1,0,0,0,0,0,5,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,5,0,0,0,5,0,0,0,0,0,0,0,5,0,0,0,0,0,0,5,0,0,0,0,1,0,0,1,1,1,1,0,0,1,1,1,1,0,0,0,0,1,1,0,1,1,1,1,0,0,1,0,0,0,1,0,0,1,1,0,0,1,1,1,1,1,1,1,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,0,0,1,1,1,1],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,0,1,0,0,0,4,0,0,0,0,1,0,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,1,0,0,1,0,0,0,0,5,0,0,0,0,5,0,0,0,0,0,5,0,0,0,0,5,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,1,1,1,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,5,0,0,0,5,0,0,0,0,0,0,0,5,0,0,0,0,0,0,5,0,0,0,0,1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,1,1,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,1,1,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,1,0,0,1,0,0,1,0,0,0,0,1,0,0,0,0,1,1,0,0,0,0,5,0,1,0,0,1,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,4,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,1,1,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,1,1,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,2],
-[2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,2,2,1,1,1,1,1,1,1,1,1,1,1,2,2,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2],
-    ];
-
-    let mut i = 1;
-    let x_bound = 6400. / 2.0 - 64. / 2.0;
-    let y_bound = 6400. / 2.0 - 64. / 2.0;
-    
-    for y in 0..100 {
-        for x in 0..100 {
-            //info!("({},{})", x, y);
-            match enemy_map[y][x] {
-                3 => {
-                    commands.spawn((
-                    // See player.rs for more info about the phong-lit material.
-                    Mesh2d(meshes.add(Rectangle::default())),
-                    MeshMaterial2d(materials.add(PlayerBaseMaterial {
-                        color: LinearRgba::BLUE,
-                        texture: Some(asset_server.load("enemy/enemy_standard_albedo.png")),
-                        lighting: crate::player_material::Lighting {
-                            ambient_reflection_coefficient: 0.0,
-                            ambient_light_intensity: 0.0,
-                            diffuse_reflection_coefficient: 1.0,
-                            shininess: 40.0,
-                        },
-                        lights: lights.lights,
-                        normal: Some(asset_server.load("enemy/enemy_standard_normal.png")),
-                        mesh_rotation: 0.0,
-                        sdf_texture: Some(sdf_texture.texture.clone()),
-                    })),
-                    Transform::from_xyz(-x_bound + (64. * x as f32), y_bound - (64. * y as f32), 5.).with_scale(Vec3::splat(64.)),
-                    Velocity::new(),
-                    Enemy::new(i, EnemyType::Normal),
-                    KinematicCollider {
-                        shape: Aabb2d {
-                            min: Vec2 { x: 0., y: 0. },
-                            max: Vec2 { x: 64., y: 64. },
-                        },
-                    },
-                    Health::new(NORMAL_HEALTH),
-                    ));
+    let Some(level) = levels.get(&current_level.handle) else {
+        error!("Level is not loaded, no enemies will be spawned");
+        return;
+    };
 
-                    i += 1;
-                }
-                4 => {
-                    commands.spawn((
-                    // See player.rs for more info about the phong-lit material.
-                    Mesh2d(meshes.add(Rectangle::default())),
-                    MeshMaterial2d(materials.add(PlayerBaseMaterial {
-                        color: LinearRgba::BLUE,
-                        texture: Some(asset_server.load("enemy/enemy_strong_albedo.png")),
-                        lighting: crate::player_material::Lighting {
-                            ambient_reflection_coefficient: 0.0,
-                            ambient_light_intensity: 0.0,
-                            diffuse_reflection_coefficient: 1.0,
-                            shininess: 40.0,
-                        },
-                        lights: lights.lights,
-                        normal: Some(asset_server.load("enemy/enemy_standard_normal.png")),
-                        mesh_rotation: 0.0,
-                        sdf_texture: Some(sdf_texture.texture.clone()),
-                    })),
-                    Transform::from_xyz(-x_bound + (64. * x as f32), y_bound - (64. * y as f32), 5.).with_scale(Vec3::splat(48.)),
-                    Velocity::new(),
-                    Enemy::new(i, EnemyType::Fast),
-                    KinematicCollider {
-                        shape: Aabb2d {
-                            min: Vec2 { x: 0., y: 0. },
-                            max: Vec2 { x: 48., y: 48. },
-                        },
-                    },
-                    Health::new(FAST_HEALTH),
-                    ));
+    let mut i: u16 = 1;
 
-                    i += 1;
-                }
-                5 => {
-                    commands.spawn((
-                    // See player.rs for more info about the phong-lit material.
-                    Mesh2d(meshes.add(Rectangle::default())),
-                    MeshMaterial2d(materials.add(PlayerBaseMaterial {
-                        color: LinearRgba::BLUE,
-                        texture: Some(asset_server.load("enemy/enemy_fast_albedo.png")),
-                        lighting: crate::player_material::Lighting {
-                            ambient_reflection_coefficient: 0.0,
-                            ambient_light_intensity: 0.0,
-                            diffuse_reflection_coefficient: 1.0,
-                            shininess: 40.0,
-                        },
-                        lights: lights.lights,
-                        normal: Some(asset_server.load("enemy/enemy_standard_normal.png")),
-                        mesh_rotation: 0.0,
-                        sdf_texture: Some(sdf_texture.texture.clone()),
-                    })),
-                    Transform::from_xyz(-x_bound + (64. * x as f32), y_bound - (64. * y as f32), 5.).with_scale(Vec3::splat(80.)),
-                    Velocity::new(),
-                    Enemy::new(i, EnemyType::Strong),
-                    KinematicCollider {
-                        shape: Aabb2d {
-                            min: Vec2 { x: 0., y: 0. },
-                            max: Vec2 { x: 80., y: 80. },
-                        },
-                    },
-                    Health::new(STRONG_HEALTH),
-                    ));
+    for y in 0..level.height {
+        for x in 0..level.width {
+            //info!("({},{})", x, y);
+            let Some(enemy_type) = EnemyType::from_code(level.enemies[y][x]) else {
+                continue;
+            };
+            let pos = level.tile_to_world(x, y);
+            let health = enemy_type.max_health();
+            spawner.spawn(&mut commands, i, enemy_type, pos, health);
+            spawned.write(EnemySpawnedEvent {
+                enemy_id: i,
+                enemy_type,
+                position: pos,
+                health,
+            });
 
-                    i += 1;
-                }
-                _ => {}
-            }
+            i += 1;
         }
     }
 
@@ -337,13 +280,11 @@ pub fn setup_enemy(
 }
 
 pub fn enemy_chase_velocity(
-    time: Res<Time>,
     mut enemy: Query<(&Enemy, &mut Transform, &mut Velocity), With<Enemy>>,
     mut player: Query<&mut Transform, (With<Player>, Without<Enemy>)>,
 ) {
     let mut min_dist = 10000.;
-    let deltat = time.delta_secs();
-    let accel = ACCEL_RATE * deltat;
+    let accel = ACCEL_RATE * TICK_SECS;
 
     for (enemy, mut enemy_transform, mut velocity) in enemy.iter_mut() {
         let mut player_transform = enemy_transform.clone();
@@ -373,7 +314,6 @@ pub fn enemy_chase_velocity(
 }
 
 pub fn enemy_cram_velocity(
-    time: Res<Time>,
     mut enemy_tuples: Query<(&Enemy, &mut Transform, &mut Velocity), With<Enemy>>,
 ) {
     let mut other_tvs: Vec<(Mut<'_, Transform>, Mut<'_, Velocity>)> = Vec::new();
@@ -401,11 +341,9 @@ pub fn enemy_cram_velocity(
 
 pub fn enemy_velocity_apply(
     mut commands: Commands,
-    time: Res<Time>,
     mut enemy_tuples: Query<(Entity, &mut Transform, &mut Velocity, &mut Enemy), With<Enemy>>,
     player: Query<&mut Transform, (With<Player>, Without<Enemy>)>,
 ) {
-    let deltat = time.delta_secs();
     for (mut this, mut transform, velocity, mut enemy) in enemy_tuples.iter_mut() {
         //Awakens enemies if players are close enough
         if !enemy.awake {
@@ -419,7 +357,7 @@ pub fn enemy_velocity_apply(
             }
         } 
         if enemy.awake {
-            let change = **velocity * deltat;
+            let change = **velocity * TICK_SECS;
             transform.translation += change.extend(0.);
             transform.translation.x = transform.translation.x.round();
             transform.translation.y = transform.translation.y.round();
@@ -443,11 +381,13 @@ pub fn enemy_attack(
 }
 
 pub fn enemy_damage(
-    mut enemies: Query<(Entity, &Transform, &mut Health), With<Enemy>>,
+    mut enemies: Query<(Entity, &Enemy, &Transform, &mut Health), With<Enemy>>,
     projectiles: Query<&Transform, With<Projectile>>,
     mut commands: Commands,
+    mut damaged: EventWriter<EnemyDamagedEvent>,
+    mut killed: EventWriter<EnemyKilledEvent>,
 ) {
-    for (enemy, enemy_transform, mut enemy_health) in enemies.iter_mut() {
+    for (entity, enemy, enemy_transform, mut enemy_health) in enemies.iter_mut() {
         for projectile_transform in projectiles.iter() {
             let distance =
                 (enemy_transform.translation - projectile_transform.translation).length();
@@ -456,7 +396,63 @@ pub fn enemy_damage(
             }
             // Damage, then check if enemy is dead...
             if enemy_health.damage(10) {
-                commands.entity(enemy).despawn();
+                commands.entity(entity).despawn();
+                killed.write(EnemyKilledEvent { enemy_id: enemy.enemy_id });
+                break;
+            }
+            damaged.write(EnemyDamagedEvent {
+                enemy_id: enemy.enemy_id,
+                health: enemy_health.current,
+            });
+        }
+    }
+}
+
+//Host messages are sent more than once, so anything already applied is skipped
+fn spawn_replicated_enemies(
+    mut commands: Commands,
+    mut spawner: EnemySpawner,
+    time: Res<Time<Real>>,
+    mut events: EventReader<EnemySpawnedEvent>,
+    enemies: Query<&Enemy>,
+) {
+    let mut known: Vec<u16> = enemies.iter().map(|e| e.enemy_id).collect();
+    for event in events.read() {
+        if known.contains(&event.enemy_id) {
+            continue;
+        }
+        let entity = spawner.spawn(&mut commands, event.enemy_id, event.enemy_type, event.position, event.health);
+        //Snapshots move it from here on
+        let mut buffer = InterpolationBuffer::default();
+        buffer.push(time.elapsed_secs_f64(), event.position, None);
+        commands.entity(entity).insert(buffer);
+        known.push(event.enemy_id);
+    }
+}
+
+fn apply_replicated_damage(
+    mut events: EventReader<EnemyDamagedEvent>,
+    mut enemies: Query<(&Enemy, &mut Health)>,
+) {
+    for event in events.read() {
+        for (enemy, mut health) in enemies.iter_mut() {
+            if enemy.enemy_id == event.enemy_id {
+                //Only ever goes down, a late duplicate can't undo a newer hit
+                health.current = health.current.min(event.health);
+            }
+        }
+    }
+}
+
+fn apply_replicated_deaths(
+    mut commands: Commands,
+    mut events: EventReader<EnemyKilledEvent>,
+    enemies: Query<(Entity, &Enemy)>,
+) {
+    for event in events.read() {
+        for (entity, enemy) in enemies.iter() {
+            if enemy.enemy_id == event.enemy_id {
+                commands.entity(entity).despawn();
             }
         }
     }
@@ -483,3 +479,42 @@ pub fn all_enemies_defeated(
     //     next_state.set(GameState::GameOver);
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The host's enemy ticks from a fixed start, with no frame time anywhere. Positions and velocities
+    //as raw bits so even a -0 against a 0 would count as a difference.
+    fn run(ticks: usize) -> Vec<(u16, [u32; 3], [u32; 2])> {
+        let mut world = World::new();
+        let mut schedule = Schedule::default();
+        schedule.add_systems((enemy_chase_velocity, enemy_cram_velocity, enemy_velocity_apply).chain());
+        world.spawn((Player { charge: 500, flashlight: None }, Transform::from_xyz(10., -20., 0.)));
+        world.spawn((Player { charge: 500, flashlight: None }, Transform::from_xyz(-200., 90., 0.)));
+        for id in 0..6 {
+            let enemy_type = if id % 2 == 0 { EnemyType::Fast } else { EnemyType::Normal };
+            let pos = Vec3::new(300. + 37. * id as f32, -120. + 51. * id as f32, 5.);
+            world.spawn((Enemy::new(id, enemy_type), Velocity::new(), Transform::from_translation(pos)));
+        }
+        for _ in 0..ticks {
+            schedule.run(&mut world);
+        }
+        let mut state: Vec<_> = world
+            .query::<(&Enemy, &Transform, &Velocity)>()
+            .iter(&world)
+            .map(|(enemy, transform, velocity)| {
+                (enemy.enemy_id, transform.translation.to_array().map(f32::to_bits), velocity.to_array().map(f32::to_bits))
+            })
+            .collect();
+        state.sort_by_key(|(id, ..)| *id);
+        state
+    }
+
+    #[test]
+    fn same_ticks_give_the_same_state() {
+        let moved = run(300);
+        assert_eq!(moved, run(300));
+        assert_ne!(moved, run(0));
+    }
+}
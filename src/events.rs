@@ -1,3 +1,4 @@
+use crate::enemy::EnemyType;
 use bevy::prelude::*;
 
 #[derive(Event, Debug, Clone, Copy)]
@@ -15,4 +16,24 @@ impl DamagePlayerEvent {
 #[derive(Event)]
 pub struct SanityDepletedEvent {
     pub player: Entity,
-}
\ No newline at end of file
+}
+
+//Host spawned an enemy, or on a client, the host said it did
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EnemySpawnedEvent {
+    pub enemy_id: u16,
+    pub enemy_type: EnemyType,
+    pub position: Vec2,
+    pub health: i32,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EnemyDamagedEvent {
+    pub enemy_id: u16,
+    pub health: i32,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EnemyKilledEvent {
+    pub enemy_id: u16,
+}
@@ -20,6 +20,9 @@ impl Plugin for ExampleScenePlugin {
     }
 }
 
+//Swaps between a match and this scene
+pub const TOGGLE_KEY: KeyCode = KeyCode::KeyG;
+
 const LAYER_MAIN: usize = 5;
 const LAYER_NORMALS: usize = 6;
 
@@ -76,7 +79,7 @@ fn toggle_scene(
     state: Res<State<crate::GameState>>,
     mut next: ResMut<NextState<crate::GameState>>,
 ) {
-    if kbd.just_pressed(KeyCode::KeyG) {
+    if kbd.just_pressed(TOGGLE_KEY) {
         match state.get() {
             crate::GameState::Playing => next.set(crate::GameState::ExampleScene),
             crate::GameState::ExampleScene => next.set(crate::GameState::Playing),
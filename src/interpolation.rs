@@ -0,0 +1,166 @@
+use crate::{GameState, config::Config, server::type_equals_host};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::f32::consts;
+
+//How much of an entity's past is kept, anything older than this can't be rendered anyway
+const BUFFER_SECONDS: f64 = 1.0;
+const BUFFER_SAMPLES: usize = 64;
+
+//Remote players and enemies on a client are drawn slightly in the past so there are usually two
+//received positions to blend between. When updates are late the last known velocity is carried
+//forward for a short while before the entity is held in place.
+pub struct InterpolationPlugin;
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            interpolate_remote_entities
+                .run_if(in_state(GameState::Playing))
+                .run_if(not(type_equals_host)),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sample {
+    time: f64,
+    pos: Vec2,
+    angle: Option<f32>,
+}
+
+//Received positions of one remote entity, stamped with Time<Real> when they arrived
+#[derive(Component, Default)]
+pub struct InterpolationBuffer {
+    samples: VecDeque<Sample>,
+}
+
+impl InterpolationBuffer {
+    //angle is the network angle in radians, None for entities that turn themselves locally
+    pub fn push(&mut self, time: f64, pos: Vec2, angle: Option<f32>) {
+        //Several packets can land in the same frame, only the newest one matters
+        if let Some(last) = self.samples.back_mut() {
+            if last.time >= time {
+                *last = Sample { time: last.time, pos, angle };
+                return;
+            }
+        }
+        self.samples.push_back(Sample { time, pos, angle });
+        while self.samples.len() > BUFFER_SAMPLES
+            || self.samples.front().is_some_and(|s| time - s.time > BUFFER_SECONDS)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    //Position and angle to draw at render_time
+    pub fn sample(&self, render_time: f64, max_extrapolation: f64) -> Option<(Vec2, Option<f32>)> {
+        let first = self.samples.front()?;
+        if render_time <= first.time {
+            return Some((first.pos, first.angle));
+        }
+
+        //Between two samples, blend
+        for (a, b) in self.samples.iter().zip(self.samples.iter().skip(1)) {
+            if render_time <= b.time {
+                let t = ((render_time - a.time) / (b.time - a.time)) as f32;
+                return Some((a.pos.lerp(b.pos, t), lerp_angle(a.angle, b.angle, t)));
+            }
+        }
+
+        //Past the newest sample, keep going in the same direction but not forever
+        let last = self.samples.back()?;
+        let Some(prev) = self.samples.iter().rev().nth(1) else {
+            return Some((last.pos, last.angle));
+        };
+        let ahead = (render_time - last.time).min(max_extrapolation);
+        let velocity = (last.pos - prev.pos) / (last.time - prev.time) as f32;
+        Some((last.pos + velocity * ahead as f32, last.angle))
+    }
+}
+
+fn lerp_angle(a: Option<f32>, b: Option<f32>, t: f32) -> Option<f32> {
+    let (a, b) = (a?, b?);
+    //Take the short way round
+    let mut diff = (b - a) % consts::TAU;
+    if diff > consts::PI {
+        diff -= consts::TAU;
+    } else if diff < -consts::PI {
+        diff += consts::TAU;
+    }
+    Some(a + diff * t)
+}
+
+fn interpolate_remote_entities(
+    time: Res<Time<Real>>,
+    config: Res<Config>,
+    mut entities: Query<(&mut Transform, &InterpolationBuffer)>,
+) {
+    let render_time = time.elapsed_secs_f64() - config.interpolation_delay as f64 / 1000.;
+    let max_extrapolation = config.max_extrapolation as f64 / 1000.;
+    for (mut transform, buffer) in &mut entities {
+        let Some((pos, angle)) = buffer.sample(render_time, max_extrapolation) else {
+            continue;
+        };
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+        if let Some(angle) = angle {
+            transform.rotation = Quat::from_rotation_z(angle - consts::PI / 2.);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(samples: &[(f64, f32, f32)]) -> InterpolationBuffer {
+        let mut buffer = InterpolationBuffer::default();
+        for &(time, x, y) in samples {
+            buffer.push(time, Vec2::new(x, y), None);
+        }
+        buffer
+    }
+
+    #[test]
+    fn empty_buffer_has_nothing_to_draw() {
+        assert_eq!(InterpolationBuffer::default().sample(1.0, 0.2), None);
+    }
+
+    #[test]
+    fn blends_between_samples() {
+        let buffer = buffer(&[(1.0, 0., 0.), (1.1, 10., -10.)]);
+        assert_eq!(buffer.sample(1.05, 0.2), Some((Vec2::new(5., -5.), None)));
+        //Before the oldest sample we hold the oldest position
+        assert_eq!(buffer.sample(0.5, 0.2), Some((Vec2::new(0., 0.), None)));
+    }
+
+    #[test]
+    fn extrapolation_is_bounded() {
+        let buffer = buffer(&[(1.0, 0., 0.), (1.1, 10., 0.)]);
+        let (pos, _) = buffer.sample(1.15, 0.2).unwrap();
+        assert!((pos.x - 15.).abs() < 1e-3);
+        //Way past the cap we stop where the cap put us
+        let (pos, _) = buffer.sample(5.0, 0.2).unwrap();
+        assert!((pos.x - 30.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn same_frame_samples_keep_the_newest() {
+        let mut buffer = buffer(&[(1.0, 0., 0.)]);
+        buffer.push(1.0, Vec2::new(3., 3.), None);
+        assert_eq!(buffer.sample(2.0, 0.2), Some((Vec2::new(3., 3.), None)));
+    }
+
+    #[test]
+    fn old_samples_are_dropped() {
+        let buffer = buffer(&[(0.0, 100., 0.), (2.0, 0., 0.), (2.1, 1., 0.)]);
+        assert_eq!(buffer.samples.len(), 2);
+    }
+
+    #[test]
+    fn angles_take_the_short_way() {
+        let angle = lerp_angle(Some(3.0), Some(-3.0), 0.5).unwrap();
+        assert!((angle.abs() - consts::PI).abs() < 0.01);
+    }
+}
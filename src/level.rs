@@ -0,0 +1,301 @@
+use crate::{
+    GameState,
+    collectible::Collectible,
+    config::Config,
+    components::{Collectible as OldCollectible, StaticCollider},
+    enemy::Enemy,
+    levelgen,
+    net_control::NetControl,
+    player::Player,
+    projectile::Projectile,
+    tiling::Tile,
+};
+use bevy::asset::{AssetLoader, LoadContext, io::Reader};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+//Every level layer is a grid of 64x64 tiles
+pub const TILE_SIZE: f32 = 64.;
+const DEFAULT_LEVEL: &str = "levels/warehouse.level.ron";
+//Every tile needs its own u16 NetId, the top 256 are kept for things the level doesn't place
+const MAX_TILES: usize = u16::MAX as usize - 255;
+
+pub struct LevelPlugin;
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .add_event::<HostLevel>()
+            .add_systems(Startup, load_level)
+            //Before StateTransition, so the level is swapped before Start takes the client into Playing
+            .add_systems(PreUpdate, follow_host_level.run_if(on_event::<HostLevel>))
+            //Entering Playing builds the world again, so whatever the last match left behind has to go
+            .add_systems(OnEnter(GameState::Menu), clear_world)
+            .add_systems(OnEnter(GameState::Joining), clear_world);
+    }
+}
+
+//Extraction zone, players win once they are all inside of it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Extraction {
+    pub position: (f32, f32),
+    pub radius: f32,
+}
+
+//A whole map, loaded from a .level.ron file under assets/levels.
+//Every layer is indexed [y][x], with row 0 being the top of the map.
+#[derive(Asset, TypePath, Debug, Clone, Serialize, Deserialize)]
+pub struct Level {
+    pub width: usize,
+    pub height: usize,
+    //Player spawn tiles as (x, y), in player id order
+    pub spawns: Vec<(usize, usize)>,
+    pub extraction: Extraction,
+    //Floor tile index into textures/tile_sheet.png, 1 based
+    pub tiles: Vec<Vec<usize>>,
+    //1 wall, 2 fence, 3/4 doors, 5+ props
+    pub walls: Vec<Vec<u8>>,
+    //3 normal, 4 fast, 5 strong
+    pub enemies: Vec<Vec<u8>>,
+    //3 ammo, 4 health, 5 battery, 6 revive kit, 10 flashlight
+    pub items: Vec<Vec<u8>>,
+}
+
+impl Level {
+    pub fn map_width(&self) -> f32 {
+        self.width as f32 * TILE_SIZE
+    }
+
+    pub fn map_height(&self) -> f32 {
+        self.height as f32 * TILE_SIZE
+    }
+
+    //World position of the center of tile (x, y)
+    pub fn tile_to_world(&self, x: usize, y: usize) -> Vec2 {
+        let x_bound = self.map_width() / 2.0 - TILE_SIZE / 2.0;
+        let y_bound = self.map_height() / 2.0 - TILE_SIZE / 2.0;
+        Vec2::new(-x_bound + (TILE_SIZE * x as f32), y_bound - (TILE_SIZE * y as f32))
+    }
+
+    //Tile a world position is on, None off the map
+    pub fn world_to_tile(&self, pos: Vec2) -> Option<(usize, usize)> {
+        let corner = self.tile_to_world(0, 0);
+        let x = ((pos.x - corner.x) / TILE_SIZE).round();
+        let y = ((corner.y - pos.y) / TILE_SIZE).round();
+        if x < 0. || y < 0. || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    //Players are placed on the top left corner of their spawn tile
+    pub fn spawn_position(&self, player_id: u8) -> Option<Vec2> {
+        let (x, y) = *self.spawns.get(player_id as usize)?;
+        Some(Vec2::new(
+            -self.map_width() / 2.0 + (TILE_SIZE * x as f32),
+            self.map_height() / 2.0 - (TILE_SIZE * y as f32),
+        ))
+    }
+
+    //NetId for whatever is built on tile (x, y), validate keeps every tile in range
+    pub fn tile_id(&self, x: usize, y: usize) -> u16 {
+        (y * self.width + x) as u16
+    }
+
+    pub fn extraction_position(&self) -> Vec2 {
+        Vec2::new(self.extraction.position.0, self.extraction.position.1)
+    }
+
+    //Makes sure every layer is height x width so the setup systems can index without checking
+    pub fn validate(&self) -> Result<(), LevelLoaderError> {
+        if self.width * self.height > MAX_TILES {
+            return Err(LevelLoaderError::TooLarge(self.width, self.height));
+        }
+        let layers = [
+            ("tiles", self.tiles.iter().map(|row| row.len()).collect::<Vec<_>>()),
+            ("walls", self.walls.iter().map(|row| row.len()).collect()),
+            ("enemies", self.enemies.iter().map(|row| row.len()).collect()),
+            ("items", self.items.iter().map(|row| row.len()).collect()),
+        ];
+        for (name, rows) in layers {
+            if rows.len() != self.height || rows.iter().any(|len| *len != self.width) {
+                return Err(LevelLoaderError::BadLayer(name));
+            }
+        }
+        for (x, y) in &self.spawns {
+            if *x >= self.width || *y >= self.height {
+                return Err(LevelLoaderError::BadSpawn(*x, *y));
+            }
+        }
+        Ok(())
+    }
+}
+
+//The level every setup system builds from
+#[derive(Resource)]
+pub struct CurrentLevel {
+    pub handle: Handle<Level>,
+    //File name without the extension, shown in the server browser
+    pub name: String,
+    //What a generated level came from, None for the level file. The host sends it with Start.
+    pub seed: Option<u64>,
+}
+
+impl CurrentLevel {
+    fn new(seed: Option<u64>, file: &LevelFile, levels: &mut Assets<Level>) -> Self {
+        match seed {
+            //Nothing to wait for, the level is in Assets straight away
+            Some(seed) => CurrentLevel {
+                handle: levels.add(levelgen::generate(seed, levelgen::DEFAULT_ROOMS)),
+                name: format!("generated-{}", seed),
+                seed: Some(seed),
+            },
+            None => CurrentLevel { handle: file.0.clone(), name: level_name(DEFAULT_LEVEL), seed: None },
+        }
+    }
+}
+
+//The level file, kept loaded so a client can go back to it after playing on something else
+#[derive(Resource)]
+struct LevelFile(Handle<Level>);
+
+//The level in the host's Start, which may not be the one we set up from our own config
+#[derive(Event, Debug, Clone, Copy)]
+pub struct HostLevel {
+    pub seed: Option<u64>,
+}
+
+fn load_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut levels: ResMut<Assets<Level>>,
+    config: Res<Config>,
+) {
+    let file = LevelFile(asset_server.load(DEFAULT_LEVEL));
+    commands.insert_resource(CurrentLevel::new(config.level_seed, &file, &mut levels));
+    commands.insert_resource(file);
+}
+
+fn follow_host_level(
+    mut events: EventReader<HostLevel>,
+    mut current: ResMut<CurrentLevel>,
+    file: Res<LevelFile>,
+    mut levels: ResMut<Assets<Level>>,
+) {
+    for event in events.read() {
+        //A replay may have left its own level in place, so the file is matched by handle
+        let same = match event.seed {
+            Some(seed) => current.seed == Some(seed),
+            None => current.handle == file.0,
+        };
+        if !same {
+            *current = CurrentLevel::new(event.seed, &file, &mut levels);
+            info!("Host is playing on {}", current.name);
+        }
+    }
+}
+
+//Despawns everything a match spawns, players included
+pub fn clear_world(
+    mut commands: Commands,
+    query_player: Query<Entity, Or<(With<Player>, With<NetControl>)>>,
+    query_tiles: Query<Entity, With<Tile>>,
+    query_collectible: Query<Entity, Or<(With<Collectible>, With<OldCollectible>)>>,
+    query_enemy: Query<Entity, With<Enemy>>,
+    query_walls: Query<Entity, With<StaticCollider>>,
+    query_projectile: Query<Entity, With<Projectile>>,
+) {
+    for entity in query_player
+        .iter()
+        .chain(query_tiles.iter())
+        .chain(query_collectible.iter())
+        .chain(query_enemy.iter())
+        .chain(query_walls.iter())
+        .chain(query_projectile.iter())
+    {
+        //Some entities match more than one query
+        commands.entity(entity).try_despawn();
+    }
+}
+
+//"levels/warehouse.level.ron" -> "warehouse"
+fn level_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    file.split('.').next().unwrap_or(file).to_string()
+}
+
+//Run condition so nothing starts a match before the level is in memory
+pub fn level_ready(current: Option<Res<CurrentLevel>>, levels: Res<Assets<Level>>) -> bool {
+    match current {
+        Some(current) => levels.contains(&current.handle),
+        None => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    BadLayer(&'static str),
+    BadSpawn(usize, usize),
+    TooLarge(usize, usize),
+}
+
+impl fmt::Display for LevelLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelLoaderError::Io(e) => write!(f, "could not read level: {}", e),
+            LevelLoaderError::Ron(e) => write!(f, "could not parse level: {}", e),
+            LevelLoaderError::BadLayer(name) => {
+                write!(f, "layer '{}' does not match the level width/height", name)
+            }
+            LevelLoaderError::BadSpawn(x, y) => {
+                write!(f, "spawn ({}, {}) is outside of the level", x, y)
+            }
+            LevelLoaderError::TooLarge(w, h) => {
+                write!(f, "{}x{} is more tiles than network ids", w, h)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LevelLoaderError {}
+
+impl From<std::io::Error> for LevelLoaderError {
+    fn from(e: std::io::Error) -> Self {
+        LevelLoaderError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for LevelLoaderError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        LevelLoaderError::Ron(e)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = LevelLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let level = ron::de::from_bytes::<Level>(&bytes)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
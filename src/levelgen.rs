@@ -0,0 +1,550 @@
+use crate::config::MAX_PLAYERS_LIMIT;
+use crate::level::{Extraction, Level, TILE_SIZE};
+use rand_chacha::ChaCha8Rng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Procedural levels. --level-seed builds the map from a seed instead of loading warehouse: one       ///
+///   room to a cell of a grid, corridors along a random spanning tree of the cells plus a few loops,    ///
+///   doors where a corridor goes through a room wall, then props, enemies and items in the rooms.       ///
+///   The players spawn in one room and the extraction zone is in the room the most corridors away.      ///
+///   It all comes from one ChaCha8Rng, a fixed algorithm, so a seed gives the same Level, codes and     ///
+///   all, on every build and machine. A test pins one seed to its level hash to keep it that way. The   ///
+///   host sends its seed with Start and clients build the same map from it, see level.rs.               ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Rooms across and down for --level-seed
+pub const DEFAULT_ROOMS: (usize, usize) = (5, 5);
+//Each room sits in a square of this many tiles, walls included, with at least 2 tiles of space to
+//the next cell so a corridor can turn in between
+const CELL: usize = 18;
+//Inside of a room without its walls. 8 fits the spawn grid and leaves a clear middle around props.
+const MIN_ROOM: usize = 8;
+const MAX_ROOM: usize = CELL - 6;
+//Wide enough for a player to get past another, and the doors are this many tiles across
+const CORRIDOR: usize = 3;
+//Chance that two neighbouring rooms off the spanning tree get a corridor anyway
+const LOOP_CHANCE: f64 = 0.15;
+//Chance that a room is a yard with a fence around it instead of walls
+const FENCE_CHANCE: f64 = 0.2;
+
+//Tile sheet ids, 1 is plain black and only used outside the map
+const VOID_TILE: usize = 1;
+const CORRIDOR_TILE: usize = 5;
+const ROOM_TILES: [usize; 14] = [2, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+const WALL: u8 = 1;
+const FENCE: u8 = 2;
+//Door in a wall running left to right, and one in a wall running up and down
+const DOOR: u8 = 3;
+const DOOR_VERT: u8 = 4;
+//Barrel, chair, lamp, waste bin, bed. Desk (6) and dumpster (22) are bigger and only go on the
+//top row of a room, where the part that doesn't fit goes into the wall.
+const SMALL_PROPS: [u8; 5] = [5, 10, 14, 18, 26];
+const ALL_PROPS: [u8; 7] = [5, 6, 10, 14, 18, 22, 26];
+//Normal ones more often than fast ones, fast ones more often than strong ones
+const ENEMIES: [u8; 6] = [3, 3, 3, 4, 4, 5];
+const ITEMS: [u8; 8] = [3, 3, 4, 4, 5, 5, 6, 10];
+
+//Tiles inside a room, the walls are one tile further out on each side
+#[derive(Debug, Clone, Copy)]
+struct Room {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    fenced: bool,
+}
+impl Room {
+    fn random_tile(&self, rng: &mut ChaCha8Rng) -> (usize, usize) {
+        (rng.random_range(self.x..self.x + self.w), rng.random_range(self.y..self.y + self.h))
+    }
+
+    //The tiles just inside the walls
+    fn on_edge(&self, x: usize, y: usize) -> bool {
+        x == self.x || y == self.y || x == self.x + self.w - 1 || y == self.y + self.h - 1
+    }
+
+    fn on_wall(&self, x: usize, y: usize) -> bool {
+        let inside_x = x + 1 >= self.x && x <= self.x + self.w;
+        let inside_y = y + 1 >= self.y && y <= self.y + self.h;
+        inside_x && inside_y && (x + 1 == self.x || y + 1 == self.y || x == self.x + self.w || y == self.y + self.h)
+    }
+}
+
+//The map as it is being built, turned into a Level at the end
+struct Generator {
+    rng: ChaCha8Rng,
+    cols: usize,
+    rows: usize,
+    width: usize,
+    height: usize,
+    floor: Vec<Vec<bool>>,
+    //Tiles a prop, spawn, enemy or item already took
+    taken: Vec<Vec<bool>>,
+    tiles: Vec<Vec<usize>>,
+    walls: Vec<Vec<u8>>,
+    enemies: Vec<Vec<u8>>,
+    items: Vec<Vec<u8>>,
+    //One per cell, cell (cx, cy) is rooms[cy * cols + cx]
+    rooms: Vec<Room>,
+    //Door tiles and their code
+    doors: Vec<(usize, usize, u8)>,
+}
+
+//Builds a cols x rows room level from the seed. The same seed and size always give the same Level.
+pub fn generate(seed: u64, (cols, rows): (usize, usize)) -> Level {
+    let cols = cols.max(1);
+    let rows = rows.max(1);
+    let (width, height) = (cols * CELL, rows * CELL);
+    let mut map = Generator {
+        rng: ChaCha8Rng::seed_from_u64(seed),
+        cols,
+        rows,
+        width,
+        height,
+        floor: vec![vec![false; width]; height],
+        taken: vec![vec![false; width]; height],
+        tiles: vec![vec![VOID_TILE; width]; height],
+        walls: vec![vec![0; width]; height],
+        enemies: vec![vec![0; width]; height],
+        items: vec![vec![0; width]; height],
+        rooms: Vec::new(),
+        doors: Vec::new(),
+    };
+
+    map.place_rooms();
+    let start = map.rng.random_range(0..cols * rows);
+    let corridors = map.plan_corridors(start);
+    for (a, b) in &corridors {
+        map.carve_corridor(*a, *b);
+    }
+    map.build_walls();
+    let exit = farthest_cell(start, cols * rows, &corridors);
+
+    let spawns = map.place_spawns(start);
+    map.place_props();
+    map.place_contents(start);
+
+    let exit_room = map.rooms[exit];
+    let mut level = Level {
+        width,
+        height,
+        spawns,
+        extraction: Extraction { position: (0., 0.), radius: 0. },
+        tiles: map.tiles,
+        walls: map.walls,
+        enemies: map.enemies,
+        items: map.items,
+    };
+    //Middle of the room, so a room with an even size puts it on the line between two tiles
+    let corner = level.tile_to_world(exit_room.x, exit_room.y);
+    let far_corner = level.tile_to_world(exit_room.x + exit_room.w - 1, exit_room.y + exit_room.h - 1);
+    let middle = (corner + far_corner) / 2.;
+    level.extraction = Extraction {
+        position: (middle.x, middle.y),
+        radius: ((exit_room.w.min(exit_room.h) as f32 / 2. - 1.) * TILE_SIZE).min(250.),
+    };
+
+    //The layout can't cut a spawn off, but props are placed by footprint so this makes sure of it
+    if !reaches_extraction(&level) {
+        for row in level.walls.iter_mut() {
+            for code in row.iter_mut().filter(|code| **code > DOOR_VERT) {
+                *code = 0;
+            }
+        }
+    }
+    level
+}
+
+impl Generator {
+    fn place_rooms(&mut self) {
+        for cy in 0..self.rows {
+            for cx in 0..self.cols {
+                let w = self.rng.random_range(MIN_ROOM..=MAX_ROOM);
+                let h = self.rng.random_range(MIN_ROOM..=MAX_ROOM);
+                //Walls go 2 tiles in from the cell edge at the least
+                let x = cx * CELL + 3 + self.rng.random_range(0..=MAX_ROOM - w);
+                let y = cy * CELL + 3 + self.rng.random_range(0..=MAX_ROOM - h);
+                let fenced = self.rng.random_bool(FENCE_CHANCE);
+                let room = Room { x, y, w, h, fenced };
+                let tile = *ROOM_TILES.choose(&mut self.rng).unwrap();
+                for ty in y..y + h {
+                    for tx in x..x + w {
+                        self.floor[ty][tx] = true;
+                        self.tiles[ty][tx] = tile;
+                    }
+                }
+                self.rooms.push(room);
+            }
+        }
+    }
+
+    //Random depth first walk over the cells for a spanning tree, so every room is connected, then a
+    //few extra corridors so it isn't all dead ends. Each pair is (left or upper cell, the other).
+    fn plan_corridors(&mut self, start: usize) -> Vec<(usize, usize)> {
+        let (cols, rows) = (self.cols, self.rows);
+        let mut visited = vec![false; cols * rows];
+        let mut corridors = Vec::new();
+        let mut stack = vec![start];
+        visited[start] = true;
+        while let Some(&cell) = stack.last() {
+            let next: Vec<usize> = neighbours(cell, cols, rows).into_iter().filter(|n| !visited[*n]).collect();
+            match next.choose(&mut self.rng) {
+                Some(&n) => {
+                    visited[n] = true;
+                    corridors.push((cell.min(n), cell.max(n)));
+                    stack.push(n);
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        for cell in 0..cols * rows {
+            for n in neighbours(cell, cols, rows) {
+                if n > cell && !corridors.contains(&(cell, n)) && self.rng.random_bool(LOOP_CHANCE) {
+                    corridors.push((cell, n));
+                }
+            }
+        }
+        corridors
+    }
+
+    //Leaves a through one wall, turns once in the space between the cells and goes into b through
+    //the facing wall. a is left of or above b.
+    fn carve_corridor(&mut self, a: usize, b: usize) {
+        let (ra, rb) = (self.rooms[a], self.rooms[b]);
+        if a / self.cols == b / self.cols {
+            let ya = self.rng.random_range(ra.y..=ra.y + ra.h - CORRIDOR);
+            let yb = self.rng.random_range(rb.y..=rb.y + rb.h - CORRIDOR);
+            //The wall columns the doors go in
+            let (xa, xb) = (ra.x + ra.w, rb.x - 1);
+            let turn = self.rng.random_range(xa + 1..=xb - CORRIDOR);
+            self.carve(xa, ya, turn + CORRIDOR - 1, ya + CORRIDOR - 1);
+            self.carve(turn, ya.min(yb), turn + CORRIDOR - 1, ya.max(yb) + CORRIDOR - 1);
+            self.carve(turn, yb, xb, yb + CORRIDOR - 1);
+            for i in 0..CORRIDOR {
+                self.doors.push((xa, ya + i, DOOR_VERT));
+                self.doors.push((xb, yb + i, DOOR_VERT));
+            }
+        } else {
+            let xa = self.rng.random_range(ra.x..=ra.x + ra.w - CORRIDOR);
+            let xb = self.rng.random_range(rb.x..=rb.x + rb.w - CORRIDOR);
+            let (ya, yb) = (ra.y + ra.h, rb.y - 1);
+            let turn = self.rng.random_range(ya + 1..=yb - CORRIDOR);
+            self.carve(xa, ya, xa + CORRIDOR - 1, turn + CORRIDOR - 1);
+            self.carve(xa.min(xb), turn, xa.max(xb) + CORRIDOR - 1, turn + CORRIDOR - 1);
+            self.carve(xb, turn, xb + CORRIDOR - 1, yb);
+            for i in 0..CORRIDOR {
+                self.doors.push((xa + i, ya, DOOR));
+                self.doors.push((xb + i, yb, DOOR));
+            }
+        }
+    }
+
+    //Inclusive corners. Room floors keep their own tile.
+    fn carve(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                if self.in_room(x, y).is_none() {
+                    self.tiles[y][x] = CORRIDOR_TILE;
+                }
+                self.floor[y][x] = true;
+            }
+        }
+    }
+
+    fn in_room(&self, x: usize, y: usize) -> Option<&Room> {
+        self.rooms.iter().find(|r| x >= r.x && x < r.x + r.w && y >= r.y && y < r.y + r.h)
+    }
+
+    //Every tile next to floor that isn't floor gets a wall, or a fence around a yard. Door tiles were
+    //carved as floor and get their code last.
+    fn build_walls(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.floor[y][x] || !self.touches_floor(x, y) {
+                    continue;
+                }
+                let fenced = self.rooms.iter().any(|r| r.fenced && r.on_wall(x, y));
+                self.walls[y][x] = if fenced { FENCE } else { WALL };
+                self.tiles[y][x] = CORRIDOR_TILE;
+            }
+        }
+        for (x, y, code) in &self.doors {
+            self.walls[*y][*x] = *code;
+        }
+    }
+
+    fn touches_floor(&self, x: usize, y: usize) -> bool {
+        (y.saturating_sub(1)..=(y + 1).min(self.height - 1))
+            .any(|ny| (x.saturating_sub(1)..=(x + 1).min(self.width - 1)).any(|nx| self.floor[ny][nx]))
+    }
+
+    //Two rows of four, a tile apart, in player id order
+    fn place_spawns(&mut self, start: usize) -> Vec<(usize, usize)> {
+        let room = self.rooms[start];
+        (0..MAX_PLAYERS_LIMIT as usize)
+            .map(|i| {
+                let spawn = (room.x + 1 + 2 * (i % 4), room.y + 1 + 2 * (i / 4));
+                self.taken[spawn.1][spawn.0] = true;
+                spawn
+            })
+            .collect()
+    }
+
+    //Props only go on the tiles along the walls and stay clear of doors, so the middle of every room
+    //and the way through it stay open
+    fn place_props(&mut self) {
+        for i in 0..self.rooms.len() {
+            let room = self.rooms[i];
+            let count = self.rng.random_range(2..=5);
+            for _ in 0..count * 4 {
+                if self.count_props(&room) >= count {
+                    break;
+                }
+                let (x, y) = room.random_tile(&mut self.rng);
+                if !room.on_edge(x, y) {
+                    continue;
+                }
+                let code = if y == room.y {
+                    *ALL_PROPS.choose(&mut self.rng).unwrap()
+                } else {
+                    *SMALL_PROPS.choose(&mut self.rng).unwrap()
+                };
+                let footprint = prop_footprint(code, x, y);
+                let fits = footprint.iter().all(|(fx, fy)| {
+                    let inside = *fx < self.width && *fy < self.height;
+                    //The parts over the wall don't matter, the parts over a corridor do
+                    let over_wall = inside && !self.floor[*fy][*fx];
+                    let in_this_room = inside && room.x <= *fx && *fx < room.x + room.w && room.y <= *fy && *fy < room.y + room.h;
+                    over_wall || (in_this_room && !self.taken[*fy][*fx] && !self.near_door(*fx, *fy))
+                });
+                if !fits {
+                    continue;
+                }
+                for (fx, fy) in footprint {
+                    if fx < self.width && fy < self.height {
+                        self.taken[fy][fx] = true;
+                    }
+                }
+                self.walls[y][x] = code;
+            }
+        }
+    }
+
+    fn count_props(&self, room: &Room) -> usize {
+        (room.y..room.y + room.h)
+            .map(|y| (room.x..room.x + room.w).filter(|x| self.walls[y][*x] > DOOR_VERT).count())
+            .sum()
+    }
+
+    fn near_door(&self, x: usize, y: usize) -> bool {
+        self.doors.iter().any(|(dx, dy, _)| dx.abs_diff(x) <= 2 && dy.abs_diff(y) <= 2)
+    }
+
+    //Enemies in every room but the one the players start in, items anywhere
+    fn place_contents(&mut self, start: usize) {
+        for i in 0..self.rooms.len() {
+            let room = self.rooms[i];
+            let enemies = if i == start { 0 } else { self.rng.random_range(1..=3) };
+            let items = self.rng.random_range(0..=2);
+            for _ in 0..enemies {
+                if let Some((x, y)) = self.free_tile(&room) {
+                    self.enemies[y][x] = *ENEMIES.choose(&mut self.rng).unwrap();
+                }
+            }
+            for _ in 0..items {
+                if let Some((x, y)) = self.free_tile(&room) {
+                    self.items[y][x] = *ITEMS.choose(&mut self.rng).unwrap();
+                }
+            }
+        }
+    }
+
+    //A few tries at a tile nothing is on, a crowded room just gets less
+    fn free_tile(&mut self, room: &Room) -> Option<(usize, usize)> {
+        for _ in 0..8 {
+            let (x, y) = room.random_tile(&mut self.rng);
+            if !self.taken[y][x] {
+                self.taken[y][x] = true;
+                return Some((x, y));
+            }
+        }
+        None
+    }
+}
+
+fn neighbours(cell: usize, cols: usize, rows: usize) -> Vec<usize> {
+    let (cx, cy) = (cell % cols, cell / cols);
+    let mut out = Vec::new();
+    if cx > 0 {
+        out.push(cell - 1);
+    }
+    if cx + 1 < cols {
+        out.push(cell + 1);
+    }
+    if cy > 0 {
+        out.push(cell - cols);
+    }
+    if cy + 1 < rows {
+        out.push(cell + cols);
+    }
+    out
+}
+
+//The cell the most corridors away from start, the first one found on a tie
+fn farthest_cell(start: usize, cells: usize, corridors: &[(usize, usize)]) -> usize {
+    let mut dist = vec![usize::MAX; cells];
+    let mut queue = VecDeque::from([start]);
+    dist[start] = 0;
+    let mut farthest = start;
+    while let Some(cell) = queue.pop_front() {
+        if dist[cell] > dist[farthest] {
+            farthest = cell;
+        }
+        for (a, b) in corridors {
+            let next = if *a == cell { *b } else if *b == cell { *a } else { continue };
+            if dist[next] == usize::MAX {
+                dist[next] = dist[cell] + 1;
+                queue.push_back(next);
+            }
+        }
+    }
+    farthest
+}
+
+//Tiles a prop's collider covers, see build_walls_from_map. Anything to the right or above can be
+//off the map for a prop on the edge, callers skip those.
+fn prop_footprint(code: u8, x: usize, y: usize) -> Vec<(usize, usize)> {
+    let (right, up) = match code {
+        6 => (2, 1),
+        22 => (2, 2),
+        _ => (0, 0),
+    };
+    (y.saturating_sub(up)..=y).flat_map(|fy| (x..=x + right).map(move |fx| (fx, fy))).collect()
+}
+
+//Whether every spawn can walk to the middle of the extraction zone. Walls, fences and props block,
+//doors don't since they open for players.
+pub fn reaches_extraction(level: &Level) -> bool {
+    let Some((ex, ey)) = level.world_to_tile(level.extraction_position()) else {
+        return false;
+    };
+    let mut blocked = vec![vec![false; level.width]; level.height];
+    for y in 0..level.height {
+        for x in 0..level.width {
+            match level.walls[y][x] {
+                0 | DOOR | DOOR_VERT => {}
+                WALL | FENCE => blocked[y][x] = true,
+                code => {
+                    for (fx, fy) in prop_footprint(code, x, y) {
+                        if fx < level.width {
+                            blocked[fy][fx] = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if blocked[ey][ex] {
+        return false;
+    }
+
+    //Flood out from the extraction zone, then look at where it got to
+    let mut reached = vec![vec![false; level.width]; level.height];
+    let mut queue = VecDeque::from([(ex, ey)]);
+    reached[ey][ex] = true;
+    while let Some((x, y)) = queue.pop_front() {
+        let steps = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+        for (nx, ny) in steps {
+            if nx < level.width && ny < level.height && !blocked[ny][nx] && !reached[ny][nx] {
+                reached[ny][nx] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    level.spawns.iter().all(|(x, y)| reached[*y][*x])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_level() {
+        let a = ron::to_string(&generate(7, DEFAULT_ROOMS)).unwrap();
+        let b = ron::to_string(&generate(7, DEFAULT_ROOMS)).unwrap();
+        let c = ron::to_string(&generate(8, DEFAULT_ROOMS)).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    //Every field of the level, with sizes as u32 so it is the same on 32 and 64 bit
+    fn level_hash(level: &Level) -> String {
+        let mut bytes = Vec::new();
+        let mut push = |n: usize| bytes.extend_from_slice(&(n as u32).to_le_bytes());
+        push(level.width);
+        push(level.height);
+        for (x, y) in &level.spawns {
+            push(*x);
+            push(*y);
+        }
+        for row in &level.tiles {
+            row.iter().for_each(|t| push(*t));
+        }
+        for layer in [&level.walls, &level.enemies, &level.items] {
+            layer.iter().flatten().for_each(|code| push(*code as usize));
+        }
+        let Extraction { position: (x, y), radius } = level.extraction;
+        for f in [x, y, radius] {
+            bytes.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        blake3::hash(&bytes).to_hex().to_string()
+    }
+
+    //Fails if anything changes what a seed makes, the generator or the random numbers under it.
+    //Hosts and clients on builds that disagree here would play on different maps.
+    #[test]
+    fn seed_makes_a_known_level() {
+        assert_eq!(level_hash(&generate(2525, DEFAULT_ROOMS)), "318a811d974f08498fd8dc6e602e0f3f6defcc43eae9f732029ef6af13677803");
+    }
+
+    #[test]
+    fn levels_are_valid_and_connected() {
+        for seed in 0..40 {
+            for rooms in [DEFAULT_ROOMS, (1, 1), (2, 1), (1, 3), (4, 2)] {
+                let level = generate(seed, rooms);
+                assert!(level.validate().is_ok(), "seed {} {:?}", seed, rooms);
+                assert_eq!(level.spawns.len(), MAX_PLAYERS_LIMIT as usize);
+                assert!(reaches_extraction(&level), "seed {} {:?}", seed, rooms);
+            }
+        }
+    }
+
+    #[test]
+    fn uses_the_level_codes() {
+        let level = generate(3, DEFAULT_ROOMS);
+        let all = |layer: &Vec<Vec<u8>>| layer.iter().flatten().copied().filter(|c| *c != 0).collect::<Vec<_>>();
+        let walls = all(&level.walls);
+        assert!(walls.contains(&WALL) && walls.contains(&DOOR) && walls.contains(&DOOR_VERT));
+        assert!(walls.iter().all(|c| *c <= DOOR_VERT || ALL_PROPS.contains(c)));
+        assert!(walls.iter().any(|c| ALL_PROPS.contains(c)));
+        assert!(all(&level.enemies).iter().all(|c| ENEMIES.contains(c)));
+        assert!(all(&level.items).iter().all(|c| ITEMS.contains(c)));
+        assert!(level.tiles.iter().flatten().all(|t| (1..=16).contains(t)));
+    }
+
+    #[test]
+    fn walled_in_spawn_is_caught() {
+        let mut level = generate(5, (2, 2));
+        let (x, y) = level.spawns[0];
+        for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            level.walls[ny][nx] = WALL;
+        }
+        assert!(!reaches_extraction(&level));
+    }
+}
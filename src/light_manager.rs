@@ -1,9 +1,12 @@
+use crate::config::MAX_PLAYERS_LIMIT;
 use crate::player::Player;
 use crate::{components::LightSource, player_material::PlayerBaseMaterial};
 use bevy::{prelude::*, render::render_resource::ShaderType};
 
-// Set number of total lights here, as well as in player_base.wgsl. Current limit of 4 light sources
-const NUM_LIGHTS: i32 = 5;
+// One flashlight per player slot comes first, the map's own lights after them
+const NUM_FLASHLIGHTS: usize = MAX_PLAYERS_LIMIT as usize;
+// Set number of total lights here, as well as in player_base.wgsl.
+pub const NUM_LIGHTS: i32 = NUM_FLASHLIGHTS as i32 + 1;
 #[derive(Resource, Default)]
 pub struct Lights {
     pub num_lights: i32,
@@ -43,41 +46,20 @@ pub struct Light {
 }
 
 pub fn setup_lights(mut commands: Commands) {
-    commands.spawn({
-        let transform = Transform::from_xyz(0., 0., 0.);
-        (
-            transform,
-            // Here's how this works:
-            // If you want point light, 'cone' = 0.
-            // For cone lights, 'cone' = angle of the cone.
-            // 'angle' is only for cone lights
-            // range does nothing for now. all lights have infinite range.
-            LightSource::new(transform.translation, 1.0, 500.0, 80, 0.0),
-        )
-    });
-    commands.spawn({
-        let transform = Transform::from_xyz(0., 0., 0.);
-        (
-            transform,
-            LightSource::new(transform.translation, 1.0, 500.0, 80, 0.0),
-        )
-    });
-
-    commands.spawn({
-        let transform = Transform::from_xyz(0., 0., 0.);
-        (
-            transform,
-            LightSource::new(transform.translation, 1.0, 500.0, 80, 0.0),
-        )
-    });
-
-    commands.spawn({
-        let transform = Transform::from_xyz(0., 0., 0.);
-        (
-            transform,
-            LightSource::new(transform.translation, 1.0, 500.0, 80, 0.0),
-        )
-    });
+    for _ in 0..NUM_FLASHLIGHTS {
+        commands.spawn({
+            let transform = Transform::from_xyz(0., 0., 0.);
+            (
+                transform,
+                // Here's how this works:
+                // If you want point light, 'cone' = 0.
+                // For cone lights, 'cone' = angle of the cone.
+                // 'angle' is only for cone lights
+                // range does nothing for now. all lights have infinite range.
+                LightSource::new(transform.translation, 1.0, 500.0, 80, 0.0),
+            )
+        });
+    }
 
     commands.spawn({
         let transform = Transform::from_xyz(-2688., 2944., 0.);
@@ -151,10 +133,16 @@ pub fn sync_lights_to_players(
     players: Query<&Transform, With<Player>>,
     mut lights_res: ResMut<Lights>,
 ) {
-    for (i, transform) in players.iter().take(NUM_LIGHTS as usize).enumerate() {
+    let mut lit = 0;
+    for (i, transform) in players.iter().take(NUM_FLASHLIGHTS).enumerate() {
         lights_res.lights[i].position = transform.translation;
         let (_, _, rot_z) = transform.rotation.to_euler(EulerRot::XYZ);
         lights_res.lights[i].angle = rot_z.to_degrees() + 90.0;
+        lit = i + 1;
+    }
+    // Flashlights for empty player slots would otherwise all shine from the middle of the map
+    for light in &mut lights_res.lights[lit..NUM_FLASHLIGHTS] {
+        light.intensity = 0.0;
     }
 }
 
@@ -163,8 +151,8 @@ pub fn assign_flashlights_to_players(
     mut players: Query<&mut Player>,
     lights: Query<Entity, With<LightSource>>,
 ) {
-    // Get the first 4 lights as a Vec<Entity>
-    let flashlight_entities: Vec<Entity> = lights.iter().take(4).collect();
+    // The flashlights are the first lights spawned, one per player slot
+    let flashlight_entities: Vec<Entity> = lights.iter().take(NUM_FLASHLIGHTS).collect();
 
     // Assign each player one flashlight.
     // Should be much more consistent than previous implementation.
@@ -1,24 +1,85 @@
 use crate::GameState;
+use crate::level::level_ready;
+use crate::player::player_color;
+use crate::protocol::LobbyEntry;
+use crate::secure::JoinCode;
 use bevy::prelude::*;
 
 pub struct LobbyPlugin;
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Lobby), display_lobby)
+        app.init_resource::<LobbyRoster>()
+            //The host waits in Lobby, clients wait in Joining, both see the same screen
+            .add_systems(OnEnter(GameState::Lobby), (reset_roster, display_lobby))
+            .add_systems(OnEnter(GameState::Joining), (reset_roster, display_lobby))
             // Clean up lobby camera when exiting to prevent double cameras from breaking rotation/shooting in Playing state
             .add_systems(OnExit(GameState::Lobby), cleanup_lobby)
-            .add_systems(Update, wait_for_input.run_if(in_state(GameState::Lobby)));
+            .add_systems(OnExit(GameState::Joining), cleanup_lobby)
+            .add_systems(
+                Update,
+                update_lobby.run_if(in_state(GameState::Lobby).or(in_state(GameState::Joining))),
+            )
+            .add_systems(Update, toggle_ready.run_if(in_state(GameState::Joining)))
+            // The match can't start until the level file has finished loading
+            .add_systems(Update, wait_for_input.run_if(in_state(GameState::Lobby)).run_if(level_ready));
+    }
+}
+
+//Name and readiness of a player waiting to start, kept by the host on each player's NetControl entity
+#[derive(Component, Debug, Clone)]
+pub struct PlayerInfo {
+    pub name: String,
+    pub ready: bool,
+    //Round trip to this player, always 0 for the host's own player
+    pub ping_ms: u16,
+}
+impl PlayerInfo {
+    pub fn new(name: String, ready: bool) -> Self {
+        Self { name, ready, ping_ms: 0 }
+    }
+}
+
+//Who is in the lobby. The host builds it from PlayerInfo, clients get it in LobbyState.
+#[derive(Resource, Default)]
+pub struct LobbyRoster {
+    pub players: Vec<LobbyEntry>,
+    //Client only, whether we have readied up
+    pub ready: bool,
+}
+impl LobbyRoster {
+    //The host counts as ready, so an empty lobby can always be started
+    pub fn all_ready(&self) -> bool {
+        self.players.iter().all(|p| p.ready)
     }
 }
 
 #[derive(Component)]
 pub struct LobbyScreen;
 
+//Holds one row per player, rebuilt whenever the roster changes
+#[derive(Component)]
+struct LobbyList;
+
+//What the local player can do next
+#[derive(Component)]
+struct LobbyHint;
+
+//The join code, for the host to pass on to everyone else
+#[derive(Component)]
+struct LobbyCode;
+
 // Tag component to identify the lobby camera so it can be cleaned up when leaving lobby state
 #[derive(Component)]
 pub struct LobbyCamera;
 
-fn cleanup_lobby(mut commands: Commands, query: Query<Entity, (With<Camera>, With<LobbyCamera>)>) {
+fn reset_roster(mut roster: ResMut<LobbyRoster>) {
+    *roster = LobbyRoster::default();
+}
+
+fn cleanup_lobby(
+    mut commands: Commands,
+    query: Query<Entity, Or<((With<Camera>, With<LobbyCamera>), With<LobbyScreen>)>>,
+) {
     for entity in &query {
         commands.entity(entity).despawn();
     }
@@ -41,6 +102,7 @@ pub fn display_lobby(
                 align_items: AlignItems::Center,
                 justify_content: JustifyContent::Center,
                 flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(24.0),
                 ..default()
             },
             BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
@@ -48,24 +110,134 @@ pub fn display_lobby(
         ))
         .with_children(|parent| {
             parent.spawn((
-                Text::new("Lobby, press 'P' or connect"),
+                Text::new("Lobby"),
                 TextFont {
                     font_size: 96.0,
                     ..default()
                 },
                 TextColor(Color::srgb(1.0, 0.0, 0.0)), //red
             ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(1.0, 0.85, 0.1)),
+                LobbyCode,
+            ));
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+                LobbyList,
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 32.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                LobbyHint,
+            ));
         });
 }
 
-fn wait_for_input(
+fn update_lobby(
     mut commands: Commands,
+    state: Res<State<GameState>>,
+    roster: Res<LobbyRoster>,
+    list: Query<Entity, With<LobbyList>>,
+    code: Option<Res<JoinCode>>,
+    mut hint: Query<&mut Text, (With<LobbyHint>, Without<LobbyCode>)>,
+    mut code_text: Query<&mut Text, With<LobbyCode>>,
+) {
+    let hosting = *state.get() == GameState::Lobby;
+    if let (Ok(mut text), Some(code), true) = (code_text.single_mut(), code, hosting) {
+        let shown = format!("Join code: {}", code.0);
+        if text.0 != shown {
+            text.0 = shown;
+        }
+    }
+    if let Ok(mut hint) = hint.single_mut() {
+        let text = match (hosting, roster.players.is_empty(), roster.all_ready(), roster.ready) {
+            (true, _, true, _) => "Press 'P' to start",
+            (true, _, false, _) => "Waiting for everyone to ready up",
+            (false, true, _, _) => "Connecting...",
+            (false, false, _, false) => "Press 'R' when you are ready",
+            (false, false, _, true) => "Ready, waiting for the host to start ('R' to cancel)",
+        };
+        if hint.0 != text {
+            hint.0 = text.to_string();
+        }
+    }
+
+    //The list entity is only there from the frame after display_lobby
+    let Ok(list) = list.single() else {
+        return;
+    };
+    if !roster.is_changed() && !roster.is_added() {
+        return;
+    }
+    commands.entity(list).despawn_related::<Children>();
+    commands.entity(list).with_children(|parent| {
+        for player in &roster.players {
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(16.0),
+                    ..default()
+                })
+                .with_children(|row| {
+                    row.spawn((
+                        Node {
+                            width: Val::Px(24.0),
+                            height: Val::Px(24.0),
+                            ..default()
+                        },
+                        BackgroundColor(player_color(player.player_id)),
+                    ));
+                    row.spawn((
+                        Text::new(format!(
+                            "P{}  {}  {} ms  {}",
+                            player.player_id,
+                            player.name,
+                            player.ping_ms,
+                            if player.ready { "READY" } else { "not ready" },
+                        )),
+                        TextFont {
+                            font_size: 32.0,
+                            ..default()
+                        },
+                        TextColor(if player.ready {
+                            Color::srgb(0.4, 1.0, 0.4)
+                        } else {
+                            Color::srgb(0.8, 0.8, 0.8)
+                        }),
+                    ));
+                });
+        }
+    });
+}
+
+//Clients ready up with R, the client plugin tells the host
+fn toggle_ready(input: Res<ButtonInput<KeyCode>>, mut roster: ResMut<LobbyRoster>) {
+    //Nothing to ready up for until the host has let us in
+    if input.just_pressed(KeyCode::KeyR) && !roster.players.is_empty() {
+        roster.ready = !roster.ready;
+    }
+}
+
+fn wait_for_input(
     mut next_state: ResMut<NextState<GameState>>,
     input: Res<ButtonInput<KeyCode>>,
-    lobbyscreen: Single<Entity, With<LobbyScreen>>,
+    roster: Res<LobbyRoster>,
 ) {
-    if input.pressed(KeyCode::KeyP) {
-        commands.entity(*lobbyscreen).despawn();
+    if input.pressed(KeyCode::KeyP) && roster.all_ready() {
         next_state.set(GameState::Playing);
     }
 }
@@ -2,7 +2,6 @@
 #![allow(warnings)]
 
 use crate::pickup_system::PickupPlugin;
-use crate::server::RollbackDetection;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::{
     prelude::*,
@@ -13,9 +12,11 @@ use bevy::{
 
 // Game modules
 mod camera;
+mod chat;
 mod client;
 mod collectible;
 mod components;
+mod config;
 mod enemy;
 mod events;
 mod light_manager;
@@ -23,12 +24,22 @@ mod menu;
 mod pickup_system;
 mod player;
 mod player_material;
+mod prediction;
 mod projectile;
+mod protocol;
+mod replay;
 mod sdf_shadows;
+mod secure;
 mod server;
+mod server_browser;
+mod sim;
+mod snapshot;
 mod tiling;
 mod ui;
+mod interpolation;
 mod inventory_ui;
+mod level;
+mod levelgen;
 mod wall;
 //mod reticle;
 //mod ground_tiles;
@@ -39,8 +50,15 @@ mod collisions;
 mod game_over;
 mod lobby;
 mod net_control;
+mod netsim;
+mod netstats;
+mod net_thread;
+mod net_overlay;
 mod slideshow;
+mod spectator;
 mod deferred_lite_simple;
+mod dedicated;
+mod discovery;
 mod example_scene;
 mod sanity;
 mod winloss;
@@ -55,9 +73,12 @@ pub enum GameState {
     Playing,
     Lobby,
     Joining,
+    ServerBrowser,
     Credits,
     GameOver,
     ExampleScene,
+    //Setting up the world for a replay, see replay.rs
+    Replay,
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -73,6 +94,11 @@ pub struct LogicType {
     pub l_type: AssignedType,
 }
 
+//The host (or a game nobody has joined) decides health, sanity and death, clients are told by it
+pub fn type_is_authority(game_type: Res<LogicType>) -> bool {
+    game_type.l_type != AssignedType::Client
+}
+
 fn setup_cursor_icon(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -90,17 +116,35 @@ fn setup_cursor_icon(
         })));
 }
 
-fn main() {
+fn main() -> AppExit {
+    //Parsed before anything else so bad arguments exit with a message instead of a panic mid game
+    let config = config::Config::from_env();
+
+    //Headless host for build boxes and CI, no window or GPU
+    if config.dedicated {
+        return App::new()
+            .insert_resource(config)
+            .add_plugins(dedicated::DedicatedPlugin)
+            .run();
+    }
+
+    let present_mode = if config.vsync {
+        PresentMode::AutoVsync
+    } else {
+        PresentMode::AutoNoVsync
+    };
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Untitled Survival Shooter".into(),
                 resolution: (WIN_W, WIN_H).into(),
-                present_mode: PresentMode::AutoVsync,
+                present_mode,
                 ..default()
             }),
             ..default()
         }))
+        .insert_resource(config)
         // GameState init
         .init_state::<GameState>()
         //FPS Counter Plugin
@@ -109,11 +153,9 @@ fn main() {
             smoothing_factor: 0.1,
         })
         .add_plugins(LogDiagnosticsPlugin::default())*/
-        //.insert_resource(Time::<Fixed>::from_hz(10.0))
         .insert_resource(LogicType {
             l_type: AssignedType::No,
         })
-        .insert_resource(RollbackDetection::default())
         // Core game systems
         //.add_systems(OnEnter(GameState::Playing), spawn_test_pickup)
         .add_systems(Startup, setup_cursor_icon)
@@ -122,6 +164,7 @@ fn main() {
             player::PlayerPlugin,
             light_manager::LightSourcePlugin,
             menu::MenuPlugin,
+            server_browser::ServerBrowserPlugin,
             tiling::TilingPlugin,
             projectile::ProjectilePlugin,
             enemy::EnemyPlugin,
@@ -139,13 +182,20 @@ fn main() {
             collisions::CollisionsPlugin,
         ))
         .add_plugins((
+            level::LevelPlugin,
             lobby::LobbyPlugin,
             server::ServerPlugin,
             client::ClientPlugin,
+            interpolation::InterpolationPlugin,
+            netsim::NetSimPlugin,
+            netstats::NetStatsPlugin,
+            net_overlay::NetOverlayPlugin,
+            chat::ChatPlugin,
+            spectator::SpectatorPlugin,
             sanity::SanityPlugin,
             winloss::WinLossPlugin,
         ))
-        .add_plugins(example_scene::ExampleScenePlugin)
+        .add_plugins((sim::SimPlugin, replay::ReplayPlugin, example_scene::ExampleScenePlugin))
         .add_event::<events::DamagePlayerEvent>()
-        .run();
+        .run()
 }
@@ -221,10 +221,8 @@ fn start_on_input(
                 }
                 MenuButton::Join => {
                     info!("join button pressed.");
-                    //let socket = UdpSocket::bind("127.0.0.1:24515").expect("couldn't bind to address");
-                    //socket.send_to(&[5; 10], "127.0.0.1:2525").expect("couldn't send data");
-                    next_state.set(GameState::Joining);
-                    game_type.l_type = AssignedType::Client;
+                    //The browser picks the host and switches to Joining
+                    next_state.set(GameState::ServerBrowser);
                 }
                 MenuButton::Credits => {
                     info!("credits button pressed.");
@@ -239,14 +237,14 @@ fn start_on_input(
     }
 }
 
+//Leave a match for the main menu
+pub const QUIT_KEYS: [KeyCode; 3] = [KeyCode::Escape, KeyCode::KeyQ, KeyCode::Backspace];
+
 fn quit_to_menu_on_input(
     keys: Res<ButtonInput<KeyCode>>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if keys.just_pressed(KeyCode::Escape)
-        || keys.just_pressed(KeyCode::KeyQ)
-        || keys.just_pressed(KeyCode::Backspace)
-    {
+    if keys.any_just_pressed(QUIT_KEYS) {
         next_state.set(GameState::Menu);
     }
 }
@@ -1,4 +1,4 @@
-use crate::{GameState, player::Player};
+use crate::{GameState, player::Player, prediction::InputQueue, protocol::PlayerState};
 use bevy::input::mouse::MouseButton;
 use bevy::prelude::*;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
@@ -15,7 +15,8 @@ pub struct NetControl {
     pub p_pos: Vec3,
     pub p_shot: bool,
 
-    pub rollback: bool,
+    //Host only, inputs from this player waiting to be applied one per tick
+    pub inputs: InputQueue,
 
     //Net
     player_addr: Option<SocketAddr>,
@@ -35,7 +36,7 @@ impl NetControl {
             p_pos: Vec3::ZERO,
             p_shot: false,
 
-            rollback: false,
+            inputs: InputQueue::default(),
 
             player_addr: addr,
             player_pos_x: 0,
@@ -53,6 +54,11 @@ impl NetControl {
         return self.player_addr;
     }
 
+    //A player that rejoined after losing the connection comes back from a new address
+    pub fn set_addr(&mut self, addr: SocketAddr) {
+        self.player_addr = Some(addr);
+    }
+
     //Getter for p_pos
     pub fn get_p_pos(&self) -> Vec3 {
         return self.p_pos;
@@ -85,6 +91,24 @@ impl NetControl {
         }
     }
 
+    //Packs the local keyboard into the same WASD00L0 byte remote players send, without the click
+    pub fn keyboard_u8(input: &ButtonInput<KeyCode>) -> u8 {
+        let mut out = 0;
+        if input.pressed(KeyCode::KeyW) {
+            out += 128;
+        }
+        if input.pressed(KeyCode::KeyA) {
+            out += 64;
+        }
+        if input.pressed(KeyCode::KeyS) {
+            out += 32;
+        }
+        if input.pressed(KeyCode::KeyD) {
+            out += 16;
+        }
+        out
+    }
+
     //Functions like MouseButton.pressed, but for the networked user. Only Left Click is currently supported.
     pub fn clicked(&self, input: MouseButton) -> bool {
         match (input) {
@@ -102,8 +126,9 @@ impl NetControl {
 
     //Setter for net_angle
     pub fn set_angle(&mut self, angle: f32) {
-        //This assumes that you've already rounded the float to 1 decimal point
-        let angle_as_i8 = (angle * 10.) as i8;
+        //This assumes that you've already rounded the float to 1 decimal point. Rounded again, a plain
+        //cast would turn 1.3 stored as 12.999 into 1.2.
+        let angle_as_i8 = (angle * 10.).round() as i8;
         self.net_angle = angle_as_i8 as u8;
     }
 
@@ -131,17 +156,6 @@ impl NetControl {
         self.player_pos_y = y_pos as i32;
     }
 
-    //MAY RUN INTO ENDIAN ISSUES BETWEEN DIFFERENT COMPUTERS
-    //Getter for pos_x
-    pub fn get_pos_x(&self) -> [u8; 4] {
-        return self.player_pos_x.to_ne_bytes();
-    }
-
-    //Getter for pos_y
-    pub fn get_pos_y(&self) -> [u8; 4] {
-        return self.player_pos_y.to_ne_bytes();
-    }
-
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -149,17 +163,16 @@ impl NetControl {
     ///                                                                                                      ///
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
-    pub fn get_out_packet(&self, op: u8, pid: u8) -> [u8; 11] {
-        let mut out_pack: [u8; 11] = [0; 11];
-        let out_x = self.get_pos_x();
-        let out_y = self.get_pos_y();
-        out_pack[0] = op;
-        //I'm packing p_shot and rollback into the player_id byte and no one can stop me
-        out_pack[1] = ((self.rollback as u8) << 7) + ((self.net_input & 2) << 5) + pid;
-        out_pack[2..6].copy_from_slice(&out_x);
-        out_pack[6..10].copy_from_slice(&out_y);
-        out_pack[10] = self.net_angle;
-        return out_pack;
+    //Wrap this in Message::PlayerState to send it, see protocol.rs
+    pub fn get_out_state(&self, pid: u8) -> PlayerState {
+        PlayerState {
+            player_id: pid,
+            shot: self.net_input & 2 == 2,
+            ack: self.inputs.processed(),
+            x: self.player_pos_x,
+            y: self.player_pos_y,
+            angle: self.net_angle,
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -169,32 +182,18 @@ impl NetControl {
     ///                                                                                                      ///
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
-    pub fn set_player_state(&mut self, pack: [u8; 11]) {
-        let mut unpack_x: [u8; 4] = [0; 4];
-        let mut unpack_y: [u8; 4] = [0; 4];
-        unpack_x.copy_from_slice(&pack[2..6]);
-        unpack_y.copy_from_slice(&pack[6..10]);
-        let x = i32::from_ne_bytes(unpack_x);
-        let y = i32::from_ne_bytes(unpack_y);
-        self.p_pos = Vec3::new(x as f32, y as f32, 0.);
-        self.p_shot = { pack[1] & 64 == 64 };
-        self.rollback = { pack[1] & 128 == 128 };
-        self.net_angle = pack[10];
+    pub fn set_player_state(&mut self, state: PlayerState) {
+        self.p_pos = Vec3::new(state.x as f32, state.y as f32, 0.);
+        self.p_shot = state.shot;
+        self.net_angle = state.angle;
         //info!("Player {}'s Position: {:?}", self.player_id, self.p_pos);
     }
 
-    //Everything gets set but angle, used for rollback
-    pub fn set_player_state_limited(&mut self, pack: [u8; 11]) {
-        let mut unpack_x: [u8; 4] = [0; 4];
-        let mut unpack_y: [u8; 4] = [0; 4];
-        unpack_x.copy_from_slice(&pack[2..6]);
-        unpack_y.copy_from_slice(&pack[6..10]);
-        let x = i32::from_ne_bytes(unpack_x);
-        let y = i32::from_ne_bytes(unpack_y);
-        self.p_pos = Vec3::new(x as f32, y as f32, 0.);
-        self.p_shot = { pack[1] & 64 == 64 };
-        self.rollback = { pack[1] & 128 == 128 };
-        //self.net_angle = pack[10];
+    //Everything gets set but angle, the local player aims for itself
+    pub fn set_player_state_limited(&mut self, state: PlayerState) {
+        self.p_pos = Vec3::new(state.x as f32, state.y as f32, 0.);
+        self.p_shot = state.shot;
+        //self.net_angle = state.angle;
     }
 
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////
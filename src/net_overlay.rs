@@ -0,0 +1,83 @@
+use crate::netstats::{NetStats, PeerStats, peer_label};
+use bevy::prelude::*;
+
+pub const TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+//Connection stats in the top right corner, one line per peer. F3 shows and hides it in any state.
+pub struct NetOverlayPlugin;
+impl Plugin for NetOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_overlay)
+            .add_systems(Update, (toggle_overlay, update_overlay).chain());
+    }
+}
+
+#[derive(Component)]
+struct NetOverlay;
+
+fn setup_overlay(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(8.0),
+            top: Val::Px(8.0),
+            padding: UiRect::all(Val::Px(6.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        //Drawn over the game HUD and menus
+        GlobalZIndex(10),
+        Visibility::Hidden,
+        NetOverlay,
+    ));
+}
+
+fn toggle_overlay(input: Res<ButtonInput<KeyCode>>, mut overlay: Query<&mut Visibility, With<NetOverlay>>) {
+    if !input.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+    for mut visibility in &mut overlay {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+//The stats only change once a window, but the text is cheap enough to rebuild whenever it is shown
+fn update_overlay(stats: Res<NetStats>, mut overlay: Query<(&mut Text, &Visibility), With<NetOverlay>>) {
+    for (mut text, visibility) in &mut overlay {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+        let mut lines = vec!["Network (F3)".to_string()];
+        if stats.peers.is_empty() {
+            lines.push("not connected".to_string());
+        }
+        lines.extend(stats.peers.iter().map(|(id, peer)| overlay_line(&peer_label(*id), peer)));
+        let joined = lines.join("\n");
+        if text.0 != joined {
+            text.0 = joined;
+        }
+    }
+}
+
+fn overlay_line(label: &str, peer: &PeerStats) -> String {
+    let optional = |value: Option<f32>| value.map_or("-".to_string(), |v| format!("{:.0} ms", v));
+    format!(
+        "{:<9} rtt {:>6}  jitter {:>3.0} ms  loss {:>5.1}%  in {:>5.1} KB/s  out {:>5.1} KB/s  snapshot {:>6}",
+        label,
+        optional(peer.rtt_ms),
+        peer.jitter_ms,
+        peer.loss * 100.,
+        peer.kb_in,
+        peer.kb_out,
+        optional(peer.snapshot_age_ms),
+    )
+}
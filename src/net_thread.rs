@@ -0,0 +1,223 @@
+use crate::netsim::{NetConditions, NetSocket};
+use crate::netstats::Traffic;
+use crate::protocol::{MAX_DATAGRAM, Message};
+use crate::secure::Dropped;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Network thread. The host and the client each hand their socket to one of these, which reads     ///
+///   every datagram the moment it arrives, stamps it, opens it and queues the message for the ECS.   ///
+///   Messages the ECS sends are queued the other way and sealed and sent from here. The systems only ///
+///   drain the queues, so a long frame no longer leaves datagrams sitting in the socket.             ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//How long the thread waits for something to send before it checks the socket again. This is also
+//how often held back datagrams in the network simulator get moved along.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+//The host's or client's half of secure.rs. The ECS keeps its own handle to it for the handshake.
+pub trait Sealer: Send + 'static {
+    fn open(&mut self, datagram: &[u8], from: SocketAddr) -> Result<Message, Dropped>;
+    //None when there is no session to seal it with, nobody could read it
+    fn seal(&mut self, msg: &Message, to: SocketAddr) -> Option<Vec<u8>>;
+}
+
+//A datagram as it came off the socket, opened or with the reason it was dropped
+pub struct Received {
+    pub from: SocketAddr,
+    pub at: Instant,
+    pub msg: Result<Message, Dropped>,
+}
+
+impl Received {
+    //When it arrived on the Time<Real> clock, however late in the frame it is handled
+    pub fn arrived(&self, real_time: &Time<Real>) -> Duration {
+        self.at.saturating_duration_since(real_time.startup())
+    }
+}
+
+struct Outgoing {
+    msg: Message,
+    to: SocketAddr,
+}
+
+pub struct NetThread {
+    //Shared for the simulator settings and traffic counts, only the thread reads and writes with it
+    socket: Arc<NetSocket>,
+    outgoing: Option<Sender<Outgoing>>,
+    incoming: Mutex<Receiver<Received>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NetThread {
+    pub fn spawn<S: Sealer>(name: &str, socket: NetSocket, sealer: Arc<Mutex<S>>) -> io::Result<Self> {
+        //The thread goes around checking for sends, so it can't sit in recv_from
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(socket);
+        let (outgoing, to_send) = mpsc::channel();
+        let (received, incoming) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn({
+                let socket = socket.clone();
+                move || run(&socket, &*sealer, &to_send, &received)
+            })?;
+        Ok(Self { socket, outgoing: Some(outgoing), incoming: Mutex::new(incoming), thread: Some(thread) })
+    }
+
+    pub fn send(&self, msg: &Message, to: SocketAddr) {
+        if let Some(outgoing) = &self.outgoing {
+            //Only fails once the thread has stopped, and then there is nothing left to send with
+            let _ = outgoing.send(Outgoing { msg: msg.clone(), to });
+        }
+    }
+
+    //The next message that came in, oldest first
+    pub fn try_recv(&self) -> Option<Received> {
+        self.incoming.lock().unwrap().try_recv().ok()
+    }
+
+    pub fn set_conditions(&self, conditions: Option<NetConditions>) {
+        self.socket.set_conditions(conditions);
+    }
+
+    pub fn take_traffic(&self) -> HashMap<SocketAddr, Traffic> {
+        self.socket.take_traffic()
+    }
+
+    #[cfg(test)]
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+//Closing the queue stops the thread once it has sent what is left, so a Leave sent on the way out
+//still goes and the port is free again by the time this returns
+impl Drop for NetThread {
+    fn drop(&mut self) {
+        self.outgoing.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run<S: Sealer>(socket: &NetSocket, sealer: &Mutex<S>, to_send: &Receiver<Outgoing>, received: &Sender<Received>) {
+    let mut buf = [0; MAX_DATAGRAM];
+    loop {
+        //Everything that came in, straight away
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    let at = Instant::now();
+                    let msg = sealer.lock().unwrap().open(&buf[..len], from);
+                    if received.send(Received { from, at, msg }).is_err() {
+                        return;
+                    }
+                }
+                //Nothing left. Windows also reports an earlier send to a closed port here, whatever is
+                //behind it gets read next time around.
+                Err(_) => break,
+            }
+        }
+
+        //Then whatever the ECS queued, waiting a little for more when there was nothing
+        let mut next = match to_send.recv_timeout(POLL_INTERVAL) {
+            Ok(out) => Some(out),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        while let Some(out) = next {
+            let bytes = sealer.lock().unwrap().seal(&out.msg, out.to);
+            //A failed send is just a lost packet, the timeouts deal with peers that are really gone
+            if let Some(Err(e)) = bytes.map(|bytes| socket.send_to(&bytes, out.to)) {
+                warn!("Could not send to {}: {}", out.to, e);
+            }
+            next = match to_send.try_recv() {
+                Ok(out) => Some(out),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolError;
+    use std::net::UdpSocket;
+    use std::thread::sleep;
+
+    //Plain datagrams, the framing is all that is checked
+    struct Plain;
+    impl Sealer for Plain {
+        fn open(&mut self, datagram: &[u8], _: SocketAddr) -> Result<Message, Dropped> {
+            Message::from_bytes(datagram).map_err(Dropped::Malformed)
+        }
+        fn seal(&mut self, msg: &Message, _: SocketAddr) -> Option<Vec<u8>> {
+            (*msg != Message::Leave { player_id: 9 }).then(|| msg.to_bytes())
+        }
+    }
+
+    fn spawn() -> NetThread {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        NetThread::spawn("test network", NetSocket::new(socket, None), Arc::new(Mutex::new(Plain))).unwrap()
+    }
+
+    fn wait_for(thread: &NetThread) -> Option<Received> {
+        let end = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < end {
+            if let Some(received) = thread.try_recv() {
+                return Some(received);
+            }
+            sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn messages_cross_between_threads() {
+        let (a, b) = (spawn(), spawn());
+        let before = Instant::now();
+        a.send(&Message::Ping { time_ms: 7 }, b.local_addr().unwrap());
+        let received = wait_for(&b).unwrap();
+        assert_eq!(received.msg, Ok(Message::Ping { time_ms: 7 }));
+        assert_eq!(received.from, a.local_addr().unwrap());
+        assert!(received.at >= before);
+    }
+
+    #[test]
+    fn unsealed_and_malformed_datagrams() {
+        let (a, b) = (spawn(), spawn());
+        a.send(&Message::Leave { player_id: 9 }, b.local_addr().unwrap());
+        assert!(wait_for(&b).is_none());
+
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.send_to(b"nonsense", b.local_addr().unwrap()).unwrap();
+        assert!(matches!(wait_for(&b).unwrap().msg, Err(Dropped::Malformed(ProtocolError::BadMagic))));
+    }
+
+    #[test]
+    fn queued_sends_go_out_before_the_thread_stops() {
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let a = spawn();
+        for _ in 0..5 {
+            a.send(&Message::Heartbeat, raw.local_addr().unwrap());
+        }
+        drop(a);
+        let mut buf = [0; 64];
+        for _ in 0..5 {
+            let (len, _) = raw.recv_from(&mut buf).unwrap();
+            assert_eq!(Message::from_bytes(&buf[..len]), Ok(Message::Heartbeat));
+        }
+    }
+}
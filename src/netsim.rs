@@ -0,0 +1,426 @@
+use crate::config::Config;
+use crate::netstats::Traffic;
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Network condition simulator. Both the host and client sockets go through NetSocket, which can    ///
+///   hold datagrams back, drop, duplicate and reorder them in both directions. Conditions come from   ///
+///   the config and can be toggled in game with F8. With nothing set it is a plain UdpSocket.         ///
+///   It also counts what goes to and comes from each address for the connection stats.              ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Extra hold on a reordered datagram so the ones sent after it overtake it
+const REORDER_DELAY: Duration = Duration::from_millis(40);
+pub const TOGGLE_KEY: KeyCode = KeyCode::F8;
+
+//Applied to each direction separately, so round trip latency is twice latency_ms
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetConditions {
+    pub latency_ms: u32,
+    //Each datagram gets up to this much added or taken off its latency
+    pub jitter_ms: u32,
+    //Percentages, 0 to 100
+    pub loss: f32,
+    pub duplicate: f32,
+    pub reorder: f32,
+}
+
+impl NetConditions {
+    //What F8 turns on when the config doesn't set anything
+    pub const BAD: NetConditions = NetConditions {
+        latency_ms: 100,
+        jitter_ms: 30,
+        loss: 5.,
+        duplicate: 1.,
+        reorder: 2.,
+    };
+
+    pub fn is_perfect(&self) -> bool {
+        *self == NetConditions::default()
+    }
+}
+
+pub struct NetSimPlugin;
+impl Plugin for NetSimPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetSimulator>()
+            .add_systems(Startup, init_simulator)
+            .add_systems(Update, toggle_simulator);
+    }
+}
+
+//What the sockets should be doing right now. The server and client copy it onto their socket when
+//it changes.
+#[derive(Resource, Default)]
+pub struct NetSimulator {
+    pub conditions: NetConditions,
+    pub enabled: bool,
+}
+
+impl NetSimulator {
+    pub fn active(&self) -> Option<NetConditions> {
+        self.enabled.then_some(self.conditions)
+    }
+}
+
+fn init_simulator(config: Res<Config>, mut sim: ResMut<NetSimulator>) {
+    sim.conditions = config.net_sim;
+    sim.enabled = !config.net_sim.is_perfect();
+    if sim.enabled {
+        info!("Simulating network conditions: {:?}", sim.conditions);
+    }
+}
+
+fn toggle_simulator(input: Res<ButtonInput<KeyCode>>, mut sim: ResMut<NetSimulator>) {
+    if !input.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+    sim.enabled = !sim.enabled;
+    if sim.enabled && sim.conditions.is_perfect() {
+        sim.conditions = NetConditions::BAD;
+    }
+    info!("Network simulator {}: {:?}", if sim.enabled { "on" } else { "off" }, sim.conditions);
+}
+
+struct Delayed {
+    due: Instant,
+    addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+struct Sim {
+    conditions: Option<NetConditions>,
+    rng: StdRng,
+    outgoing: Vec<Delayed>,
+    incoming: Vec<Delayed>,
+    //What went through send_to and came out of recv_from since the last take_traffic
+    traffic: HashMap<SocketAddr, Traffic>,
+}
+
+impl Sim {
+    //Queues zero, one or two copies of a datagram depending on loss and duplication
+    fn schedule(&mut self, queue_outgoing: bool, addr: SocketAddr, data: &[u8], now: Instant) {
+        let Some(c) = self.conditions else {
+            return;
+        };
+        let copies = if self.rng.random_range(0.0..100.0) < c.duplicate { 2 } else { 1 };
+        for _ in 0..copies {
+            if self.rng.random_range(0.0..100.0) < c.loss {
+                continue;
+            }
+            let jitter = self.rng.random_range(-(c.jitter_ms as i64)..=c.jitter_ms as i64);
+            let mut delay = Duration::from_millis((c.latency_ms as i64 + jitter).max(0) as u64);
+            if self.rng.random_range(0.0..100.0) < c.reorder {
+                delay += REORDER_DELAY;
+            }
+            let delayed = Delayed { due: now + delay, addr, data: data.to_vec() };
+            if queue_outgoing {
+                self.outgoing.push(delayed);
+            } else {
+                self.incoming.push(delayed);
+            }
+        }
+    }
+
+    //Earliest datagram that is due, if any
+    fn pop_due(queue: &mut Vec<Delayed>, now: Instant) -> Option<Delayed> {
+        let (i, _) = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.due <= now)
+            .min_by_key(|(_, d)| d.due)?;
+        Some(queue.remove(i))
+    }
+}
+
+//UdpSocket with optional simulated conditions. The network thread polls its socket constantly, and those polls
+//are what move held back datagrams along, so the socket has to be non-blocking.
+pub struct NetSocket {
+    socket: UdpSocket,
+    sim: Mutex<Sim>,
+}
+
+impl NetSocket {
+    pub fn new(socket: UdpSocket, conditions: Option<NetConditions>) -> Self {
+        Self::with_rng(socket, conditions, StdRng::from_os_rng())
+    }
+
+    //Same dice every run, for tests
+    pub fn seeded(socket: UdpSocket, conditions: Option<NetConditions>, seed: u64) -> Self {
+        Self::with_rng(socket, conditions, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(socket: UdpSocket, conditions: Option<NetConditions>, rng: StdRng) -> Self {
+        let conditions = conditions.filter(|c| !c.is_perfect());
+        Self {
+            socket,
+            sim: Mutex::new(Sim {
+                conditions,
+                rng,
+                outgoing: Vec::new(),
+                incoming: Vec::new(),
+                traffic: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: Option<NetConditions>) {
+        self.sim.lock().unwrap().conditions = conditions.filter(|c| !c.is_perfect());
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    //Bytes and datagrams per address since the last call
+    pub fn take_traffic(&self) -> HashMap<SocketAddr, Traffic> {
+        std::mem::take(&mut self.sim.lock().unwrap().traffic)
+    }
+
+    pub fn send_to(&self, data: &[u8], addr: impl ToSocketAddrs) -> io::Result<usize> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let mut sim = self.sim.lock().unwrap();
+        let traffic = sim.traffic.entry(addr).or_default();
+        traffic.bytes_out += data.len() as u64;
+        traffic.packets_out += 1;
+        if sim.conditions.is_none() {
+            return self.socket.send_to(data, addr);
+        }
+        let now = Instant::now();
+        sim.schedule(true, addr, data, now);
+        self.flush(&mut sim, now);
+        Ok(data.len())
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut sim = self.sim.lock().unwrap();
+        let (len, from) = self.receive(&mut sim, buf)?;
+        let traffic = sim.traffic.entry(from).or_default();
+        traffic.bytes_in += len as u64;
+        traffic.packets_in += 1;
+        Ok((len, from))
+    }
+
+    fn receive(&self, sim: &mut Sim, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let now = Instant::now();
+        self.flush(sim, now);
+
+        //Datagrams still held from before the simulator was turned off are let through first
+        if sim.conditions.is_some() {
+            let mut scratch = [0; 2048];
+            loop {
+                match self.socket.recv_from(&mut scratch) {
+                    Ok((len, from)) => sim.schedule(false, from, &scratch[..len], now),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if let Some(delayed) = Sim::pop_due(&mut sim.incoming, now) {
+            let len = delayed.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&delayed.data[..len]);
+            return Ok((len, delayed.addr));
+        }
+        if sim.conditions.is_some() || !sim.incoming.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.socket.recv_from(buf)
+    }
+
+    fn flush(&self, sim: &mut Sim, now: Instant) {
+        //Everything goes once the simulator is off, there is nothing left to wait for
+        let now = if sim.conditions.is_none() { now + Duration::from_secs(3600) } else { now };
+        while let Some(delayed) = Sim::pop_due(&mut sim.outgoing, now) {
+            //A lost send is just another dropped packet
+            let _ = self.socket.send_to(&delayed.data, delayed.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::MapBounds;
+    use crate::player::movement_step;
+    use crate::prediction::{InputQueue, MoveState, Prediction};
+    use crate::protocol::{MAX_INPUTS_PER_PACKET, Message, PlayerState};
+    use crate::sim::TICK_SECS;
+    use bevy::math::bounding::Aabb2d;
+    use std::thread::sleep;
+
+    fn pair(conditions: NetConditions, seed: u64) -> (NetSocket, NetSocket) {
+        let bind = || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_nonblocking(true).unwrap();
+            socket
+        };
+        //Both ends get their own fixed seed, so conditions set on the second one later are the same
+        //dice every run too
+        (NetSocket::seeded(bind(), Some(conditions), seed), NetSocket::seeded(bind(), None, seed + 1))
+    }
+
+    //Everything that arrives within the time limit
+    fn drain(socket: &NetSocket, wait: Duration) -> Vec<Vec<u8>> {
+        let end = Instant::now() + wait;
+        let mut out = Vec::new();
+        let mut buf = [0; 64];
+        while Instant::now() < end {
+            match socket.recv_from(&mut buf) {
+                Ok((len, _)) => out.push(buf[..len].to_vec()),
+                Err(_) => sleep(Duration::from_millis(1)),
+            }
+        }
+        out
+    }
+
+    fn send_numbers(from: &NetSocket, to: &NetSocket, count: u8) {
+        let addr = to.local_addr().unwrap();
+        for i in 0..count {
+            from.send_to(&[i], addr).unwrap();
+        }
+    }
+
+    #[test]
+    fn perfect_conditions_pass_straight_through() {
+        let (a, b) = pair(NetConditions::default(), 1);
+        send_numbers(&a, &b, 10);
+        let got = drain(&b, Duration::from_millis(50));
+        assert_eq!(got, (0..10).map(|i| vec![i]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn latency_holds_datagrams_back() {
+        let (a, b) = pair(NetConditions { latency_ms: 60, ..default() }, 1);
+        send_numbers(&a, &b, 3);
+        assert!(drain(&b, Duration::from_millis(20)).is_empty());
+        //Only the sender polls its outgoing queue
+        assert!(drain(&a, Duration::from_millis(60)).is_empty());
+        assert_eq!(drain(&b, Duration::from_millis(20)).len(), 3);
+    }
+
+    #[test]
+    fn loss_and_duplication() {
+        let (a, b) = pair(NetConditions { loss: 100., ..default() }, 1);
+        send_numbers(&a, &b, 20);
+        assert!(drain(&b, Duration::from_millis(30)).is_empty());
+
+        let (a, b) = pair(NetConditions { duplicate: 100., ..default() }, 1);
+        send_numbers(&a, &b, 5);
+        assert_eq!(drain(&b, Duration::from_millis(30)).len(), 10);
+    }
+
+    #[test]
+    fn reordering() {
+        let (a, b) = pair(NetConditions { reorder: 50., ..default() }, 7);
+        send_numbers(&a, &b, 20);
+        //Reordered datagrams sit in a's queue until a is polled again
+        let mut got = drain(&b, Duration::from_millis(5));
+        drain(&a, REORDER_DELAY + Duration::from_millis(10));
+        got.extend(drain(&b, Duration::from_millis(10)));
+        assert_eq!(got.len(), 20);
+        assert!(got.windows(2).any(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn traffic_is_counted_per_address() {
+        let (a, b) = pair(NetConditions::default(), 1);
+        send_numbers(&a, &b, 3);
+        assert_eq!(drain(&b, Duration::from_millis(20)).len(), 3);
+        let sent = a.take_traffic();
+        assert_eq!(sent[&b.local_addr().unwrap()], Traffic { bytes_out: 3, packets_out: 3, ..default() });
+        assert!(a.take_traffic().is_empty());
+        let received = b.take_traffic();
+        assert_eq!(received[&a.local_addr().unwrap()], Traffic { bytes_in: 3, packets_in: 3, ..default() });
+    }
+
+    #[test]
+    fn incoming_conditions_apply_on_the_receiving_side() {
+        let (a, b) = pair(NetConditions { loss: 100., ..default() }, 1);
+        send_numbers(&b, &a, 5);
+        assert!(drain(&a, Duration::from_millis(30)).is_empty());
+    }
+
+    //Client prediction against a host over a lossy, jittery, reordering link. The movement step is
+    //deterministic and redundant inputs cover the losses, so the prediction should never be wrong.
+    #[test]
+    fn prediction_survives_a_bad_network() {
+        let bad = NetConditions { latency_ms: 3, jitter_ms: 2, loss: 10., duplicate: 10., reorder: 10. };
+        let (client, host) = pair(bad, 42);
+        host.set_conditions(Some(bad));
+        let (client_addr, host_addr) = (client.local_addr().unwrap(), host.local_addr().unwrap());
+
+        let collider = Aabb2d { min: Vec2::ZERO, max: Vec2::splat(64.) };
+        let map = MapBounds { width: 10_000., height: 10_000. };
+        let step = |state: MoveState, input: u8| movement_step(state, input, TICK_SECS, &collider, &[], &map);
+
+        let mut prediction = Prediction::default();
+        let mut predicted = MoveState::default();
+        let mut queue = InputQueue::default();
+        let mut authoritative = MoveState::default();
+        let mut corrections = 0;
+        let mut buf = [0; 256];
+
+        //W for a while, then D, then nothing so the player comes to a stop. Like the game, an input
+        //goes out every tick even when nothing is pressed.
+        let ticks = 250;
+        for tick in 0..ticks {
+            let input = if tick < 60 { 128 } else if tick < 110 { 16 } else { 0 };
+            let seq = prediction.next_seq();
+            predicted = step(predicted, input);
+            prediction.record(seq, input, predicted);
+            let msg = Message::Input { angle: 0, seq, inputs: prediction.recent_inputs(MAX_INPUTS_PER_PACKET) };
+            client.send_to(&msg.to_bytes(), host_addr).unwrap();
+
+            while let Ok((len, _)) = host.recv_from(&mut buf) {
+                if let Ok(Message::Input { seq, inputs, .. }) = Message::from_bytes(&buf[..len]) {
+                    queue.receive(seq, &inputs);
+                }
+            }
+            for input in queue.take_tick() {
+                authoritative = step(authoritative, input);
+            }
+            let state = PlayerState {
+                player_id: 0,
+                shot: false,
+                ack: queue.processed(),
+                x: authoritative.pos.x as i32,
+                y: authoritative.pos.y as i32,
+                angle: 0,
+            };
+            host.send_to(&Message::PlayerState(state).to_bytes(), client_addr).unwrap();
+
+            while let Ok((len, _)) = client.recv_from(&mut buf) {
+                if let Ok(Message::PlayerState(state)) = Message::from_bytes(&buf[..len]) {
+                    let pos = Vec2::new(state.x as f32, state.y as f32);
+                    if let Some(corrected) = prediction.reconcile(state.ack, pos, step) {
+                        predicted = corrected;
+                        corrections += 1;
+                    }
+                }
+            }
+            sleep(Duration::from_millis(4));
+        }
+
+        assert_eq!(corrections, 0);
+        //The last few can still be in flight
+        assert!(queue.processed() > ticks - 20);
+        assert_eq!(authoritative.pos, predicted.pos);
+        assert!(predicted.pos.x > 0. && predicted.pos.y > 0.);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::config::Config;
+use crate::protocol::HOST_ID;
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Connection statistics. The server and client feed in round trip samples, sequence numbers and    ///
+///   the bytes NetSocket counted for each peer. Once a second the totals become rates, which the F3   ///
+///   overlay shows and --net-stats appends to a CSV file.                                             ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Rates are worked out over this many seconds
+pub const STATS_WINDOW: f32 = 1.0;
+pub const CSV_HEADER: &str = "time_s,player,rtt_ms,jitter_ms,loss_pct,kb_in_per_s,kb_out_per_s,snapshot_age_ms";
+
+//Bytes and datagrams to and from one address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u32,
+    pub packets_out: u32,
+}
+
+impl Traffic {
+    pub fn add(&mut self, other: Traffic) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.packets_in += other.packets_in;
+        self.packets_out += other.packets_out;
+    }
+}
+
+//One peer as seen from this machine. On the host that is each client, on a client it is the host.
+#[derive(Debug, Clone, Default)]
+pub struct PeerStats {
+    //Smoothed round trip and how much it varies between samples
+    pub rtt_ms: Option<f32>,
+    pub jitter_ms: f32,
+    //Fraction of the last window's datagrams that never arrived
+    pub loss: f32,
+    //Kilobytes per second over the last window
+    pub kb_in: f32,
+    pub kb_out: f32,
+    //How old the newest complete world state was at the end of the last window
+    pub snapshot_age_ms: Option<f32>,
+
+    traffic: Traffic,
+    last_rtt: Option<f32>,
+    last_seq: Option<u16>,
+    expected: u32,
+    received: u32,
+    last_snapshot: Option<f64>,
+}
+
+impl PeerStats {
+    pub fn record_rtt(&mut self, rtt_ms: f32) {
+        //Same smoothing as TCP for the round trip, RFC 3550's estimator for the jitter
+        if let Some(last) = self.last_rtt {
+            self.jitter_ms += ((rtt_ms - last).abs() - self.jitter_ms) / 16.;
+        }
+        self.last_rtt = Some(rtt_ms);
+        self.rtt_ms = Some(match self.rtt_ms {
+            Some(rtt) => rtt + (rtt_ms - rtt) / 8.,
+            None => rtt_ms,
+        });
+    }
+
+    //Sequence numbers of a stream sent once a tick. Gaps count as lost, late and repeated ones are ignored.
+    pub fn record_seq(&mut self, seq: u16) {
+        let gap = match self.last_seq {
+            Some(last) => seq.wrapping_sub(last) as i16,
+            None => 1,
+        };
+        if gap <= 0 {
+            return;
+        }
+        self.last_seq = Some(seq);
+        self.expected += gap as u32;
+        self.received += 1;
+    }
+
+    //A complete world state arrived (client) or was acknowledged (host), now is Time<Real> in seconds
+    pub fn record_snapshot(&mut self, now: f64) {
+        self.last_snapshot = Some(now);
+    }
+
+    pub fn add_traffic(&mut self, traffic: Traffic) {
+        self.traffic.add(traffic);
+    }
+
+    //Turns what was counted over the last window into rates and starts counting again
+    pub fn roll(&mut self, window_secs: f32, now: f64) {
+        self.kb_in = self.traffic.bytes_in as f32 / 1024. / window_secs;
+        self.kb_out = self.traffic.bytes_out as f32 / 1024. / window_secs;
+        self.traffic = Traffic::default();
+
+        if self.expected > 0 {
+            self.loss = 1. - self.received as f32 / self.expected as f32;
+        } else if self.last_seq.is_some() {
+            //Heard from before, but nothing at all this window
+            self.loss = 1.;
+        }
+        self.expected = 0;
+        self.received = 0;
+
+        self.snapshot_age_ms = self.last_snapshot.map(|at| ((now - at) * 1000.) as f32);
+    }
+
+    pub fn csv_row(&self, time: f64, player: &str) -> String {
+        let optional = |value: Option<f32>| value.map_or(String::new(), |v| format!("{:.1}", v));
+        format!(
+            "{:.1},{},{},{:.1},{:.1},{:.2},{:.2},{}",
+            time,
+            player,
+            optional(self.rtt_ms),
+            self.jitter_ms,
+            self.loss * 100.,
+            self.kb_in,
+            self.kb_out,
+            optional(self.snapshot_age_ms),
+        )
+    }
+}
+
+//Stats for every peer by player id, the host is HOST_ID on a client
+#[derive(Resource, Default)]
+pub struct NetStats {
+    pub peers: BTreeMap<u8, PeerStats>,
+}
+
+impl NetStats {
+    pub fn peer(&mut self, player_id: u8) -> &mut PeerStats {
+        self.peers.entry(player_id).or_default()
+    }
+
+    pub fn roll(&mut self, window_secs: f32, now: f64) {
+        for peer in self.peers.values_mut() {
+            peer.roll(window_secs, now);
+        }
+    }
+}
+
+//How peers are named in the overlay and the CSV
+pub fn peer_label(player_id: u8) -> String {
+    if player_id == HOST_ID {
+        "host".to_string()
+    } else {
+        format!("player {}", player_id)
+    }
+}
+
+pub struct NetStatsPlugin;
+impl Plugin for NetStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetStats>()
+            .add_systems(Startup, open_stats_log)
+            .add_systems(Update, roll_stats);
+    }
+}
+
+#[derive(Resource)]
+struct StatsLog {
+    timer: Timer,
+    //Only there with --net-stats
+    file: Option<BufWriter<File>>,
+}
+
+fn open_stats_log(mut commands: Commands, config: Res<Config>) {
+    let file = config.net_stats_csv.as_ref().and_then(|path| {
+        match File::create(path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            writeln!(file, "{}", CSV_HEADER)?;
+            Ok(file)
+        }) {
+            Ok(file) => {
+                info!("Writing network stats to {}", path.display());
+                Some(file)
+            }
+            Err(e) => {
+                warn!("Could not write network stats to {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+    commands.insert_resource(StatsLog {
+        timer: Timer::from_seconds(STATS_WINDOW, TimerMode::Repeating),
+        file,
+    });
+}
+
+fn roll_stats(time: Res<Time<Real>>, mut log: ResMut<StatsLog>, mut stats: ResMut<NetStats>) {
+    if !log.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+    stats.roll(STATS_WINDOW, now);
+    let Some(file) = log.file.as_mut() else {
+        return;
+    };
+    let mut result = Ok(());
+    for (player_id, peer) in &stats.peers {
+        result = result.and_then(|_| writeln!(file, "{}", peer.csv_row(now, &peer_label(*player_id))));
+    }
+    //Flushed every window so the file is usable while the game is still running
+    if let Err(e) = result.and_then(|_| file.flush()) {
+        warn!("Stopped writing network stats: {}", e);
+        log.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_is_smoothed_and_jitter_follows_the_changes() {
+        let mut peer = PeerStats::default();
+        peer.record_rtt(100.);
+        assert_eq!(peer.rtt_ms, Some(100.));
+        assert_eq!(peer.jitter_ms, 0.);
+        peer.record_rtt(116.);
+        assert_eq!(peer.rtt_ms, Some(102.));
+        assert_eq!(peer.jitter_ms, 1.);
+        //A steady round trip lets the jitter settle back down
+        for _ in 0..100 {
+            peer.record_rtt(116.);
+        }
+        assert!(peer.jitter_ms < 0.01);
+        assert!((peer.rtt_ms.unwrap() - 116.).abs() < 0.01);
+    }
+
+    #[test]
+    fn gaps_count_as_loss() {
+        let mut peer = PeerStats::default();
+        for seq in [u16::MAX - 1, u16::MAX, 2, 3] {
+            peer.record_seq(seq);
+        }
+        //Late and repeated numbers don't change anything
+        peer.record_seq(1);
+        peer.record_seq(3);
+        peer.roll(1., 0.);
+        //Expected MAX-1 through 3, six numbers, and got four of them
+        assert!((peer.loss - 2. / 6.).abs() < 1e-6);
+
+        peer.record_seq(4);
+        peer.roll(1., 0.);
+        assert_eq!(peer.loss, 0.);
+        //Silence after hearing from them is total loss
+        peer.roll(1., 0.);
+        assert_eq!(peer.loss, 1.);
+    }
+
+    #[test]
+    fn roll_turns_bytes_into_rates() {
+        let mut stats = NetStats::default();
+        stats.peer(1).add_traffic(Traffic { bytes_in: 2048, bytes_out: 512, packets_in: 4, packets_out: 1 });
+        stats.peer(1).add_traffic(Traffic { bytes_in: 2048, ..default() });
+        stats.peer(1).record_snapshot(9.75);
+        stats.roll(2., 10.);
+        let peer = &stats.peers[&1];
+        assert_eq!((peer.kb_in, peer.kb_out), (2., 0.25));
+        assert_eq!(peer.snapshot_age_ms, Some(250.));
+
+        stats.roll(2., 11.);
+        assert_eq!(stats.peers[&1].kb_in, 0.);
+    }
+
+    #[test]
+    fn csv_rows_match_the_header() {
+        let mut peer = PeerStats::default();
+        assert_eq!(peer.csv_row(1., "host"), "1.0,host,,0.0,0.0,0.00,0.00,");
+        peer.record_rtt(42.);
+        peer.record_snapshot(0.5);
+        peer.roll(1., 1.);
+        let row = peer.csv_row(1., "player 2");
+        assert_eq!(row, "1.0,player 2,42.0,0.0,0.0,0.00,0.00,500.0");
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+    }
+}
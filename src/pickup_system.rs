@@ -3,10 +3,10 @@ use crate::collectible::{
     pickup_flashlight,
 };
 use crate::components::{
-    Collectible as OldCollectible, CollectibleKind as OldCollectibleKind, Health,
+    Collectible as OldCollectible, CollectibleKind as OldCollectibleKind, Health, NetId,
 };
-use crate::net_control::PlayerType;
-use crate::net_control::NetControl;
+use crate::{GameState, type_is_authority};
+use crate::sim::SimSet;
 use crate::player_material::PlayerBaseMaterial;
 use crate::light_manager::Lights;
 use crate::player::Player;
@@ -15,6 +15,9 @@ use bevy::prelude::*;
 /// how close to pick up
 const PICKUP_RADIUS: f32 = 32.0;
 
+/// the test items spawned with each match aren't on the map, they take ids down from the top of the range
+const STARTUP_ITEM_ID: u16 = u16::MAX;
+
 /// collecting ammo
 #[derive(Event, Debug, Clone, Copy)]
 pub struct AmmoPickupEvent {
@@ -24,6 +27,7 @@ pub struct AmmoPickupEvent {
 /// collecting battery
 #[derive(Event, Debug, Clone, Copy)]
 pub struct BatteryPickupEvent {
+    pub player: Entity,
     pub amount: i32,
 }
 
@@ -31,6 +35,12 @@ pub struct BatteryPickupEvent {
 #[derive(Event, Debug, Clone, Copy)]
 pub struct ReviveKitPickupEvent;
 
+/// collectible gone for good, the server passes its id on to clients
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollectibleTakenEvent {
+    pub collectible_id: u16,
+}
+
 /// Plugin
 pub struct PickupPlugin;
 
@@ -39,10 +49,16 @@ impl Plugin for PickupPlugin {
         app.add_event::<AmmoPickupEvent>()
             .add_event::<BatteryPickupEvent>()
             .add_event::<ReviveKitPickupEvent>()
-            .add_systems(Startup, spawn_revive_kit)
-            .add_systems(Startup, spawn_battery)
-            .add_systems(Update, battery_pickup_system)
-            .add_systems(Update, (pickup_system, attach_flashlight_to_player,));
+            .add_event::<CollectibleTakenEvent>()
+            //Every match starts with the same items, clear_world takes away what is left of them
+            .add_systems(OnEnter(GameState::Playing), (spawn_revive_kit, spawn_battery))
+            //Clients are told what was taken instead of picking things up themselves, and charge is
+            //replicated, so they only show what the host says
+            .add_systems(
+                FixedUpdate,
+                (pickup_system, battery_pickup_system).chain().in_set(SimSet::Combat).run_if(type_is_authority),
+            )
+            .add_systems(Update, attach_flashlight_to_player);
     }
 }
 
@@ -51,12 +67,14 @@ fn spawn_revive_kit(mut commands: Commands, asset_server: Res<AssetServer>) {
         Sprite::from_image(asset_server.load("revive kit/Revive Kit_albedo.png")),
         Transform::from_xyz(200.0, 150.0, 0.0).with_scale(Vec3::new(0.5, 0.5, 0.5)),
         OldCollectible::revive(),
+        NetId(STARTUP_ITEM_ID),
     ));
     //spawn a second revive kit
     commands.spawn((
         Sprite::from_image(asset_server.load("revive kit/Revive Kit_albedo.png")),
         Transform::from_xyz(-200.0, -150.0, 0.0).with_scale(Vec3::new(0.5, 0.5, 0.5)),
         OldCollectible::revive(),
+        NetId(STARTUP_ITEM_ID - 1),
     ));
 }
 
@@ -84,6 +102,7 @@ fn spawn_battery(mut commands: Commands,
         })),
         Transform::from_xyz(100., 0., 10.).with_scale(Vec3::splat(64.)),
         OldCollectible::battery(500),
+        NetId(STARTUP_ITEM_ID - 2),
     ));
 
     commands.spawn((
@@ -104,6 +123,7 @@ fn spawn_battery(mut commands: Commands,
         })),
         Transform::from_xyz(400., 0., 10.).with_scale(Vec3::splat(64.)),
         OldCollectible::battery(150),
+        NetId(STARTUP_ITEM_ID - 3),
     ));
 }
 
@@ -113,25 +133,31 @@ fn pickup_system(
     mut ammo_writer: EventWriter<AmmoPickupEvent>,
     mut battery_writer: EventWriter<BatteryPickupEvent>,
     mut revive_writer: EventWriter<ReviveKitPickupEvent>,
+    mut taken_writer: EventWriter<CollectibleTakenEvent>,
     // For each player: transform, optional health, player component, and inventory (mut)
     mut player_q: Query<
         (Entity, &Transform, Option<Mut<Health>>, &mut Player, &mut PlayerInventory),
         (With<Player>, Without<crate::components::Dead>)
     >,
     // Old collectibles from components.rs
-    old_collectibles_q: Query<(Entity, &Transform, &OldCollectible)>,
+    old_collectibles_q: Query<(Entity, &Transform, &OldCollectible, &NetId)>,
     // New collectibles from collectible.rs
-    new_collectibles_q: Query<(Entity, &Transform, &NewCollectible)>,
+    new_collectibles_q: Query<(Entity, &Transform, &NewCollectible, &NetId)>,
     // dead players query for revival
     mut dead_players_q: Query<
         (Entity, &Transform, &mut Health),
         With<crate::components::Dead>
     >,
 ) {
+    //Despawns only happen after the system, so remember what is gone or a second player could take it too
+    let mut taken: Vec<(Entity, NetId)> = Vec::new();
     // iterate through current players
     for (player_entity, player_tf, mut player_health_opt, mut player, mut inventory) in player_q.iter_mut() {
         // Old collectibles
-        for (entity, item_tf, col) in old_collectibles_q.iter() {
+        for (entity, item_tf, col, id) in old_collectibles_q.iter() {
+            if taken.iter().any(|(e, _)| *e == entity) {
+                continue;
+            }
             if player_tf.translation.distance(item_tf.translation) > PICKUP_RADIUS {
                 continue;
             }
@@ -142,24 +168,24 @@ fn pickup_system(
                     if let Some(h) = player_health_opt.as_deref_mut() {
                         h.heal(col.amount.max(0));
                     }
-                    commands.entity(entity).despawn();
+                    taken.push((entity, *id));
                 }
                 OldCollectibleKind::Ammo => {
                     let added = inventory.add_to_reserve(col.amount.max(0));
                     if added > 0 {
                         ammo_writer.write(AmmoPickupEvent { amount: added });
-                        commands.entity(entity).despawn();
+                        taken.push((entity, *id));
                     }
                 }
                 OldCollectibleKind::Battery => {
-                    battery_writer.write(BatteryPickupEvent { amount: col.amount.max(0) });
-                    commands.entity(entity).despawn();
+                    battery_writer.write(BatteryPickupEvent { player: player_entity, amount: col.amount.max(0) });
+                    taken.push((entity, *id));
                 }
                 OldCollectibleKind::ReviveKit => {
                     if inventory.revive_kits < inventory.max_revive_kits {
                         inventory.revive_kits += 1;
                         revive_writer.write(ReviveKitPickupEvent);
-                        commands.entity(entity).despawn();
+                        taken.push((entity, *id));
                         info!("Collected a revive kit! Total: {}", inventory.revive_kits);
                     }
                 }
@@ -167,7 +193,10 @@ fn pickup_system(
         }
 
         // New collectibles
-        for (entity, item_tf, col) in new_collectibles_q.iter() {
+        for (entity, item_tf, col, id) in new_collectibles_q.iter() {
+            if taken.iter().any(|(e, _)| *e == entity) {
+                continue;
+            }
             if player_tf.translation.distance(item_tf.translation) > PICKUP_RADIUS {
                 continue;
             }
@@ -176,24 +205,24 @@ fn pickup_system(
                     if let Some(h) = player_health_opt.as_deref_mut() {
                         h.heal(amount.max(0));
                     }
-                    commands.entity(entity).despawn();
+                    taken.push((entity, *id));
                 }
                 NewCollectibleType::Ammo(amount) => {
                     let added = inventory.add_to_reserve(amount.max(0));
                     if added > 0 {
                         ammo_writer.write(AmmoPickupEvent { amount: added });
-                        commands.entity(entity).despawn();
+                        taken.push((entity, *id));
                     }
                 }
                 NewCollectibleType::Battery(amount) => {
-                    battery_writer.write(BatteryPickupEvent { amount: amount.max(500) });
-                    commands.entity(entity).despawn();
+                    battery_writer.write(BatteryPickupEvent { player: player_entity, amount: amount.max(500) });
+                    taken.push((entity, *id));
                 }
                 NewCollectibleType::ReviveKit => {
                     if inventory.revive_kits < inventory.max_revive_kits {
                         inventory.revive_kits += 1;
                         revive_writer.write(ReviveKitPickupEvent);
-                        commands.entity(entity).despawn();
+                        taken.push((entity, *id));
                         info!("Collected a revive kit! Total: {}", inventory.revive_kits);
                     }
                 }
@@ -201,7 +230,7 @@ fn pickup_system(
                     if !inventory.has_flashlight {
                         inventory.has_flashlight = true;
                     }
-                    commands.entity(entity).despawn();
+                    taken.push((entity, *id));
                 }
             }
         }
@@ -222,6 +251,11 @@ fn pickup_system(
             }
         }
     }
+
+    for (entity, id) in taken {
+        commands.entity(entity).despawn();
+        taken_writer.write(CollectibleTakenEvent { collectible_id: id.0 });
+    }
 }
 
 #[derive(Component)]
@@ -290,13 +324,12 @@ fn attach_flashlight_to_player(
 // Max battery charge is 500, for now.
 fn battery_pickup_system(
     mut events: EventReader<BatteryPickupEvent>,
-    mut players: Query<(&mut Player, &NetControl)>,
+    mut players: Query<&mut Player>,
 ) {
     for event in events.read() {
-        for (mut player, net_control) in &mut players {
-            if net_control.player_type == PlayerType::Local {
-                player.charge_battery(event.amount);
-            }
+        //Whoever walked over it, not just the player at this keyboard
+        if let Ok(mut player) = players.get_mut(event.player) {
+            player.charge_battery(event.amount);
         }
     }
 }
\ No newline at end of file
@@ -1,10 +1,11 @@
 use crate::components::Sanity;
 use crate::{
-    GameState, components::FlowMap, components::Health, components::KinematicCollider,
+    GameState, type_is_authority, components::FlowMap, components::Health, components::KinematicCollider,
     components::LightSource, components::StaticCollider, components::Dead, events::DamagePlayerEvent,
     net_control::NetControl, net_control::PlayerType, player_material::PlayerBaseMaterial,
-    collisions::find_mtv, server::InputHistory, server::RollbackDetection, wall::Door,
-    light_manager::Lights,
+    collisions::find_mtv, prediction::MoveState, wall::Door,
+    light_manager::Lights, level::CurrentLevel, level::Level, camera::MapBounds,
+    sim::{SimSet, TICK_SECS}, replay::Playback,
 };
 use bevy::math::bounding::Aabb2d;
 use bevy::math::bounding::IntersectsVolume;
@@ -27,23 +28,18 @@ const MAX_HEALTH: i32 = 100;
 pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Playing), setup_player)
-            .add_systems(FixedUpdate, player_movement.run_if(in_state(GameState::Playing)))
+        app.init_resource::<LocalInput>()
+            .add_event::<InputsApplied>()
+            .add_systems(OnEnter(GameState::Playing), setup_player)
+            .add_systems(FixedUpdate, (read_local_input, player_movement).chain().in_set(SimSet::Players))
             .add_systems(
                 Update,
-                player_orientation.run_if(in_state(GameState::Playing)),
+                //A replay aims for the players
+                player_orientation.run_if(in_state(GameState::Playing)).run_if(not(resource_exists::<Playback>)),
             )
-            .add_systems(
-                Update,
-                drain_battery.run_if(in_state(GameState::Playing)),
-            )
-            .add_systems(
-            Update,
-            player_movement_from_history
-                .run_if(in_state(GameState::Playing))
-                .run_if(rollback_from_history),
-        )
-            .add_systems(Update, player_damage.run_if(in_state(GameState::Playing)))
+            .add_systems(FixedUpdate, drain_battery.in_set(SimSet::Combat))
+            //Clients are told who is hurt or dead by the host
+            .add_systems(FixedUpdate, player_damage.in_set(SimSet::Damage).run_if(type_is_authority))
             .add_systems(
                 Update,
                 player_calculate_flow.run_if(in_state(GameState::Playing)),
@@ -51,10 +47,6 @@ impl Plugin for PlayerPlugin {
     }
 }
 
-fn rollback_from_history(roll: Res<RollbackDetection>) -> bool {
-    return roll.is_rollback;
-}
-
 #[derive(Component)]
 pub struct Player {
     pub charge: i32,
@@ -67,6 +59,24 @@ pub struct LocalPlayer {
     pub entity: Entity,
 }
 
+//This tick's WASD00L0 byte from our own keyboard and mouse. A replay fills it in from the file instead.
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct LocalInput(pub u8);
+
+impl LocalInput {
+    pub fn shooting(&self) -> bool {
+        self.0 & 2 == 2
+    }
+}
+
+//The inputs the host moved a remote player with this tick, see replay.rs
+#[derive(Event, Debug, Clone)]
+pub struct InputsApplied {
+    pub player_id: u8,
+    pub angle: u8,
+    pub inputs: Vec<u8>,
+}
+
 impl Player {
     pub fn charge_battery(&mut self, value: i32) {
         self.charge += value;
@@ -101,6 +111,30 @@ impl Velocity {
     }
 }
 
+//The first four player slots have their own texture so players can tell each other apart, the rest
+//go by player_color in the UI
+pub fn player_albedo(player_id: u8) -> &'static str {
+    match player_id {
+        0 => "player/player_albedo_blue.png",
+        1 => "player/player_albedo_purple.png",
+        2 => "player/player_albedo_yellow.png",
+        3 => "player/player_albedo_orange.png",
+        _ => "player/player_albedo.png",
+    }
+}
+
+//Flat color matching player_albedo, for UI that can't show the texture. Slots past the textured ones
+//step around the color wheel by the golden angle, which keeps neighbouring ids far apart.
+pub fn player_color(player_id: u8) -> Color {
+    match player_id {
+        0 => Color::srgb(0.2, 0.4, 1.0),
+        1 => Color::srgb(0.6, 0.2, 0.9),
+        2 => Color::srgb(1.0, 0.85, 0.1),
+        3 => Color::srgb(1.0, 0.5, 0.1),
+        _ => Color::hsl((player_id as f32 * 137.5 + 10.) % 360., 0.75, 0.55),
+    }
+}
+
 pub fn setup_player(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -111,38 +145,23 @@ pub fn setup_player(
     players: Query<(Entity, &mut NetControl), With<NetControl>>,
     lights: Res<Lights>,
     sdf_texture: Res<crate::sdf_shadows::SdfTexture>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
 ) {
+    let level = levels.get(&current_level.handle);
+
     // if query.is_empty() {
     //     commands.spawn(Camera2d);
     // }
 
     for i in players {
-        let mut model_select;
-        let mut start_pos_x = 0.;
-        let mut start_pos_y = 0.;
-        match i.1.player_id {
-            0 => {
-                model_select = "player/player_albedo_blue.png";
-                start_pos_x = -3200. + (64. * 8.);
-                start_pos_y = 3200. - (64. * 4.);
-            }
-            1 => {
-                model_select = "player/player_albedo_purple.png";
-                start_pos_x = -3200. + (64. * 12.);
-                start_pos_y = 3200. - (64. * 4.);
-            }
-            2 => {
-                model_select = "player/player_albedo_yellow.png";
-                start_pos_x = -3200. + (64. * 8.);
-                start_pos_y = 3200. - (64. * 8.);
-            }
-            3 => {
-                model_select = "player/player_albedo_orange.png";
-                start_pos_x = -3200. + (64. * 12.);
-                start_pos_y = 3200. - (64. * 8.);
-            }
-            _ => model_select = "player/player_albedo.png",
-        }
+        let model_select = player_albedo(i.1.player_id);
+        //Spawn points come from the level, players without one start in the middle of the map
+        let start_pos = level
+            .and_then(|level| level.spawn_position(i.1.player_id))
+            .unwrap_or(Vec2::ZERO);
+        let start_pos_x = start_pos.x;
+        let start_pos_y = start_pos.y;
         commands.entity(i.0).insert((
             // For any entities that we want to have lighting,
             // add the following two components.
@@ -181,7 +200,6 @@ pub fn setup_player(
                 },
             },
             FlowMap::default(),
-            InputHistory::default(),
         ));
 
         // Identify the local player and store it
@@ -239,140 +257,133 @@ pub fn player_calculate_flow(
     }
 }
 
+//Walls the player can't walk through, already moved to where they are in the world
+pub fn static_shapes(
+    statics: &Query<(&StaticCollider, &Transform), (Without<KinematicCollider>, Without<Door>)>,
+) -> Vec<Aabb2d> {
+    statics
+        .iter()
+        .map(|(sc, st)| {
+            let mut shape = sc.shape.clone();
+            shape.min += st.translation.truncate();
+            shape.max += st.translation.truncate();
+            shape
+        })
+        .collect()
+}
+
+//One fixed tick of player movement from a WASD00L0 input byte. The host, the client's prediction and
+//the client's replay after a correction all go through here, so the same inputs land on the same spot.
+pub fn movement_step(
+    state: MoveState,
+    input: u8,
+    deltat: f32,
+    collider: &Aabb2d,
+    statics: &[Aabb2d],
+    map: &MapBounds,
+) -> MoveState {
+    let mut dir = Vec2::ZERO;
+    if NetControl::pressed_u8(KeyCode::KeyA, input) {
+        dir.x -= 1.;
+    }
+    if NetControl::pressed_u8(KeyCode::KeyD, input) {
+        dir.x += 1.;
+    }
+    if NetControl::pressed_u8(KeyCode::KeyW, input) {
+        dir.y += 1.;
+    }
+    if NetControl::pressed_u8(KeyCode::KeyS, input) {
+        dir.y -= 1.;
+    }
+
+    let accel = ACCEL_RATE * deltat;
+    let velocity = if dir.length() > 0. {
+        (state.velocity + (dir.normalize_or_zero() * accel)).clamp_length_max(PLAYER_SPEED)
+    } else if state.velocity.length() > accel {
+        state.velocity + (state.velocity.normalize_or_zero() * -accel)
+    } else {
+        Vec2::ZERO
+    };
+
+    let change = velocity * deltat;
+
+    //keep player in bounds. The change is applied a second time here, player speed is tuned with that in
+    let max = Vec2::new(map.width / 2. - PLAYER_SIZE / 2., map.height / 2. - PLAYER_SIZE / 2.);
+    let mut pos = (state.pos + change + change).clamp(-max, max);
+
+    //Collision check
+    for shape in statics {
+        let mut transformed_kc_shape = collider.clone();
+        transformed_kc_shape.min += pos;
+        transformed_kc_shape.max += pos;
+
+        if transformed_kc_shape.intersects(shape) {
+            pos += find_mtv(&transformed_kc_shape, shape);
+        }
+    }
+
+    //Rounds position to integers
+    MoveState { pos: pos.round(), velocity }
+}
+
+//Read once per tick so everything in the tick sees the same keys
+pub fn read_local_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut local_input: ResMut<LocalInput>,
+) {
+    let click = if mouse.pressed(MouseButton::Left) { 2 } else { 0 };
+    local_input.0 = NetControl::keyboard_u8(&keys) | click;
+}
+
+//Moves the host's own player from the keyboard and every remote player from its queued inputs. A
+//client's own player is moved by its prediction in client.rs, other players on a client are interpolated.
 pub fn player_movement(
-    time: Res<Time>,
     input: Res<ButtonInput<KeyCode>>,
+    local_input: Res<LocalInput>,
+    mut applied: EventWriter<InputsApplied>,
     player_net: Query<
-        (&mut Transform, &mut Velocity, &mut NetControl, &KinematicCollider, &mut InputHistory),
+        (&mut Transform, &mut Velocity, &mut NetControl, &KinematicCollider),
         (With<Player>, With<NetControl>, Without<Dead>),
     >,
     statics: Query<(&StaticCollider, &Transform), (Without<KinematicCollider>, Without<Door>)>,
+    map: Res<MapBounds>,
 ) {
-    for (mut transform, mut velocity, mut control, player_collider, hist) in player_net {
-        let mut dir = Vec2::ZERO;
-        
-        if control.get_type() == PlayerType::Local && !control.host {
-            //info!("Rollback = {:?}", control.rollback)
-        }
-
-        if control.get_type() == PlayerType::Local {//&& !control.rollback {
-            if input.pressed(KeyCode::KeyA) {
-                dir.x -= 1.;
-            }
-
-            if input.pressed(KeyCode::KeyD) {
-                dir.x += 1.;
-            }
-
-            if input.pressed(KeyCode::KeyW) {
-                dir.y += 1.;
-            }
-
-            if input.pressed(KeyCode::KeyS) {
-                dir.y -= 1.;
-            }
-
+    let shapes = static_shapes(&statics);
+    for (mut transform, mut velocity, mut control, player_collider) in player_net {
+        let inputs = if control.get_type() == PlayerType::Local && control.host {
             //Debug for checking current pos
             if input.pressed(KeyCode::KeyO) {
                 info!("Player {}'s Current Position -> {:?}", control.player_id, transform.translation);
             }
+            vec![local_input.0]
 
         //REMOTE PLAYER INPUTS ON HOST
         } else if control.host {
-            if control.pressed(KeyCode::KeyA) {
-                dir.x -= 1.;
-            }
-
-            if control.pressed(KeyCode::KeyD) {
-                dir.x += 1.;
-            }
-
-            if control.pressed(KeyCode::KeyW) {
-                dir.y += 1.;
-            }
-
-            if control.pressed(KeyCode::KeyS) {
-                dir.y -= 1.;
-            }
-
             //REMOTE PLAYER ANGLE ON HOST
             let rounded_rot_z = control.get_angle();
-            //info!("Player {}: {:?}", control.player_id, rounded_rot_z);
             transform.rotation = Quat::from_rotation_z(rounded_rot_z - consts::PI / 2.);
 
-        //REMOTE PLAYER ON REMOTE
-        } else {
-
-            if control.get_type() == PlayerType::Local {
-                //info!("Rollback {}: {:?}", control.player_id, control.get_p_pos());
+            //Inputs are applied in the order the client made them, see InputQueue::take_tick
+            let inputs = control.inputs.take_tick();
+            if let Some(&bits) = inputs.last() {
+                control.net_input = bits;
             }
-            
-            transform.translation = control.get_p_pos();
-            let rounded_rot_z = control.get_angle();
-            transform.rotation = Quat::from_rotation_z(rounded_rot_z - consts::PI / 2.);
-            control.rollback = false;
-            continue;
-        }
-
-        let deltat = time.delta_secs();
-        let accel = ACCEL_RATE * deltat;
-        //info!("deltat = {:?}", deltat);
+            applied.write(InputsApplied { player_id: control.player_id, angle: control.net_angle, inputs: inputs.clone() });
+            inputs
 
-        **velocity = if dir.length() > 0. {
-            (**velocity + (dir.normalize_or_zero() * accel)).clamp_length_max(PLAYER_SPEED)
-        } else if velocity.length() > accel {
-            **velocity + (velocity.normalize_or_zero() * -accel)
+        //LOCAL PLAYER ON REMOTE is predicted, REMOTE PLAYER ON REMOTE is interpolated
         } else {
-            Vec2::ZERO
+            continue;
         };
 
-        let change = **velocity * deltat;
-
-
-        //If a rollback is detected, set the starting position to the server stated position
-        if control.get_type() == PlayerType::Local && control.rollback {
-            transform.translation = control.get_p_pos(); 
-        }
-
-        transform.translation += change.extend(0.);
-
-        //keep player in bounds
-        let max = Vec3::new(
-            100. * 64. / 2. - PLAYER_SIZE / 2.,
-            100. * 64. / 2. - PLAYER_SIZE / 2.,
-            0.,
-        );
-
-        let min = max.clone() * -1.;
-
-        let translate = (transform.translation + change.extend(0.)).clamp(min, max);
-
-        transform.translation = translate;
-
-        //Collision check
-        for (sc, st) in &statics {
-            let mut transformed_kc_shape = player_collider.shape.clone();
-            transformed_kc_shape.min += transform.translation.truncate();
-            transformed_kc_shape.max += transform.translation.truncate();
-
-            let mut transformed_sc_shape = sc.shape.clone();
-            transformed_sc_shape.min += st.translation.truncate();
-            transformed_sc_shape.max += st.translation.truncate();
-
-            let colliding = transformed_kc_shape.intersects(&transformed_sc_shape);
-            if colliding {
-                transform.translation = transform.translation
-                    + find_mtv(&transformed_kc_shape, &transformed_sc_shape).extend(0.);
-            }
-        }
-
-        //Rounds position to integers
-        transform.translation.x = transform.translation.x.round();
-        transform.translation.y = transform.translation.y.round();
-        
-        if control.get_type() == PlayerType::Local && !control.host && input.pressed(KeyCode::KeyP) {
-            //info!("Steps: {:?}", transform.translation);
+        let mut state = MoveState { pos: transform.translation.truncate(), velocity: **velocity };
+        for bits in inputs {
+            state = movement_step(state, bits, TICK_SECS, &player_collider.shape, &shapes, &map);
         }
+        **velocity = state.velocity;
+        transform.translation.x = state.pos.x;
+        transform.translation.y = state.pos.y;
 
         //Sets position in NetControl
         control.set_pos_x(transform.translation.x);
@@ -380,145 +391,6 @@ pub fn player_movement(
     }
 }
 
-pub fn player_movement_from_history(
-    time: Res<Time>,
-    player_net: Query<
-        (&mut Transform, &mut Velocity, &mut NetControl, &KinematicCollider, &mut InputHistory),
-        (With<Player>, With<NetControl>),
-    >,
-    statics: Query<(&StaticCollider, &Transform), Without<KinematicCollider>>,
-    mut roll: ResMut<RollbackDetection>,
-) {
-    for (mut transform, mut velocity, mut control, player_collider, mut hist) in player_net {
-
-        //Check if correct player for rollback
-        if control.player_id == hist.player && hist.usable {
-            hist.history_used();
-
-            //info!("hist.last_pos -> {:?}", hist.last_pos);
-
-            //Reset player position before rollback
-            let mut trans_temp = hist.last_pos;
-            //transform.translation = hist.last_pos;
-
-            let input_seq;
-
-            if (hist.start > hist.end) {
-                input_seq = (hist.start..=255).chain(0..=hist.end).into_iter();
-            }
-            else {
-                //This is just hist.start..=hist.end, but built with a chain so they could be assigned to the same var for the for loop
-                input_seq = (hist.start..=(hist.start+1)).chain((hist.start+2)..=hist.end);
-            }
-
-            let mut counter = 0;
-
-            for i in input_seq {
-                let mut dir = Vec2::ZERO;
-                counter += 1;
-
-                if NetControl::pressed_u8(KeyCode::KeyA, hist.complete_history[i as usize]) {
-                    dir.x -= 1.;
-                }
-
-                if NetControl::pressed_u8(KeyCode::KeyD, hist.complete_history[i as usize]) {
-                    dir.x += 1.;
-                }
-
-                if NetControl::pressed_u8(KeyCode::KeyW, hist.complete_history[i as usize]) {
-                    dir.y += 1.;
-                }
-
-                if NetControl::pressed_u8(KeyCode::KeyS, hist.complete_history[i as usize]) {
-                    dir.y -= 1.;
-                }
-
-                let deltat = 0.015625;//time.delta_secs();
-                let accel = ACCEL_RATE * deltat;
-                //info!("deltat = {:?}", deltat);
-
-                **velocity = if dir.length() > 0. {
-                    (**velocity + (dir.normalize_or_zero() * accel)).clamp_length_max(PLAYER_SPEED)
-                } else if velocity.length() > accel {
-                    **velocity + (velocity.normalize_or_zero() * -accel)
-                } else {
-                    Vec2::ZERO
-                };
-
-                let change = **velocity * deltat;
-
-
-                //transform.translation += change.extend(0.);
-                trans_temp += change.extend(0.);
-
-                //keep player in bounds
-                let max = Vec3::new(
-                    100. * 64. / 2. - PLAYER_SIZE / 2.,
-                    100. * 64. / 2. - PLAYER_SIZE / 2.,
-                    0.,
-                );
-
-                let min = max.clone() * -1.;
-
-                let translate = (transform.translation + change.extend(0.)).clamp(min, max);
-
-                //HERE
-                //transform.translation = translate;
-                trans_temp = translate;
-
-                //Collision check
-                /*for (sc, st) in &statics {
-                    let mut transformed_kc_shape = player_collider.shape.clone();
-                    transformed_kc_shape.min += transform.translation.truncate();
-                    transformed_kc_shape.max += transform.translation.truncate();
-
-                    let mut transformed_sc_shape = sc.shape.clone();
-                    transformed_sc_shape.min += st.translation.truncate();
-                    transformed_sc_shape.max += st.translation.truncate();
-
-                    let colliding = transformed_kc_shape.intersects(&transformed_sc_shape);
-                    if colliding {
-                        transform.translation = transform.translation
-                        + find_mtv(&transformed_kc_shape, &transformed_sc_shape).extend(0.);
-                    }
-                }*/
-                for (sc, st) in &statics {
-                    let mut transformed_kc_shape = player_collider.shape.clone();
-                    transformed_kc_shape.min += trans_temp.truncate();
-                    transformed_kc_shape.max += trans_temp.truncate();
-
-                    let mut transformed_sc_shape = sc.shape.clone();
-                    transformed_sc_shape.min += st.translation.truncate();
-                    transformed_sc_shape.max += st.translation.truncate();
-
-                    let colliding = transformed_kc_shape.intersects(&transformed_sc_shape);
-                    if colliding {
-                        trans_temp = trans_temp
-                        + find_mtv(&transformed_kc_shape, &transformed_sc_shape).extend(0.);
-                    }
-                }
-
-                //Rounds position to integers
-                trans_temp.x = trans_temp.x.round();
-                trans_temp.y = trans_temp.y.round();
-                //info!("History Traceback Iter: {:?}, Seq: {:?} -> {:?}", counter, i, trans_temp);
-
-                //Sets position in NetControl
-                //control.set_pos_x(trans_temp.x);
-                //control.set_pos_y(trans_temp.y);
-            }
-
-            //info!("Setting player position via REMOTE PLAYER ON REMOTE for Player {}: {:?}", control.player_id, transform.translation);
-
-            control.set_pos_x(trans_temp.x);
-            control.set_pos_y(trans_temp.y);
-
-            roll.is_rollback = false;
-            control.rollback = false;
-        }
-    }
-}
-
 pub fn player_orientation(
     mut player_net: Query<
         (
@@ -545,8 +417,6 @@ pub fn player_orientation(
             camera.viewport_to_world_2d(camera_transform, cursor_position)
         {
             for (mut material, mut player_transform, mut netcontrol) in player_net.iter_mut() {
-                let mut rounded_rot_z = 0.;
-
                 if netcontrol.player_type == PlayerType::Local {
                     let player_position = player_transform.translation.truncate();
                     let direction = cursor_world_position - player_position;
@@ -554,12 +424,13 @@ pub fn player_orientation(
                     if direction.length() > 0.0 {
                         let rotation_z = direction.y.atan2(direction.x);
                         //Rounding is needed to prevent precision errors when networking
-                        rounded_rot_z = (rotation_z * 10.).round() / 10.;
+                        let rounded_rot_z = (rotation_z * 10.).round() / 10.;
                         //info!("PL_ROT Player {}: {:?}", netcontrol.player_id, rounded_rot_z);
                         netcontrol.set_angle(rounded_rot_z);
                     }
+                    //Aimed with the angle remote players and replays see, so shots go the same way for them
                     player_transform.rotation =
-                        Quat::from_rotation_z(rounded_rot_z - consts::PI / 2.);
+                        Quat::from_rotation_z(netcontrol.get_angle() - consts::PI / 2.);
                 }
             }
 
@@ -614,11 +485,10 @@ pub fn player_damage(
 }
 
 pub fn drain_battery(
-    time: Res<Time>,
     mut players: Query<&mut Player>,
     mut lights: Query<&mut LightSource>,
 ) {
-    let delta = time.delta_secs();
+    let delta = TICK_SECS;
     let max_range = 500.0;
     let min_range = 50.0;
 
@@ -1,4 +1,4 @@
-use crate::light_manager::Light;
+use crate::light_manager::{Light, NUM_LIGHTS};
 use bevy::{
     prelude::*,
     reflect::TypePath,
@@ -19,7 +19,7 @@ pub struct Lighting {
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct PlayerBaseMaterial {
     #[uniform(0)]
-    pub lights: [Light; 5],
+    pub lights: [Light; NUM_LIGHTS as usize],
 
     #[uniform(1)]
     pub color: LinearRgba,
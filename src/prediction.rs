@@ -0,0 +1,253 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Client-side prediction. The client moves its own player as soon as a key is pressed and tags     ///
+///   every input with a sequence number. The host applies those inputs one per tick and sends back    ///
+///   the last sequence it applied with each PlayerState. If the host's position for that input        ///
+///   doesn't match what the client predicted, the client rewinds to the host's position and replays   ///
+///   the inputs the host hasn't got to yet.                                                           ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Sequence 0 means "no input yet", so real inputs start at 1 and skip 0 when they wrap
+pub const NO_INPUT: u16 = 0;
+//About two seconds of inputs at the fixed rate, anything older than this the host has long since lost
+pub const MAX_PENDING_INPUTS: usize = 128;
+//Inputs the host holds before it starts skipping to catch up
+pub const MAX_QUEUED_INPUTS: usize = 8;
+//After a lost packet the next one brings two inputs, applying both keeps the host from staying a tick behind
+pub const MAX_INPUTS_PER_TICK: usize = 2;
+//Positions are rounded to whole units on both sides, so anything under half a unit is the same spot
+const POSITION_TOLERANCE: f32 = 0.5;
+
+//True when a is after b, allowing for wrap around
+pub fn seq_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+fn next_seq(seq: u16) -> u16 {
+    match seq.wrapping_add(1) {
+        NO_INPUT => 1,
+        next => next,
+    }
+}
+
+//Everything the movement step reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MoveState {
+    pub pos: Vec2,
+    pub velocity: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingInput {
+    seq: u16,
+    input: u8,
+    //Where this input left the player
+    state: MoveState,
+}
+
+//Client side record of inputs the host hasn't acknowledged
+#[derive(Default)]
+pub struct Prediction {
+    pending: VecDeque<PendingInput>,
+    last_seq: u16,
+    acked: u16,
+}
+
+impl Prediction {
+    pub fn next_seq(&mut self) -> u16 {
+        self.last_seq = next_seq(self.last_seq);
+        self.last_seq
+    }
+
+    pub fn record(&mut self, seq: u16, input: u8, state: MoveState) {
+        self.pending.push_back(PendingInput { seq, input, state });
+        while self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+    }
+
+    //The newest inputs oldest first, each packet carries several so one lost packet loses nothing
+    pub fn recent_inputs(&self, max: usize) -> Vec<u8> {
+        let skip = self.pending.len().saturating_sub(max);
+        self.pending.iter().skip(skip).map(|p| p.input).collect()
+    }
+
+    //Takes the host's position after input ack. Returns the corrected current state when the
+    //prediction was wrong, None when it was right or the update is stale.
+    pub fn reconcile(
+        &mut self,
+        ack: u16,
+        authoritative: Vec2,
+        mut step: impl FnMut(MoveState, u8) -> MoveState,
+    ) -> Option<MoveState> {
+        if ack == NO_INPUT || !seq_newer(ack, self.acked) {
+            return None;
+        }
+        self.acked = ack;
+
+        let predicted = self.pending.iter().find(|p| p.seq == ack).map(|p| p.state);
+        while self.pending.front().is_some_and(|p| !seq_newer(p.seq, ack)) {
+            self.pending.pop_front();
+        }
+        //Velocity isn't sent, but it only depends on the inputs so ours is the host's
+        let velocity = match predicted {
+            Some(state) if state.pos.distance(authoritative) < POSITION_TOLERANCE => return None,
+            Some(state) => state.velocity,
+            None => Vec2::ZERO,
+        };
+
+        let mut state = MoveState { pos: authoritative, velocity };
+        for pending in self.pending.iter_mut() {
+            state = step(state, pending.input);
+            pending.state = state;
+        }
+        Some(state)
+    }
+}
+
+//Host side inputs from one client, waiting to be applied
+#[derive(Default)]
+pub struct InputQueue {
+    queue: VecDeque<(u16, u8)>,
+    newest: u16,
+    processed: u16,
+}
+
+impl InputQueue {
+    //inputs are oldest first and the last one has sequence number seq
+    pub fn receive(&mut self, seq: u16, inputs: &[u8]) {
+        for (i, &input) in inputs.iter().enumerate() {
+            let input_seq = seq.wrapping_sub((inputs.len() - 1 - i) as u16);
+            if input_seq == NO_INPUT || !seq_newer(input_seq, self.newest) {
+                continue;
+            }
+            self.queue.push_back((input_seq, input));
+            self.newest = input_seq;
+        }
+        //Falling behind adds latency to everything the client does, skip ahead instead
+        while self.queue.len() > MAX_QUEUED_INPUTS {
+            if let Some((seq, _)) = self.queue.pop_front() {
+                self.processed = seq;
+            }
+        }
+    }
+
+    //The inputs to apply this tick, usually one. None queued means the client hasn't sent the next one
+    //yet, and the player waits for it rather than guessing, so the host never moves the player in a way
+    //the client didn't.
+    pub fn take_tick(&mut self) -> Vec<u8> {
+        let count = self.queue.len().min(MAX_INPUTS_PER_TICK);
+        let inputs: Vec<(u16, u8)> = self.queue.drain(..count).collect();
+        if let Some(&(seq, _)) = inputs.last() {
+            self.processed = seq;
+        }
+        inputs.into_iter().map(|(_, input)| input).collect()
+    }
+
+    //Swaps whatever is queued for exactly these inputs, the ones a replay says the host took this tick
+    pub fn replace(&mut self, inputs: &[u8]) {
+        self.queue.clear();
+        for &input in inputs {
+            self.newest = next_seq(self.newest);
+            self.queue.push_back((self.newest, input));
+        }
+    }
+
+    //Last input applied, sent back in PlayerState
+    pub fn processed(&self) -> u16 {
+        self.processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Stand-in for the movement step, input is how far to move along x
+    fn step(state: MoveState, input: u8) -> MoveState {
+        MoveState { pos: state.pos + Vec2::new(input as f32, 0.), velocity: Vec2::new(input as f32, 0.) }
+    }
+
+    fn predict(prediction: &mut Prediction, inputs: &[u8]) -> MoveState {
+        let mut state = MoveState::default();
+        for &input in inputs {
+            let seq = prediction.next_seq();
+            state = step(state, input);
+            prediction.record(seq, input, state);
+        }
+        state
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_and_skip_zero() {
+        assert!(seq_newer(1, u16::MAX));
+        assert!(!seq_newer(u16::MAX, 1));
+        assert!(!seq_newer(5, 5));
+        assert_eq!(next_seq(u16::MAX), 1);
+    }
+
+    #[test]
+    fn matching_prediction_is_left_alone() {
+        let mut prediction = Prediction::default();
+        predict(&mut prediction, &[1, 2, 3, 4]);
+        //Host applied the first two, 1 + 2
+        assert_eq!(prediction.reconcile(2, Vec2::new(3., 0.), step), None);
+        assert_eq!(prediction.recent_inputs(8), vec![3, 4]);
+    }
+
+    #[test]
+    fn mismatch_rewinds_and_replays() {
+        let mut prediction = Prediction::default();
+        let current = predict(&mut prediction, &[1, 2, 3, 4]);
+        assert_eq!(current.pos.x, 10.);
+        //Host had the player blocked by something after input 2
+        let corrected = prediction.reconcile(2, Vec2::new(0., 5.), step).unwrap();
+        assert_eq!(corrected.pos, Vec2::new(7., 5.));
+        //The replayed states are what the next ack is compared against
+        assert_eq!(prediction.reconcile(3, Vec2::new(3., 5.), step), None);
+    }
+
+    #[test]
+    fn stale_acks_are_ignored() {
+        let mut prediction = Prediction::default();
+        predict(&mut prediction, &[1, 1, 1]);
+        assert_eq!(prediction.reconcile(2, Vec2::new(2., 0.), step), None);
+        assert_eq!(prediction.reconcile(1, Vec2::new(50., 0.), step), None);
+        assert_eq!(prediction.reconcile(NO_INPUT, Vec2::new(50., 0.), step), None);
+    }
+
+    #[test]
+    fn host_applies_each_input_once_in_order() {
+        let mut queue = InputQueue::default();
+        queue.receive(1, &[10]);
+        assert_eq!(queue.take_tick(), vec![10]);
+        //Redundant copy of 1 plus the new 2
+        queue.receive(2, &[10, 20]);
+        assert_eq!(queue.take_tick(), vec![20]);
+        assert_eq!(queue.take_tick(), Vec::<u8>::new());
+        assert_eq!(queue.processed(), 2);
+    }
+
+    #[test]
+    fn host_catches_up_after_a_gap() {
+        let mut queue = InputQueue::default();
+        queue.receive(1, &[10]);
+        queue.take_tick();
+        //Packet with 2 was lost, 3 carries both
+        assert_eq!(queue.take_tick(), Vec::<u8>::new());
+        queue.receive(3, &[10, 20, 30]);
+        assert_eq!(queue.take_tick(), vec![20, 30]);
+        assert_eq!(queue.processed(), 3);
+    }
+
+    #[test]
+    fn host_skips_ahead_when_flooded() {
+        let mut queue = InputQueue::default();
+        let inputs: Vec<u8> = (1..=20).collect();
+        queue.receive(20, &inputs);
+        assert_eq!(queue.processed(), 20 - MAX_QUEUED_INPUTS as u16);
+        assert_eq!(queue.take_tick()[0], 20 - MAX_QUEUED_INPUTS as u8 + 1);
+    }
+}
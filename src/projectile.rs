@@ -1,11 +1,11 @@
 use crate::{
     collectible::{consume_ammo, PlayerInventory},
     GameState, net_control::NetControl, net_control::PlayerType, player::FireCooldown,
-    player::Player, components::KinematicCollider,
+    player::LocalInput, player::Player, player::player_movement, components::KinematicCollider, sim::{SimSet, TICK_SECS},
 };
-use bevy::input::ButtonInput;
 use bevy::input::mouse::MouseButton;
 use bevy::{math::bounding::Aabb2d, math::bounding::IntersectsVolume, prelude::*};
+use std::time::Duration;
 
 const PROJECTILE_SPEED: f32 = 1000.;
 
@@ -15,14 +15,9 @@ impl Plugin for ProjectilePlugin {
         app.insert_resource(MouseMemory {
             last_pos: Vec2::ZERO,
         })
-        .add_systems(
-            Update,
-            projectile_inputs.run_if(in_state(GameState::Playing)),
-        )
-        .add_systems(
-            Update,
-            projectile_movement.run_if(in_state(GameState::Playing)),
-        );
+        //Shots come off the players after they have moved this tick
+        .add_systems(FixedUpdate, projectile_inputs.in_set(SimSet::Players).after(player_movement))
+        .add_systems(FixedUpdate, projectile_movement.in_set(SimSet::Projectiles));
     }
 }
 
@@ -49,15 +44,14 @@ impl Velocity {
 
 pub fn projectile_inputs(
     mut commands: Commands,
-    mouse_button_io: Res<ButtonInput<MouseButton>>,
+    local_input: Res<LocalInput>,
     windows: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
     mut player_q: Query<(&Transform, &mut FireCooldown, &mut NetControl, &mut PlayerInventory), With<Player>>,
-    time: Res<Time>,
     asset_server: Res<AssetServer>,
     mut pos_history: ResMut<MouseMemory>,
 ) {
-    let shooting = mouse_button_io.pressed(MouseButton::Left);
+    let shooting = local_input.shooting();
 
     for (transform, mut cooldown, mut netcontrol, mut inventory) in player_q.iter_mut() {
         let projectile_pos = transform.translation;
@@ -65,7 +59,7 @@ pub fn projectile_inputs(
 
         //Local Shooting
         if shooting && netcontrol.get_type() == PlayerType::Local {
-            if cooldown.tick(time.delta()) && inventory.has_available_ammo() && consume_ammo(&mut inventory, 1) {
+            if cooldown.tick(Duration::from_secs_f32(TICK_SECS)) && inventory.has_available_ammo() && consume_ammo(&mut inventory, 1) {
                 commands.spawn((
                     Sprite::from_image(asset_server.load("textures/bullet.png")),
                     Transform::from_scale(Vec3::splat(0.2)).with_translation(projectile_pos),
@@ -95,7 +89,7 @@ pub fn projectile_inputs(
         }
         //Networked player on Host/Network
         else if (netcontrol.clicked(MouseButton::Left) || netcontrol.p_shot)
-            && cooldown.tick(time.delta())
+            && cooldown.tick(Duration::from_secs_f32(TICK_SECS))
             && netcontrol.get_type() == PlayerType::Network
         {
             commands.spawn((
@@ -117,12 +111,10 @@ pub fn projectile_inputs(
 }
 
 pub fn projectile_movement(
-    time: Res<Time>,
     mut projectiles: Query<(&mut Transform, &Velocity), With<Projectile>>,
 ) {
     for (mut transform, velocity) in &mut projectiles {
-        let delta_t = time.delta_secs();
-        let delta_d = **velocity * delta_t;
+        let delta_d = **velocity * TICK_SECS;
         transform.translation += delta_d.extend(0.);
     }
 }
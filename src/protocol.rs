@@ -0,0 +1,1358 @@
+use std::fmt;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///                                         Wire format                                                  ///
+///   Every datagram starts with MAGIC, PROTOCOL_VERSION and a message type byte. Everything after the   ///
+///   header is little-endian so hosts and clients on different machines agree.                          ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub const MAGIC: [u8; 2] = *b"UT";
+//Bump this whenever the layout of any message changes
+pub const PROTOCOL_VERSION: u8 = 15;
+pub const HEADER_LEN: usize = 4;
+//Biggest datagram either side will ever send, used to size receive buffers
+pub const MAX_DATAGRAM: usize = 1400;
+//Everything but the handshake ends with a sequence number and tag this long, see secure.rs
+pub const SEAL_OVERHEAD: usize = 24;
+//Random bytes each side puts into the handshake so every session gets its own keys
+pub const NONCE_LEN: usize = 16;
+//Limit on how many enemies fit in one EnemySpawns/EnemyDamage/EnemyDeaths message
+pub const MAX_ENEMIES_PER_PACKET: usize = 64;
+//Most inputs one Input message carries, the newest plus the ones before it in case packets were lost
+pub const MAX_INPUTS_PER_PACKET: usize = 8;
+//player_id the host puts in Leave when it is shutting down. A dedicated host has no player of its own,
+//so player 0 can be a remote client.
+pub const HOST_ID: u8 = u8::MAX;
+//Longest player name on the wire in bytes, longer names are cut short
+pub const MAX_NAME_LEN: usize = 16;
+//Longest chat line on the wire in bytes
+pub const MAX_CHAT_LEN: usize = 120;
+
+//Message type bytes. Kept the same as the old bare op codes where one existed.
+mod op {
+    pub const START: u8 = 0;
+    pub const PLAYER_STATE: u8 = 1;
+    pub const CLOCK_SYNC: u8 = 2;
+    //3 and 7 were the input history rollback, replaced by acknowledged inputs in INPUT and PLAYER_STATE
+    //4 was the old fixed size enemy position buffer, replaced by SNAPSHOT
+    pub const INVENTORY: u8 = 5;
+    pub const INPUT: u8 = 6;
+    pub const ACCEPT: u8 = 8;
+    pub const REJECT: u8 = 9;
+    pub const LEAVE: u8 = 10;
+    pub const HEARTBEAT: u8 = 11;
+    pub const ENEMY_SPAWNS: u8 = 12;
+    pub const ENEMY_DAMAGE: u8 = 13;
+    pub const ENEMY_DEATHS: u8 = 14;
+    pub const SNAPSHOT: u8 = 15;
+    pub const SNAPSHOT_ACK: u8 = 16;
+    pub const READY: u8 = 17;
+    pub const LOBBY_STATE: u8 = 18;
+    pub const PING: u8 = 19;
+    pub const PONG: u8 = 20;
+    pub const DISCOVERY_QUERY: u8 = 21;
+    pub const DISCOVERY_REPLY: u8 = 22;
+    pub const RESUME: u8 = 23;
+    pub const DOOR_STATES: u8 = 24;
+    pub const PLAYER_STATUS: u8 = 25;
+    pub const COLLECTIBLES_TAKEN: u8 = 26;
+    pub const CHAT: u8 = 27;
+    pub const MARKER: u8 = 28;
+    pub const SPECTATE: u8 = 29;
+    pub const HELLO: u8 = 30;
+    pub const CHALLENGE: u8 = 31;
+    pub const JOIN: u8 = 255;
+}
+
+//Position/angle of a single player, sent host -> client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerState {
+    pub player_id: u8,
+    pub shot: bool,
+    //Last input sequence the host applied for this player, see prediction.rs
+    pub ack: u16,
+    pub x: i32,
+    pub y: i32,
+    pub angle: u8,
+}
+
+//One entity's change since the snapshot baseline. Only the fields that changed are on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityDelta {
+    pub net_id: u16,
+    pub x: Option<i16>,
+    pub y: Option<i16>,
+    //Entity left the client's area of interest (or the world)
+    pub removed: bool,
+}
+
+impl EntityDelta {
+    pub fn removed(net_id: u16) -> Self {
+        Self { net_id, x: None, y: None, removed: true }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        3 + 2 * (self.x.is_some() as usize + self.y.is_some() as usize)
+    }
+}
+
+//Part of a world snapshot, see snapshot.rs. A snapshot too big for one datagram is split into
+//fragment_count pieces that all share tick and baseline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub baseline: u32,
+    pub fragment: u8,
+    pub fragment_count: u8,
+    pub entities: Vec<EntityDelta>,
+}
+
+//Everything a client needs to create an enemy the host spawned. kind is enemy::EnemyType::to_byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnemySpawn {
+    pub enemy_id: u16,
+    pub kind: u8,
+    pub x: i16,
+    pub y: i16,
+    pub health: i16,
+}
+
+//Health of an enemy after the host applied a hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnemyHealth {
+    pub enemy_id: u16,
+    pub health: i16,
+}
+
+//One row of the lobby list. Colors aren't sent, they follow from player_id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LobbyEntry {
+    pub player_id: u8,
+    pub ready: bool,
+    pub ping_ms: u16,
+    pub name: String,
+}
+
+//Every PlayerInventory field plus the battery charge. Sent with a length in front, so fields added
+//later go on the end and older layouts around it don't move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InventoryState {
+    pub revive_kits: u16,
+    pub max_revive_kits: u16,
+    pub magazine: u16,
+    pub max_magazine: u16,
+    pub reserve: u16,
+    pub max_reserve: u16,
+    pub charge: u16,
+    pub flashlight: bool,
+}
+
+//A rejoining client's own player as the host last had it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeState {
+    pub x: i32,
+    pub y: i32,
+    pub health: i16,
+    //Tenths, so the slow drain without a flashlight isn't rounded away
+    pub sanity_tenths: u16,
+    pub inventory: InventoryState,
+}
+
+//The host's view of one player's vitals, it decides damage, sanity, battery and death for everyone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerStatus {
+    pub player_id: u8,
+    pub health: i16,
+    pub sanity_tenths: u16,
+    pub charge: u16,
+    pub dead: bool,
+    pub flashlight: bool,
+}
+
+//door_id is the door's NetId
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoorState {
+    pub door_id: u16,
+    pub open: bool,
+}
+
+//What a host tells the server browser about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub player_count: u8,
+    pub max_players: u8,
+    //Match already running, anyone joining now spectates
+    pub in_progress: bool,
+    pub map: String,
+}
+
+//Cuts a name down to what fits in MAX_NAME_LEN without splitting a character
+pub fn truncate_name(name: &str) -> String {
+    truncate_text(name, MAX_NAME_LEN)
+}
+
+//Same for any other length limit
+pub fn truncate_text(text: &str, max_len: usize) -> String {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+//Why the host turned a join down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    Full,
+    //Mid match with every spectator slot taken
+    InProgress,
+    //The Join wasn't sealed with a key from our join code
+    BadCode,
+}
+
+impl RejectReason {
+    fn to_byte(self) -> u8 {
+        match self {
+            RejectReason::Full => 0,
+            RejectReason::InProgress => 1,
+            RejectReason::BadCode => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(RejectReason::Full),
+            1 => Some(RejectReason::InProgress),
+            2 => Some(RejectReason::BadCode),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    //Client -> Host
+    //First thing a client sends, before Join. Answered with Challenge.
+    Hello { nonce: [u8; NONCE_LEN] },
+    //token is 0 for a new player, or the one from Accept to take back a slot after losing the connection
+    Join { name: String, token: u64 },
+    Ready { ready: bool },
+    //Echo of a Ping
+    Pong { time_ms: u32 },
+    //inputs are oldest first, the last one has sequence number seq
+    Input { angle: u8, seq: u16, inputs: Vec<u8> },
+    SnapshotAck { tick: u32 },
+    //Dead or late joining, watching this player. The host builds our snapshots around them.
+    Spectate { player_id: u8 },
+
+    //Host -> Client
+    //Both nonces and the join code make the session keys, encrypted says whether bodies are hidden too
+    Challenge { nonce: [u8; NONCE_LEN], encrypted: bool },
+    //A player_id of player_count or more means we joined late and only spectate. level_seed is the
+    //generated level the host plays on, see levelgen.rs, or None for the level file.
+    Start { player_count: u8, player_id: u8, level_seed: Option<u64> },
+    PlayerState(PlayerState),
+    ClockSync { player_count: u8 },
+    Snapshot(Snapshot),
+    Inventory { player_id: u8, inventory: InventoryState },
+    //token lets the client rejoin the same slot if it loses the connection mid match
+    Accept { player_id: u8, player_count: u8, token: u64 },
+    Reject(RejectReason),
+    //Everyone waiting in the lobby, sent regularly until the match starts
+    LobbyState(Vec<LobbyEntry>),
+    //time_ms is the host's clock, the client sends it straight back so the host can measure ping
+    Ping { time_ms: u32 },
+    //The host owns the enemy set, clients only create, hurt and remove enemies when told to
+    EnemySpawns(Vec<EnemySpawn>),
+    EnemyDamage(Vec<EnemyHealth>),
+    EnemyDeaths(Vec<u16>),
+    //Sent after Start to a client that rejoined, along with every enemy and door
+    Resume(ResumeState),
+    DoorStates(Vec<DoorState>),
+    //Every player's vitals, sent each tick. Clients never work these out for themselves.
+    PlayerStatus(Vec<PlayerStatus>),
+    //NetIds of collectibles the host let someone pick up, clients just remove them
+    CollectiblesTaken(Vec<u16>),
+
+    //Server browser -> any host, usually broadcast. Hosts answer with DiscoveryReply.
+    DiscoveryQuery,
+    DiscoveryReply(ServerInfo),
+
+    //Both ways. A client sends its own id when quitting, the host sends the id of whoever left
+    //(HOST_ID means the host itself is shutting down).
+    Leave { player_id: u8 },
+    //Both ways, keeps the connection alive when nothing else is being sent
+    Heartbeat,
+    //Both ways. Clients send their own chat lines and markers, the host relays everyone's to everyone
+    //with the sender's player_id. Each is sent a few times over, id tells the copies apart from a
+    //new one.
+    Chat { player_id: u8, id: u16, text: String },
+    Marker { player_id: u8, id: u16, x: i32, y: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    TooShort,
+    BadMagic,
+    WrongVersion(u8),
+    UnknownType(u8),
+    BadLength { msg_type: u8, len: usize },
+    TooManyEnemies(usize),
+    BadField(u8),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::TooShort => write!(f, "datagram is shorter than the header"),
+            ProtocolError::BadMagic => write!(f, "datagram does not start with the protocol magic"),
+            ProtocolError::WrongVersion(v) => {
+                write!(f, "protocol version {} does not match ours ({})", v, PROTOCOL_VERSION)
+            }
+            ProtocolError::UnknownType(t) => write!(f, "unknown message type {}", t),
+            ProtocolError::BadLength { msg_type, len } => {
+                write!(f, "message type {} has a bad body length of {}", msg_type, len)
+            }
+            ProtocolError::TooManyEnemies(n) => {
+                write!(f, "{} enemies do not fit in one packet (max {})", n, MAX_ENEMIES_PER_PACKET)
+            }
+            ProtocolError::BadField(t) => write!(f, "message type {} has an invalid field", t),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl Message {
+    fn msg_type(&self) -> u8 {
+        match self {
+            Message::Join { .. } => op::JOIN,
+            Message::Hello { .. } => op::HELLO,
+            Message::Challenge { .. } => op::CHALLENGE,
+            Message::Ready { .. } => op::READY,
+            Message::Pong { .. } => op::PONG,
+            Message::LobbyState(_) => op::LOBBY_STATE,
+            Message::Ping { .. } => op::PING,
+            Message::Resume(_) => op::RESUME,
+            Message::DoorStates(_) => op::DOOR_STATES,
+            Message::PlayerStatus(_) => op::PLAYER_STATUS,
+            Message::CollectiblesTaken(_) => op::COLLECTIBLES_TAKEN,
+            Message::DiscoveryQuery => op::DISCOVERY_QUERY,
+            Message::DiscoveryReply(_) => op::DISCOVERY_REPLY,
+            Message::Input { .. } => op::INPUT,
+            Message::Start { .. } => op::START,
+            Message::PlayerState(_) => op::PLAYER_STATE,
+            Message::ClockSync { .. } => op::CLOCK_SYNC,
+            Message::Snapshot(_) => op::SNAPSHOT,
+            Message::SnapshotAck { .. } => op::SNAPSHOT_ACK,
+            Message::Spectate { .. } => op::SPECTATE,
+            Message::Inventory { .. } => op::INVENTORY,
+            Message::Accept { .. } => op::ACCEPT,
+            Message::Reject(_) => op::REJECT,
+            Message::Leave { .. } => op::LEAVE,
+            Message::Heartbeat => op::HEARTBEAT,
+            Message::Chat { .. } => op::CHAT,
+            Message::Marker { .. } => op::MARKER,
+            Message::EnemySpawns(_) => op::ENEMY_SPAWNS,
+            Message::EnemyDamage(_) => op::ENEMY_DAMAGE,
+            Message::EnemyDeaths(_) => op::ENEMY_DEATHS,
+        }
+    }
+
+    //Sent and accepted without a session key. Everything else is sealed, see secure.rs.
+    pub fn is_open(&self) -> bool {
+        is_open_type(self.msg_type())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + 16);
+        out.extend_from_slice(&MAGIC);
+        out.push(PROTOCOL_VERSION);
+        out.push(self.msg_type());
+
+        match self {
+            Message::Join { name, token } => {
+                out.extend_from_slice(&token.to_le_bytes());
+                write_name(&mut out, name);
+            }
+            Message::Hello { nonce } => {
+                out.extend_from_slice(nonce);
+            }
+            Message::Challenge { nonce, encrypted } => {
+                out.extend_from_slice(nonce);
+                out.push(*encrypted as u8);
+            }
+            Message::Ready { ready } => {
+                out.push(*ready as u8);
+            }
+            Message::Ping { time_ms } | Message::Pong { time_ms } => {
+                out.extend_from_slice(&time_ms.to_le_bytes());
+            }
+            Message::Resume(state) => {
+                out.extend_from_slice(&state.x.to_le_bytes());
+                out.extend_from_slice(&state.y.to_le_bytes());
+                out.extend_from_slice(&state.health.to_le_bytes());
+                out.extend_from_slice(&state.sanity_tenths.to_le_bytes());
+                write_inventory(&mut out, &state.inventory);
+            }
+            Message::DoorStates(doors) => {
+                write_list(&mut out, doors, |out, door| {
+                    out.extend_from_slice(&door.door_id.to_le_bytes());
+                    out.push(door.open as u8);
+                });
+            }
+            Message::PlayerStatus(players) => {
+                write_list(&mut out, players, |out, status| {
+                    out.push(status.player_id);
+                    out.extend_from_slice(&status.health.to_le_bytes());
+                    out.extend_from_slice(&status.sanity_tenths.to_le_bytes());
+                    out.extend_from_slice(&status.charge.to_le_bytes());
+                    out.push(status.dead as u8 | (status.flashlight as u8) << 1);
+                });
+            }
+            Message::CollectiblesTaken(ids) => {
+                write_list(&mut out, ids, |out, id| out.extend_from_slice(&id.to_le_bytes()));
+            }
+            Message::DiscoveryQuery => {}
+            Message::DiscoveryReply(info) => {
+                out.extend_from_slice(&[info.player_count, info.max_players, info.in_progress as u8]);
+                write_name(&mut out, &info.name);
+                write_name(&mut out, &info.map);
+            }
+            Message::LobbyState(players) => {
+                out.push(players.len() as u8);
+                for player in players {
+                    out.extend_from_slice(&[player.player_id, player.ready as u8]);
+                    out.extend_from_slice(&player.ping_ms.to_le_bytes());
+                    write_name(&mut out, &player.name);
+                }
+            }
+            Message::Input { angle, seq, inputs } => {
+                out.push(*angle);
+                out.extend_from_slice(&seq.to_le_bytes());
+                out.push(inputs.len() as u8);
+                out.extend_from_slice(inputs);
+            }
+            Message::Start { player_count, player_id, level_seed } => {
+                out.extend_from_slice(&[*player_count, *player_id, level_seed.is_some() as u8]);
+                out.extend_from_slice(&level_seed.unwrap_or(0).to_le_bytes());
+            }
+            Message::PlayerState(state) => {
+                write_player_state(&mut out, state);
+            }
+            Message::ClockSync { player_count } => {
+                out.push(*player_count);
+            }
+            Message::Snapshot(snapshot) => {
+                write_snapshot(&mut out, snapshot);
+            }
+            Message::SnapshotAck { tick } => {
+                out.extend_from_slice(&tick.to_le_bytes());
+            }
+            Message::Inventory { player_id, inventory } => {
+                out.push(*player_id);
+                write_inventory(&mut out, inventory);
+            }
+            Message::Accept { player_id, player_count, token } => {
+                out.extend_from_slice(&[*player_id, *player_count]);
+                out.extend_from_slice(&token.to_le_bytes());
+            }
+            Message::Reject(reason) => {
+                out.push(reason.to_byte());
+            }
+            Message::Leave { player_id } | Message::Spectate { player_id } => {
+                out.push(*player_id);
+            }
+            Message::Heartbeat => {}
+            Message::Chat { player_id, id, text } => {
+                out.push(*player_id);
+                out.extend_from_slice(&id.to_le_bytes());
+                write_text(&mut out, text, MAX_CHAT_LEN);
+            }
+            Message::Marker { player_id, id, x, y } => {
+                out.push(*player_id);
+                out.extend_from_slice(&id.to_le_bytes());
+                out.extend_from_slice(&x.to_le_bytes());
+                out.extend_from_slice(&y.to_le_bytes());
+            }
+            Message::EnemySpawns(spawns) => {
+                write_list(&mut out, spawns, |out, spawn| {
+                    out.extend_from_slice(&spawn.enemy_id.to_le_bytes());
+                    out.push(spawn.kind);
+                    out.extend_from_slice(&spawn.x.to_le_bytes());
+                    out.extend_from_slice(&spawn.y.to_le_bytes());
+                    out.extend_from_slice(&spawn.health.to_le_bytes());
+                });
+            }
+            Message::EnemyDamage(hits) => {
+                write_list(&mut out, hits, |out, hit| {
+                    out.extend_from_slice(&hit.enemy_id.to_le_bytes());
+                    out.extend_from_slice(&hit.health.to_le_bytes());
+                });
+            }
+            Message::EnemyDeaths(ids) => {
+                write_list(&mut out, ids, |out, id| out.extend_from_slice(&id.to_le_bytes()));
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Message, ProtocolError> {
+        let msg_type = peek_type(buf)?;
+        let body = &buf[HEADER_LEN..];
+        let expect = |len: usize| {
+            if body.len() == len {
+                Ok(())
+            } else {
+                Err(ProtocolError::BadLength { msg_type, len: body.len() })
+            }
+        };
+
+        match msg_type {
+            op::JOIN => {
+                let token = body.get(..8).ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+                let token = u64::from_le_bytes(token.try_into().unwrap());
+                let mut pos = 8;
+                let name = read_name(msg_type, body, &mut pos)?;
+                expect(pos)?;
+                Ok(Message::Join { name, token })
+            }
+            op::HELLO => {
+                expect(NONCE_LEN)?;
+                Ok(Message::Hello { nonce: body.try_into().unwrap() })
+            }
+            op::CHALLENGE => {
+                expect(NONCE_LEN + 1)?;
+                Ok(Message::Challenge {
+                    nonce: body[..NONCE_LEN].try_into().unwrap(),
+                    encrypted: body[NONCE_LEN] != 0,
+                })
+            }
+            op::RESUME => {
+                if body.len() < RESUME_HEADER_LEN {
+                    return Err(ProtocolError::BadLength { msg_type, len: body.len() });
+                }
+                let i32_at = |i: usize| i32::from_le_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]]);
+                let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+                let mut pos = RESUME_HEADER_LEN;
+                let inventory = read_inventory(msg_type, body, &mut pos)?;
+                expect(pos)?;
+                Ok(Message::Resume(ResumeState {
+                    x: i32_at(0),
+                    y: i32_at(4),
+                    health: u16_at(8) as i16,
+                    sanity_tenths: u16_at(10),
+                    inventory,
+                }))
+            }
+            op::DOOR_STATES => {
+                let doors = read_list(msg_type, body, DOOR_STATE_LEN, |d| DoorState {
+                    door_id: u16::from_le_bytes([d[0], d[1]]),
+                    open: d[2] != 0,
+                })?;
+                Ok(Message::DoorStates(doors))
+            }
+            op::PLAYER_STATUS => {
+                let players = read_list(msg_type, body, PLAYER_STATUS_LEN, |p| PlayerStatus {
+                    player_id: p[0],
+                    health: i16::from_le_bytes([p[1], p[2]]),
+                    sanity_tenths: u16::from_le_bytes([p[3], p[4]]),
+                    charge: u16::from_le_bytes([p[5], p[6]]),
+                    dead: p[7] & 1 != 0,
+                    flashlight: p[7] & 2 != 0,
+                })?;
+                Ok(Message::PlayerStatus(players))
+            }
+            op::COLLECTIBLES_TAKEN => {
+                let ids = read_list(msg_type, body, 2, |c| u16::from_le_bytes([c[0], c[1]]))?;
+                Ok(Message::CollectiblesTaken(ids))
+            }
+            op::READY => {
+                expect(1)?;
+                Ok(Message::Ready { ready: body[0] != 0 })
+            }
+            op::DISCOVERY_QUERY => {
+                expect(0)?;
+                Ok(Message::DiscoveryQuery)
+            }
+            op::DISCOVERY_REPLY => {
+                let fixed = body.get(..3).ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+                let (player_count, max_players, in_progress) = (fixed[0], fixed[1], fixed[2] != 0);
+                let mut pos = 3;
+                let name = read_name(msg_type, body, &mut pos)?;
+                let map = read_name(msg_type, body, &mut pos)?;
+                expect(pos)?;
+                Ok(Message::DiscoveryReply(ServerInfo { name, player_count, max_players, in_progress, map }))
+            }
+            op::PING | op::PONG => {
+                expect(4)?;
+                let time_ms = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+                Ok(if msg_type == op::PING { Message::Ping { time_ms } } else { Message::Pong { time_ms } })
+            }
+            op::LOBBY_STATE => {
+                let count = *body.first().ok_or(ProtocolError::BadLength { msg_type, len: 0 })?;
+                let mut pos = 1;
+                let mut players = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let fixed = body
+                        .get(pos..pos + 4)
+                        .ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+                    let (player_id, ready, ping_ms) = (fixed[0], fixed[1] != 0, u16::from_le_bytes([fixed[2], fixed[3]]));
+                    pos += 4;
+                    let name = read_name(msg_type, body, &mut pos)?;
+                    players.push(LobbyEntry { player_id, ready, ping_ms, name });
+                }
+                expect(pos)?;
+                Ok(Message::LobbyState(players))
+            }
+            op::INPUT => {
+                if body.len() < 4 {
+                    return Err(ProtocolError::BadLength { msg_type, len: body.len() });
+                }
+                let count = body[3] as usize;
+                if count == 0 || count > MAX_INPUTS_PER_PACKET {
+                    return Err(ProtocolError::BadField(msg_type));
+                }
+                expect(4 + count)?;
+                Ok(Message::Input {
+                    angle: body[0],
+                    seq: u16::from_le_bytes([body[1], body[2]]),
+                    inputs: body[4..].to_vec(),
+                })
+            }
+            op::START => {
+                expect(11)?;
+                let level_seed = match body[2] {
+                    0 => None,
+                    1 => Some(u64::from_le_bytes(body[3..11].try_into().unwrap())),
+                    _ => return Err(ProtocolError::BadField(msg_type)),
+                };
+                Ok(Message::Start { player_count: body[0], player_id: body[1], level_seed })
+            }
+            op::PLAYER_STATE => {
+                expect(PLAYER_STATE_LEN)?;
+                Ok(Message::PlayerState(read_player_state(body)))
+            }
+            op::CLOCK_SYNC => {
+                expect(1)?;
+                Ok(Message::ClockSync { player_count: body[0] })
+            }
+            op::SNAPSHOT => Ok(Message::Snapshot(read_snapshot(msg_type, body)?)),
+            op::SNAPSHOT_ACK => {
+                expect(4)?;
+                Ok(Message::SnapshotAck { tick: u32::from_le_bytes([body[0], body[1], body[2], body[3]]) })
+            }
+            op::INVENTORY => {
+                let mut pos = 1;
+                let inventory = read_inventory(msg_type, body, &mut pos)?;
+                expect(pos)?;
+                Ok(Message::Inventory { player_id: body[0], inventory })
+            }
+            op::ACCEPT => {
+                expect(10)?;
+                let token = u64::from_le_bytes(body[2..10].try_into().unwrap());
+                Ok(Message::Accept { player_id: body[0], player_count: body[1], token })
+            }
+            op::REJECT => {
+                expect(1)?;
+                let reason = RejectReason::from_byte(body[0]).ok_or(ProtocolError::BadField(msg_type))?;
+                Ok(Message::Reject(reason))
+            }
+            op::LEAVE => {
+                expect(1)?;
+                Ok(Message::Leave { player_id: body[0] })
+            }
+            op::SPECTATE => {
+                expect(1)?;
+                Ok(Message::Spectate { player_id: body[0] })
+            }
+            op::HEARTBEAT => {
+                expect(0)?;
+                Ok(Message::Heartbeat)
+            }
+            op::CHAT => {
+                let fixed = body.get(..3).ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+                let (player_id, id) = (fixed[0], u16::from_le_bytes([fixed[1], fixed[2]]));
+                let mut pos = 3;
+                let text = read_text(msg_type, body, &mut pos, MAX_CHAT_LEN)?;
+                expect(pos)?;
+                Ok(Message::Chat { player_id, id, text })
+            }
+            op::MARKER => {
+                expect(MARKER_LEN)?;
+                Ok(Message::Marker {
+                    player_id: body[0],
+                    id: u16::from_le_bytes([body[1], body[2]]),
+                    x: i32::from_le_bytes([body[3], body[4], body[5], body[6]]),
+                    y: i32::from_le_bytes([body[7], body[8], body[9], body[10]]),
+                })
+            }
+            op::ENEMY_SPAWNS => {
+                let spawns = read_list(msg_type, body, ENEMY_SPAWN_LEN, |e| EnemySpawn {
+                    enemy_id: u16::from_le_bytes([e[0], e[1]]),
+                    kind: e[2],
+                    x: i16::from_le_bytes([e[3], e[4]]),
+                    y: i16::from_le_bytes([e[5], e[6]]),
+                    health: i16::from_le_bytes([e[7], e[8]]),
+                })?;
+                Ok(Message::EnemySpawns(spawns))
+            }
+            op::ENEMY_DAMAGE => {
+                let hits = read_list(msg_type, body, ENEMY_HEALTH_LEN, |e| EnemyHealth {
+                    enemy_id: u16::from_le_bytes([e[0], e[1]]),
+                    health: i16::from_le_bytes([e[2], e[3]]),
+                })?;
+                Ok(Message::EnemyDamage(hits))
+            }
+            op::ENEMY_DEATHS => {
+                let ids = read_list(msg_type, body, 2, |e| u16::from_le_bytes([e[0], e[1]]))?;
+                Ok(Message::EnemyDeaths(ids))
+            }
+            _ => Err(ProtocolError::UnknownType(msg_type)),
+        }
+    }
+}
+
+//Checks the header and returns the message type, without looking at the body
+pub fn peek_type(buf: &[u8]) -> Result<u8, ProtocolError> {
+    if buf.len() < HEADER_LEN {
+        return Err(ProtocolError::TooShort);
+    }
+    if buf[0..2] != MAGIC {
+        return Err(ProtocolError::BadMagic);
+    }
+    if buf[2] != PROTOCOL_VERSION {
+        return Err(ProtocolError::WrongVersion(buf[2]));
+    }
+    Ok(buf[3])
+}
+
+//The handshake, turning a join down and the server browser work before there is a key
+pub fn is_open_type(msg_type: u8) -> bool {
+    matches!(msg_type, op::HELLO | op::CHALLENGE | op::REJECT | op::DISCOVERY_QUERY | op::DISCOVERY_REPLY)
+}
+
+const PLAYER_STATE_LEN: usize = 13;
+const ENEMY_SPAWN_LEN: usize = 9;
+const ENEMY_HEALTH_LEN: usize = 4;
+//Position, health and sanity, the inventory follows
+const RESUME_HEADER_LEN: usize = 12;
+//Fields this version knows about, newer ones may send more
+const INVENTORY_STATE_LEN: usize = 15;
+const DOOR_STATE_LEN: usize = 3;
+const PLAYER_STATUS_LEN: usize = 8;
+const MARKER_LEN: usize = 11;
+//tick, baseline, fragment, fragment_count and the u16 entity count
+pub const SNAPSHOT_HEADER_LEN: usize = 12;
+
+//Flag bits in front of each snapshot entity
+const DELTA_X: u8 = 1;
+const DELTA_Y: u8 = 2;
+const DELTA_REMOVED: u8 = 4;
+
+fn write_snapshot(out: &mut Vec<u8>, snapshot: &Snapshot) {
+    out.extend_from_slice(&snapshot.tick.to_le_bytes());
+    out.extend_from_slice(&snapshot.baseline.to_le_bytes());
+    out.extend_from_slice(&[snapshot.fragment, snapshot.fragment_count]);
+    out.extend_from_slice(&(snapshot.entities.len() as u16).to_le_bytes());
+    for entity in &snapshot.entities {
+        out.extend_from_slice(&entity.net_id.to_le_bytes());
+        let flags = if entity.removed {
+            DELTA_REMOVED
+        } else {
+            (entity.x.is_some() as u8 * DELTA_X) | (entity.y.is_some() as u8 * DELTA_Y)
+        };
+        out.push(flags);
+        if !entity.removed {
+            if let Some(x) = entity.x {
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            if let Some(y) = entity.y {
+                out.extend_from_slice(&y.to_le_bytes());
+            }
+        }
+    }
+}
+
+fn read_snapshot(msg_type: u8, body: &[u8]) -> Result<Snapshot, ProtocolError> {
+    let bad_length = ProtocolError::BadLength { msg_type, len: body.len() };
+    if body.len() < SNAPSHOT_HEADER_LEN {
+        return Err(bad_length);
+    }
+    let tick = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+    let baseline = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+    let (fragment, fragment_count) = (body[8], body[9]);
+    let count = u16::from_le_bytes([body[10], body[11]]) as usize;
+    if fragment >= fragment_count {
+        return Err(ProtocolError::BadField(msg_type));
+    }
+
+    let mut rest = &body[SNAPSHOT_HEADER_LEN..];
+    let mut take = |n: usize| -> Result<&[u8], ProtocolError> {
+        if rest.len() < n {
+            return Err(ProtocolError::BadLength { msg_type, len: body.len() });
+        }
+        let (head, tail) = rest.split_at(n);
+        rest = tail;
+        Ok(head)
+    };
+    let mut entities = Vec::with_capacity(count.min(MAX_DATAGRAM / 3));
+    for _ in 0..count {
+        let head = take(3)?;
+        let net_id = u16::from_le_bytes([head[0], head[1]]);
+        let flags = head[2];
+        if flags & !(DELTA_X | DELTA_Y | DELTA_REMOVED) != 0
+            || (flags & DELTA_REMOVED != 0 && flags != DELTA_REMOVED)
+        {
+            return Err(ProtocolError::BadField(msg_type));
+        }
+        let mut field = |bit: u8| -> Result<Option<i16>, ProtocolError> {
+            if flags & bit == 0 {
+                return Ok(None);
+            }
+            let b = take(2)?;
+            Ok(Some(i16::from_le_bytes([b[0], b[1]])))
+        };
+        let x = field(DELTA_X)?;
+        let y = field(DELTA_Y)?;
+        entities.push(EntityDelta { net_id, x, y, removed: flags == DELTA_REMOVED });
+    }
+    if !rest.is_empty() {
+        return Err(bad_length);
+    }
+    Ok(Snapshot { tick, baseline, fragment, fragment_count, entities })
+}
+
+//Enemy lists are a count byte followed by fixed size entries. Anything past the limit is dropped,
+//the sender is expected to split.
+fn write_list<T>(out: &mut Vec<u8>, items: &[T], write: impl Fn(&mut Vec<u8>, &T)) {
+    let count = items.len().min(MAX_ENEMIES_PER_PACKET);
+    out.push(count as u8);
+    for item in &items[..count] {
+        write(out, item);
+    }
+}
+
+fn read_list<T>(
+    msg_type: u8,
+    body: &[u8],
+    item_len: usize,
+    read: impl Fn(&[u8]) -> T,
+) -> Result<Vec<T>, ProtocolError> {
+    if body.is_empty() {
+        return Err(ProtocolError::BadLength { msg_type, len: 0 });
+    }
+    let count = body[0] as usize;
+    if count > MAX_ENEMIES_PER_PACKET {
+        return Err(ProtocolError::TooManyEnemies(count));
+    }
+    if body.len() != 1 + count * item_len {
+        return Err(ProtocolError::BadLength { msg_type, len: body.len() });
+    }
+    Ok(body[1..].chunks_exact(item_len).map(read).collect())
+}
+
+fn write_inventory(out: &mut Vec<u8>, inventory: &InventoryState) {
+    out.push(INVENTORY_STATE_LEN as u8);
+    for value in [
+        inventory.revive_kits,
+        inventory.max_revive_kits,
+        inventory.magazine,
+        inventory.max_magazine,
+        inventory.reserve,
+        inventory.max_reserve,
+        inventory.charge,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.push(inventory.flashlight as u8);
+}
+
+//Reads the inventory starting at pos and moves pos past it. Bytes past the fields we know are skipped.
+fn read_inventory(msg_type: u8, body: &[u8], pos: &mut usize) -> Result<InventoryState, ProtocolError> {
+    let len = *body.get(*pos).ok_or(ProtocolError::BadLength { msg_type, len: body.len() })? as usize;
+    let fields = body
+        .get(*pos + 1..*pos + 1 + len)
+        .filter(|fields| fields.len() >= INVENTORY_STATE_LEN)
+        .ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+    *pos += 1 + len;
+    let u16_at = |i: usize| u16::from_le_bytes([fields[i * 2], fields[i * 2 + 1]]);
+    Ok(InventoryState {
+        revive_kits: u16_at(0),
+        max_revive_kits: u16_at(1),
+        magazine: u16_at(2),
+        max_magazine: u16_at(3),
+        reserve: u16_at(4),
+        max_reserve: u16_at(5),
+        charge: u16_at(6),
+        flashlight: fields[14] != 0,
+    })
+}
+
+//Length prefixed UTF-8, never more than MAX_NAME_LEN bytes
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_text(out, name, MAX_NAME_LEN);
+}
+
+fn read_name(msg_type: u8, body: &[u8], pos: &mut usize) -> Result<String, ProtocolError> {
+    read_text(msg_type, body, pos, MAX_NAME_LEN)
+}
+
+//max_len has to fit in the length byte
+fn write_text(out: &mut Vec<u8>, text: &str, max_len: usize) {
+    let text = truncate_text(text, max_len);
+    out.push(text.len() as u8);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn read_text(msg_type: u8, body: &[u8], pos: &mut usize, max_len: usize) -> Result<String, ProtocolError> {
+    let len = *body.get(*pos).ok_or(ProtocolError::BadLength { msg_type, len: body.len() })? as usize;
+    if len > max_len {
+        return Err(ProtocolError::BadField(msg_type));
+    }
+    let bytes = body
+        .get(*pos + 1..*pos + 1 + len)
+        .ok_or(ProtocolError::BadLength { msg_type, len: body.len() })?;
+    *pos += 1 + len;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::BadField(msg_type))
+}
+
+fn write_player_state(out: &mut Vec<u8>, state: &PlayerState) {
+    out.push(state.player_id);
+    out.push(state.shot as u8);
+    out.extend_from_slice(&state.ack.to_le_bytes());
+    out.extend_from_slice(&state.x.to_le_bytes());
+    out.extend_from_slice(&state.y.to_le_bytes());
+    out.push(state.angle);
+}
+
+fn read_player_state(body: &[u8]) -> PlayerState {
+    PlayerState {
+        player_id: body[0],
+        shot: body[1] != 0,
+        ack: u16::from_le_bytes([body[2], body[3]]),
+        x: i32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+        y: i32::from_le_bytes([body[8], body[9], body[10], body[11]]),
+        angle: body[12],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(msg: Message) {
+        let bytes = msg.to_bytes();
+        assert_eq!(Message::from_bytes(&bytes), Ok(msg));
+    }
+
+    fn sample_state() -> PlayerState {
+        PlayerState {
+            player_id: 3,
+            shot: true,
+            ack: 65_000,
+            x: -2688,
+            y: 2944,
+            angle: (-15i8) as u8,
+        }
+    }
+
+    #[test]
+    fn join_round_trip() {
+        round_trip(Message::Join { name: "Gordon".to_string(), token: 0 });
+        round_trip(Message::Join { name: String::new(), token: u64::MAX - 7 });
+    }
+
+    #[test]
+    fn long_names_are_cut_on_a_character_boundary() {
+        let bytes = Message::Join { name: "ééééééééé".to_string(), token: 0 }.to_bytes();
+        assert_eq!(Message::from_bytes(&bytes), Ok(Message::Join { name: "éééééééé".to_string(), token: 0 }));
+        assert_eq!(truncate_name("short"), "short");
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let mut bytes = Message::Join { name: "ab".to_string(), token: 0 }.to_bytes();
+        bytes[HEADER_LEN + 9] = 0xFF;
+        assert_eq!(Message::from_bytes(&bytes), Err(ProtocolError::BadField(bytes[3])));
+        bytes[HEADER_LEN + 8] = 3;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn lobby_round_trip() {
+        round_trip(Message::Ready { ready: true });
+        round_trip(Message::Ping { time_ms: 123_456 });
+        round_trip(Message::Pong { time_ms: u32::MAX });
+        round_trip(Message::LobbyState(vec![]));
+        round_trip(Message::LobbyState(vec![
+            LobbyEntry { player_id: 0, ready: true, ping_ms: 0, name: "Host".to_string() },
+            LobbyEntry { player_id: 3, ready: false, ping_ms: 512, name: "Amyia".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn discovery_round_trip() {
+        round_trip(Message::DiscoveryQuery);
+        round_trip(Message::DiscoveryReply(ServerInfo {
+            name: "Gordon".to_string(),
+            player_count: 2,
+            max_players: 4,
+            in_progress: true,
+            map: "warehouse".to_string(),
+        }));
+        let mut bytes = Message::DiscoveryQuery.to_bytes();
+        bytes.push(0);
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn rejects_truncated_lobby_state() {
+        let mut bytes = Message::LobbyState(vec![
+            LobbyEntry { player_id: 1, ready: true, ping_ms: 20, name: "Gordon".to_string() },
+        ])
+        .to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+        bytes[HEADER_LEN] = 0;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn input_round_trip() {
+        round_trip(Message::Input { angle: 31, seq: 300, inputs: vec![0b1010_0010] });
+        round_trip(Message::Input { angle: 0, seq: u16::MAX, inputs: vec![0; MAX_INPUTS_PER_PACKET] });
+    }
+
+    #[test]
+    fn rejects_bad_input_counts() {
+        let mut bytes = Message::Input { angle: 0, seq: 1, inputs: vec![16, 16] }.to_bytes();
+        bytes[HEADER_LEN + 3] = 3;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+        bytes[HEADER_LEN + 3] = 0;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadField(_))));
+        bytes[HEADER_LEN + 3] = (MAX_INPUTS_PER_PACKET + 1) as u8;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadField(_))));
+    }
+
+    #[test]
+    fn start_round_trip() {
+        round_trip(Message::Start { player_count: 4, player_id: 2, level_seed: None });
+        round_trip(Message::Start { player_count: 1, player_id: 0, level_seed: Some(u64::MAX - 7) });
+        let mut bytes = Message::Start { player_count: 1, player_id: 0, level_seed: None }.to_bytes();
+        bytes[HEADER_LEN + 2] = 2;
+        assert_eq!(Message::from_bytes(&bytes), Err(ProtocolError::BadField(op::START)));
+    }
+
+    #[test]
+    fn player_state_round_trip() {
+        round_trip(Message::PlayerState(sample_state()));
+    }
+
+    #[test]
+    fn clock_sync_round_trip() {
+        round_trip(Message::ClockSync { player_count: 2 });
+    }
+
+    fn sample_snapshot() -> Snapshot {
+        Snapshot {
+            tick: 70_000,
+            baseline: 69_990,
+            fragment: 1,
+            fragment_count: 3,
+            entities: vec![
+                EntityDelta::removed(4),
+                EntityDelta { net_id: 300, x: Some(-3168), y: Some(3168), removed: false },
+                EntityDelta { net_id: 60_000, x: None, y: Some(-1), removed: false },
+                EntityDelta { net_id: 2, x: Some(12), y: None, removed: false },
+            ],
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        round_trip(Message::Snapshot(Snapshot { entities: vec![], ..sample_snapshot() }));
+        round_trip(Message::Snapshot(sample_snapshot()));
+    }
+
+    #[test]
+    fn snapshot_only_carries_changed_fields() {
+        let full = EntityDelta { net_id: 1, x: Some(1), y: Some(1), removed: false };
+        let snapshot = Snapshot { entities: vec![full, EntityDelta::removed(2)], ..sample_snapshot() };
+        let bytes = Message::Snapshot(snapshot.clone()).to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + SNAPSHOT_HEADER_LEN + full.encoded_len() + 3);
+    }
+
+    #[test]
+    fn snapshot_ack_round_trip() {
+        round_trip(Message::SnapshotAck { tick: 123_456 });
+    }
+
+    #[test]
+    fn enemy_spawns_round_trip() {
+        round_trip(Message::EnemySpawns(vec![]));
+        round_trip(Message::EnemySpawns(vec![
+            EnemySpawn { enemy_id: 1, kind: 0, x: -3168, y: 3168, health: 5 },
+            EnemySpawn { enemy_id: 1000, kind: 2, x: 0, y: -64, health: 10 },
+        ]));
+    }
+
+    #[test]
+    fn enemy_damage_round_trip() {
+        round_trip(Message::EnemyDamage(vec![
+            EnemyHealth { enemy_id: 3, health: 2 },
+            EnemyHealth { enemy_id: 4, health: -5 },
+        ]));
+    }
+
+    #[test]
+    fn enemy_deaths_round_trip() {
+        round_trip(Message::EnemyDeaths(vec![]));
+        round_trip(Message::EnemyDeaths(vec![1, 2, 255, 65_535]));
+    }
+
+    fn inventory() -> InventoryState {
+        InventoryState {
+            revive_kits: 1,
+            max_revive_kits: 3,
+            magazine: 300,
+            max_magazine: 300,
+            reserve: 1000,
+            max_reserve: 65_535,
+            charge: 500,
+            flashlight: true,
+        }
+    }
+
+    #[test]
+    fn inventory_round_trip() {
+        round_trip(Message::Inventory { player_id: 1, inventory: InventoryState::default() });
+        //Everything above 255 used to be cut down to a byte
+        round_trip(Message::Inventory { player_id: 1, inventory: inventory() });
+    }
+
+    #[test]
+    fn inventory_skips_fields_it_does_not_know() {
+        let mut bytes = Message::Inventory { player_id: 2, inventory: inventory() }.to_bytes();
+        bytes[HEADER_LEN + 1] += 3;
+        bytes.extend_from_slice(&[7, 7, 7]);
+        assert_eq!(Message::from_bytes(&bytes), Ok(Message::Inventory { player_id: 2, inventory: inventory() }));
+    }
+
+    #[test]
+    fn rejects_short_inventory() {
+        let mut bytes = Message::Inventory { player_id: 2, inventory: inventory() }.to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+        //A length smaller than the fields we need is just as bad, even if the bytes are there
+        let mut bytes = Message::Inventory { player_id: 2, inventory: inventory() }.to_bytes();
+        bytes[HEADER_LEN + 1] -= 1;
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn accept_round_trip() {
+        round_trip(Message::Accept { player_id: 3, player_count: 4, token: 0x0123_4567_89AB_CDEF });
+    }
+
+    #[test]
+    fn resume_round_trip() {
+        round_trip(Message::Resume(ResumeState {
+            x: -2688,
+            y: 3104,
+            health: -5,
+            sanity_tenths: 999,
+            inventory: inventory(),
+        }));
+        round_trip(Message::DoorStates(vec![]));
+        round_trip(Message::DoorStates(vec![
+            DoorState { door_id: 1216, open: true },
+            DoorState { door_id: 65_535, open: false },
+        ]));
+        let mut bytes = Message::Resume(ResumeState {
+            x: 0,
+            y: 0,
+            health: 100,
+            sanity_tenths: 1000,
+            inventory: InventoryState::default(),
+        })
+        .to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn player_status_round_trip() {
+        round_trip(Message::PlayerStatus(vec![]));
+        round_trip(Message::PlayerStatus(vec![
+            PlayerStatus { player_id: 0, health: 100, sanity_tenths: 1000, charge: 500, dead: false, flashlight: true },
+            PlayerStatus { player_id: 3, health: 0, sanity_tenths: 0, charge: 0, dead: true, flashlight: false },
+        ]));
+        let mut bytes = Message::PlayerStatus(vec![PlayerStatus {
+            player_id: 1,
+            health: 40,
+            sanity_tenths: 123,
+            charge: 7,
+            dead: false,
+            flashlight: false,
+        }])
+        .to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn collectibles_taken_round_trip() {
+        round_trip(Message::CollectiblesTaken(vec![]));
+        round_trip(Message::CollectiblesTaken(vec![0, 417, u16::MAX]));
+        let mut bytes = Message::CollectiblesTaken(vec![3, 4]).to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn chat_and_marker_round_trip() {
+        round_trip(Message::Chat { player_id: 2, id: 40_000, text: "battery by the door".to_string() });
+        round_trip(Message::Chat { player_id: 0, id: 0, text: String::new() });
+        round_trip(Message::Marker { player_id: 3, id: 7, x: -2688, y: 100_000 });
+
+        let long = "ä".repeat(MAX_CHAT_LEN);
+        let bytes = Message::Chat { player_id: 1, id: 1, text: long }.to_bytes();
+        let Ok(Message::Chat { text, .. }) = Message::from_bytes(&bytes) else {
+            panic!("long chat line did not decode");
+        };
+        assert_eq!(text, "ä".repeat(MAX_CHAT_LEN / 2));
+
+        let mut bytes = Message::Marker { player_id: 0, id: 1, x: 0, y: 0 }.to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn reject_round_trip() {
+        round_trip(Message::Reject(RejectReason::Full));
+        round_trip(Message::Reject(RejectReason::InProgress));
+    }
+
+    #[test]
+    fn leave_round_trip() {
+        round_trip(Message::Leave { player_id: 0 });
+        round_trip(Message::Leave { player_id: 2 });
+        round_trip(Message::Leave { player_id: HOST_ID });
+    }
+
+    #[test]
+    fn spectate_round_trip() {
+        round_trip(Message::Spectate { player_id: 3 });
+        let mut bytes = Message::Spectate { player_id: 3 }.to_bytes();
+        bytes.push(0);
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn handshake_round_trip() {
+        round_trip(Message::Hello { nonce: [7; NONCE_LEN] });
+        round_trip(Message::Challenge { nonce: [0xA5; NONCE_LEN], encrypted: true });
+        round_trip(Message::Challenge { nonce: [0; NONCE_LEN], encrypted: false });
+        round_trip(Message::Reject(RejectReason::BadCode));
+        let mut bytes = Message::Hello { nonce: [7; NONCE_LEN] }.to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+        //Only what has to work before there is a key goes unsealed
+        assert!(Message::Hello { nonce: [0; NONCE_LEN] }.is_open());
+        assert!(Message::DiscoveryQuery.is_open());
+        assert!(!Message::Join { name: String::new(), token: 0 }.is_open());
+        assert!(!Message::Heartbeat.is_open());
+    }
+
+    #[test]
+    fn heartbeat_round_trip() {
+        round_trip(Message::Heartbeat);
+    }
+
+    #[test]
+    fn rejects_unknown_reject_reason() {
+        let mut bytes = Message::Reject(RejectReason::Full).to_bytes();
+        bytes[HEADER_LEN] = 7;
+        assert_eq!(Message::from_bytes(&bytes), Err(ProtocolError::BadField(bytes[3])));
+    }
+
+    #[test]
+    fn positions_are_little_endian() {
+        let bytes = Message::PlayerState(PlayerState { x: 1, y: -2, ..sample_state() }).to_bytes();
+        assert_eq!(&bytes[HEADER_LEN + 4..HEADER_LEN + 8], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[HEADER_LEN + 8..HEADER_LEN + 12], &[0xFE, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn rejects_short_packets() {
+        assert_eq!(Message::from_bytes(&[]), Err(ProtocolError::TooShort));
+        assert_eq!(Message::from_bytes(&[b'U', b'T', PROTOCOL_VERSION]), Err(ProtocolError::TooShort));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        //The old unversioned join byte
+        assert_eq!(Message::from_bytes(&[255, 0, 0, 0]), Err(ProtocolError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_wrong_version() {
+        let mut bytes = Message::Heartbeat.to_bytes();
+        bytes[2] = PROTOCOL_VERSION + 1;
+        assert_eq!(Message::from_bytes(&bytes), Err(ProtocolError::WrongVersion(PROTOCOL_VERSION + 1)));
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        let bytes = [MAGIC[0], MAGIC[1], PROTOCOL_VERSION, 200];
+        assert_eq!(Message::from_bytes(&bytes), Err(ProtocolError::UnknownType(200)));
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_bodies() {
+        let mut bytes = Message::PlayerState(sample_state()).to_bytes();
+        bytes.pop();
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+
+        let mut bytes = Message::Start { player_count: 2, player_id: 1, level_seed: None }.to_bytes();
+        bytes.push(0);
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+    }
+
+    #[test]
+    fn rejects_enemy_count_mismatch() {
+        let mut bytes = Message::EnemyDeaths(vec![1]).to_bytes();
+        bytes[HEADER_LEN] = 2;
+        assert!(matches!(Message::from_bytes(&bytes), Err(ProtocolError::BadLength { .. })));
+
+        bytes[HEADER_LEN] = (MAX_ENEMIES_PER_PACKET + 1) as u8;
+        assert_eq!(
+            Message::from_bytes(&bytes),
+            Err(ProtocolError::TooManyEnemies(MAX_ENEMIES_PER_PACKET + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let bytes = Message::Snapshot(sample_snapshot()).to_bytes();
+        //Entity count says there is one more entity than there is
+        let mut short = bytes.clone();
+        short[HEADER_LEN + 10] += 1;
+        assert!(matches!(Message::from_bytes(&short), Err(ProtocolError::BadLength { .. })));
+        //Trailing garbage
+        let mut long = bytes.clone();
+        long.push(0);
+        assert!(matches!(Message::from_bytes(&long), Err(ProtocolError::BadLength { .. })));
+        //Fragment index past the count
+        let mut fragment = bytes.clone();
+        fragment[HEADER_LEN + 8] = 3;
+        assert_eq!(Message::from_bytes(&fragment), Err(ProtocolError::BadField(op::SNAPSHOT)));
+        //Removed entities don't carry fields
+        let mut flags = bytes;
+        flags[HEADER_LEN + SNAPSHOT_HEADER_LEN + 2] = 4 | 1;
+        assert_eq!(Message::from_bytes(&flags), Err(ProtocolError::BadField(op::SNAPSHOT)));
+    }
+}
@@ -0,0 +1,767 @@
+use crate::{
+    AssignedType, GameState, LogicType,
+    camera::GameCamera,
+    config::Config,
+    level::{CurrentLevel, Level, clear_world},
+    lobby::{LobbyRoster, PlayerInfo},
+    net_control::{Local, NetControl, Network, PlayerType},
+    player::{InputsApplied, LocalInput, Player, player_movement, read_local_input},
+    protocol::LobbyEntry,
+    server::type_equals_host,
+    sim::{SimSet, SimTick, TICK_RATE},
+};
+use bevy::app::FixedMain;
+use bevy::prelude::*;
+use std::f32::consts;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Replays. With --record the host writes the level and who is playing to a file when a match       ///
+///   starts, then every tick the inputs it moved each player with: the WASD00L0 bytes the clients     ///
+///   sent, its own keys and click, and where everyone was aiming. The simulation is deterministic     ///
+///   on a fixed tick, so --replay runs the same ticks from the same inputs and the match plays out    ///
+///   again. Space pauses, up/down change the speed, left/right seek, F picks who the camera follows   ///
+///   and WASD moves it freely.                                                                        ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+const MAGIC: &[u8; 4] = b"UTRP";
+const FORMAT_VERSION: u8 = 1;
+//Often enough that a crash loses about a second of the match
+const FLUSH_TICKS: u32 = TICK_RATE as u32;
+
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1., 2., 4., 8.];
+const NORMAL_SPEED: usize = 2;
+const SEEK_TICKS: u32 = 5 * TICK_RATE as u32;
+//Seeking runs ticks as fast as it can, this many a frame keeps the window responding
+const SEEK_TICKS_PER_FRAME: u32 = 256;
+const FREE_CAMERA_SPEED: f32 = 800.;
+
+//Someone in the match. The host's own player is moved by LocalInput, everyone else by their queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayPlayer {
+    pub player_id: u8,
+    pub host: bool,
+    pub name: String,
+}
+
+//What one player was moved with in one tick. Usually one input, none while they are dead or their
+//inputs are late, two when the host caught up on them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickInputs {
+    pub player_id: u8,
+    pub angle: u8,
+    pub inputs: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TickRecord {
+    pub tick: u32,
+    //Players that left the match before this tick
+    pub left: Vec<u8>,
+    pub players: Vec<TickInputs>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub level_name: String,
+    pub level: Level,
+    pub players: Vec<ReplayPlayer>,
+    //One per tick, in order
+    pub ticks: Vec<TickRecord>,
+}
+
+impl Replay {
+    pub fn last_tick(&self) -> u32 {
+        self.ticks.last().map_or(0, |record| record.tick)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(PathBuf, io::Error),
+    NotAReplay,
+    WrongVersion(u8),
+    WrongTickRate(u16),
+    Truncated,
+    BadLevel(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::WrongVersion(v) => {
+                write!(f, "replay format {} does not match ours ({})", v, FORMAT_VERSION)
+            }
+            ReplayError::WrongTickRate(rate) => {
+                write!(f, "replay was recorded at {} ticks a second, we run at {}", rate, TICK_RATE)
+            }
+            ReplayError::Truncated => write!(f, "replay header is cut off"),
+            ReplayError::BadLevel(e) => write!(f, "replay has a bad level: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+//Magic, version and tick rate, then the level as RON so the replay doesn't depend on the level
+//files staying the same, then the players
+fn write_header(out: &mut Vec<u8>, level_name: &str, level: &Level, players: &[ReplayPlayer]) -> Result<(), ReplayError> {
+    let level = ron::to_string(level).map_err(|e| ReplayError::BadLevel(e.to_string()))?;
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    out.extend_from_slice(&(TICK_RATE as u16).to_le_bytes());
+    write_bytes(out, level_name.as_bytes());
+    out.extend_from_slice(&(level.len() as u32).to_le_bytes());
+    out.extend_from_slice(level.as_bytes());
+    out.push(players.len() as u8);
+    for player in players {
+        out.push(player.player_id);
+        out.push(player.host as u8);
+        write_bytes(out, player.name.as_bytes());
+    }
+    Ok(())
+}
+
+fn write_record(out: &mut Vec<u8>, record: &TickRecord) {
+    out.extend_from_slice(&record.tick.to_le_bytes());
+    write_bytes(out, &record.left);
+    out.push(record.players.len() as u8);
+    for player in &record.players {
+        out.push(player.player_id);
+        out.push(player.angle);
+        write_bytes(out, &player.inputs);
+    }
+}
+
+//Length byte then the bytes, names and input lists are both well under 256
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let len = bytes.len().min(u8::MAX as usize);
+    out.push(len as u8);
+    out.extend_from_slice(&bytes[..len]);
+}
+
+//Takes len bytes starting at pos and moves pos past them
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], ReplayError> {
+    let taken = bytes.get(*pos..*pos + len).ok_or(ReplayError::Truncated)?;
+    *pos += len;
+    Ok(taken)
+}
+
+fn take_u8(bytes: &[u8], pos: &mut usize) -> Result<u8, ReplayError> {
+    Ok(take(bytes, pos, 1)?[0])
+}
+
+fn take_bytes<'a>(bytes: &'a [u8], pos: &mut usize) -> Result<&'a [u8], ReplayError> {
+    let len = take_u8(bytes, pos)? as usize;
+    take(bytes, pos, len)
+}
+
+fn take_string(bytes: &[u8], pos: &mut usize) -> Result<String, ReplayError> {
+    Ok(String::from_utf8_lossy(take_bytes(bytes, pos)?).into_owned())
+}
+
+fn read_record(bytes: &[u8], pos: &mut usize) -> Result<TickRecord, ReplayError> {
+    let tick = u32::from_le_bytes(take(bytes, pos, 4)?.try_into().unwrap());
+    let left = take_bytes(bytes, pos)?.to_vec();
+    let count = take_u8(bytes, pos)?;
+    let mut players = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let player_id = take_u8(bytes, pos)?;
+        let angle = take_u8(bytes, pos)?;
+        let inputs = take_bytes(bytes, pos)?.to_vec();
+        players.push(TickInputs { player_id, angle, inputs });
+    }
+    Ok(TickRecord { tick, left, players })
+}
+
+pub fn parse_replay(bytes: &[u8]) -> Result<Replay, ReplayError> {
+    let mut pos = 0;
+    if take(bytes, &mut pos, MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(ReplayError::NotAReplay);
+    }
+    let version = take_u8(bytes, &mut pos)?;
+    if version != FORMAT_VERSION {
+        return Err(ReplayError::WrongVersion(version));
+    }
+    let tick_rate = u16::from_le_bytes(take(bytes, &mut pos, 2)?.try_into().unwrap());
+    if tick_rate != TICK_RATE as u16 {
+        return Err(ReplayError::WrongTickRate(tick_rate));
+    }
+    let level_name = take_string(bytes, &mut pos)?;
+    let level_len = u32::from_le_bytes(take(bytes, &mut pos, 4)?.try_into().unwrap());
+    let level: Level = ron::de::from_bytes(take(bytes, &mut pos, level_len as usize)?)
+        .map_err(|e| ReplayError::BadLevel(e.to_string()))?;
+    level.validate().map_err(|e| ReplayError::BadLevel(e.to_string()))?;
+    let mut players = Vec::new();
+    for _ in 0..take_u8(bytes, &mut pos)? {
+        let player_id = take_u8(bytes, &mut pos)?;
+        let host = take_u8(bytes, &mut pos)? != 0;
+        let name = take_string(bytes, &mut pos)?;
+        players.push(ReplayPlayer { player_id, host, name });
+    }
+
+    //A host that crashed leaves half a record on the end, everything before it still plays
+    let mut ticks = Vec::new();
+    while pos < bytes.len() {
+        match read_record(bytes, &mut pos) {
+            Ok(record) => ticks.push(record),
+            Err(_) => {
+                warn!("Replay ends part way through tick {}", ticks.len() + 1);
+                break;
+            }
+        }
+    }
+    Ok(Replay { level_name, level, players, ticks })
+}
+
+pub fn load_replay(path: &Path) -> Result<Replay, ReplayError> {
+    let bytes = std::fs::read(path).map_err(|e| ReplayError::Io(path.to_path_buf(), e))?;
+    parse_replay(&bytes)
+}
+
+//The host can play several matches in one run, the second goes to "name-2.ext" and so on
+fn numbered(path: &Path, count: u32) -> PathBuf {
+    if count <= 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, count, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, count),
+    };
+    path.with_file_name(name)
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RecordedMatches>()
+            //Recording, on whichever host was started with --record
+            .add_systems(
+                OnEnter(GameState::Playing),
+                start_recording
+                    .run_if(type_equals_host)
+                    .run_if(|config: Res<Config>| config.record.is_some()),
+            )
+            .add_systems(
+                FixedUpdate,
+                record_tick
+                    .in_set(SimSet::Players)
+                    .after(player_movement)
+                    .run_if(resource_exists::<Recorder>),
+            )
+            .add_systems(OnExit(GameState::Playing), stop_recording.run_if(resource_exists::<Recorder>))
+            //Playback
+            .add_systems(PostStartup, open_replay.run_if(|config: Res<Config>| config.replay.is_some()))
+            .add_systems(OnEnter(GameState::Replay), (clear_world, start_playback).chain())
+            .add_systems(
+                FixedUpdate,
+                feed_replay
+                    .in_set(SimSet::Players)
+                    .after(read_local_input)
+                    .before(player_movement)
+                    .run_if(resource_exists::<Playback>),
+            )
+            .add_systems(OnEnter(GameState::Playing), setup_replay_hud.run_if(resource_exists::<Playback>))
+            .add_systems(OnExit(GameState::Playing), cleanup_replay_hud)
+            .add_systems(
+                Update,
+                //A seek back leaves Playing before seek_forward would see the old tick
+                (seek_forward, playback_controls, apply_playback_time, replay_camera, show_replay_hud)
+                    .chain()
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(resource_exists::<Playback>),
+            )
+            .add_systems(OnEnter(GameState::Menu), stop_playback.run_if(resource_exists::<Playback>));
+    }
+}
+
+//Matches recorded so far this run, for numbered
+#[derive(Resource, Default)]
+struct RecordedMatches(u32);
+
+#[derive(Resource)]
+struct Recorder {
+    path: PathBuf,
+    file: BufWriter<File>,
+    //Who was still in the match last tick
+    players: Vec<u8>,
+    ticks: u32,
+}
+
+fn start_recording(
+    mut commands: Commands,
+    config: Res<Config>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    mut matches: ResMut<RecordedMatches>,
+    players: Query<(&NetControl, &PlayerInfo)>,
+) {
+    let (Some(path), Some(level)) = (&config.record, levels.get(&current_level.handle)) else {
+        return;
+    };
+    matches.0 += 1;
+    let path = numbered(path, matches.0);
+    //In the order they were spawned, playback spawns them the same way
+    let players: Vec<ReplayPlayer> = players
+        .iter()
+        .map(|(control, info)| ReplayPlayer {
+            player_id: control.player_id,
+            host: control.get_type() == PlayerType::Local,
+            name: info.name.clone(),
+        })
+        .collect();
+
+    let mut header = Vec::new();
+    let written = write_header(&mut header, &current_level.name, level, &players)
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            let mut file = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+            file.write_all(&header).map_err(|e| e.to_string())?;
+            Ok(file)
+        });
+    match written {
+        Ok(file) => {
+            info!("Recording match to {}", path.display());
+            let players = players.iter().map(|p| p.player_id).collect();
+            commands.insert_resource(Recorder { path, file, players, ticks: 0 });
+        }
+        Err(e) => warn!("Could not record to {}: {}", path.display(), e),
+    }
+}
+
+fn record_tick(
+    mut commands: Commands,
+    tick: Res<SimTick>,
+    local_input: Res<LocalInput>,
+    mut applied: EventReader<InputsApplied>,
+    players: Query<&NetControl, With<Player>>,
+    mut recorder: ResMut<Recorder>,
+) {
+    let mut record = TickRecord { tick: tick.0, ..default() };
+    let present: Vec<u8> = players.iter().map(|control| control.player_id).collect();
+    record.left = recorder.players.iter().copied().filter(|id| !present.contains(id)).collect();
+    recorder.players.retain(|id| present.contains(id));
+
+    //The host's keys count every tick, dead or not, they can still click
+    if let Some(control) = players.iter().find(|control| control.get_type() == PlayerType::Local) {
+        record.players.push(TickInputs { player_id: control.player_id, angle: control.net_angle, inputs: vec![local_input.0] });
+    }
+    for event in applied.read() {
+        record.players.push(TickInputs { player_id: event.player_id, angle: event.angle, inputs: event.inputs.clone() });
+    }
+
+    let mut out = Vec::new();
+    write_record(&mut out, &record);
+    recorder.ticks += 1;
+    let flush = recorder.ticks % FLUSH_TICKS == 0;
+    let result = recorder.file.write_all(&out).and_then(|_| if flush { recorder.file.flush() } else { Ok(()) });
+    if let Err(e) = result {
+        warn!("Stopped recording to {}: {}", recorder.path.display(), e);
+        commands.remove_resource::<Recorder>();
+    }
+}
+
+fn stop_recording(mut commands: Commands, mut recorder: ResMut<Recorder>) {
+    match recorder.file.flush() {
+        Ok(()) => info!("Saved {} ticks of replay to {}", recorder.ticks, recorder.path.display()),
+        Err(e) => warn!("Could not finish {}: {}", recorder.path.display(), e),
+    }
+    commands.remove_resource::<Recorder>();
+}
+
+//The replay being played and how it is being watched
+#[derive(Resource)]
+pub struct Playback {
+    replay: Replay,
+    //Index of the record for the next tick
+    next: usize,
+    //Ticks run as fast as they can until this one
+    seek_to: Option<u32>,
+    speed: usize,
+    paused: bool,
+    //player_id the camera is on, None for the free camera
+    follow: Option<u8>,
+}
+
+impl Playback {
+    fn new(replay: Replay) -> Self {
+        let follow = replay.players.iter().find(|p| p.host).or(replay.players.first()).map(|p| p.player_id);
+        Self { replay, next: 0, seek_to: None, speed: NORMAL_SPEED, paused: false, follow }
+    }
+
+    //The record for this tick, skipping past any the cursor is behind on
+    fn record(&mut self, tick: u32) -> Option<&TickRecord> {
+        while self.replay.ticks.get(self.next).is_some_and(|record| record.tick < tick) {
+            self.next += 1;
+        }
+        self.replay.ticks.get(self.next).filter(|record| record.tick == tick)
+    }
+
+    fn ended(&self, tick: u32) -> bool {
+        tick >= self.replay.last_tick()
+    }
+}
+
+fn open_replay(
+    mut commands: Commands,
+    config: Res<Config>,
+    mut levels: ResMut<Assets<Level>>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut game_type: ResMut<LogicType>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(path) = &config.replay else {
+        return;
+    };
+    let replay = match load_replay(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            error!("{}", e);
+            exit.write(AppExit::error());
+            return;
+        }
+    };
+    info!(
+        "Playing back {} on {}, {} players, {} ticks",
+        path.display(),
+        replay.level_name,
+        replay.players.len(),
+        replay.last_tick()
+    );
+    //The level as it was, even if the file on disk has changed since
+    *current_level = CurrentLevel { handle: levels.add(replay.level.clone()), name: replay.level_name.clone(), seed: None };
+    //Playback runs everything the host ran
+    game_type.l_type = AssignedType::Host;
+    commands.insert_resource(Playback::new(replay));
+    next_state.set(GameState::Replay);
+}
+
+//Puts the players back the way the match started with them, then starts it
+fn start_playback(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    mut roster: ResMut<LobbyRoster>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    playback.next = 0;
+    roster.players.clear();
+    for player in &playback.replay.players {
+        let info = PlayerInfo::new(player.name.clone(), true);
+        if player.host {
+            commands.spawn((NetControl::new(true, PlayerType::Local, player.player_id, None), Local, info));
+        } else {
+            commands.spawn((NetControl::new(true, PlayerType::Network, player.player_id, None), Network, info));
+        }
+        roster.players.push(LobbyEntry { player_id: player.player_id, ready: true, ping_ms: 0, name: player.name.clone() });
+    }
+    next_state.set(GameState::Playing);
+}
+
+//Hands player_movement the inputs the host used this tick, in place of the keyboard and the network
+fn feed_replay(
+    mut commands: Commands,
+    tick: Res<SimTick>,
+    mut playback: ResMut<Playback>,
+    mut local_input: ResMut<LocalInput>,
+    mut players: Query<(Entity, &mut NetControl, &mut Transform), With<Player>>,
+) {
+    let record = playback.record(tick.0).cloned().unwrap_or_default();
+    local_input.0 = 0;
+    for (entity, mut control, mut transform) in &mut players {
+        if record.left.contains(&control.player_id) {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let Some(entry) = record.players.iter().find(|entry| entry.player_id == control.player_id) else {
+            control.inputs.replace(&[]);
+            continue;
+        };
+        control.net_angle = entry.angle;
+        if control.get_type() == PlayerType::Local {
+            local_input.0 = entry.inputs.first().copied().unwrap_or(0);
+            //player_orientation would aim with the mouse
+            transform.rotation = Quat::from_rotation_z(control.get_angle() - consts::PI / 2.);
+        } else {
+            control.inputs.replace(&entry.inputs);
+        }
+    }
+    if playback.ended(tick.0) && !playback.paused {
+        info!("End of replay at tick {}", tick.0);
+        playback.paused = true;
+    }
+}
+
+fn playback_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    tick: Res<SimTick>,
+    mut playback: ResMut<Playback>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    //Nothing left to play at the end, only seeking back
+    if keys.just_pressed(KeyCode::Space) && !playback.ended(tick.0) {
+        playback.paused = !playback.paused;
+    }
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed + 1).min(SPEEDS.len() - 1);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = playback.speed.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::KeyF) {
+        let ids: Vec<u8> = playback.replay.players.iter().map(|p| p.player_id).collect();
+        //Through everyone then the free camera
+        playback.follow = match playback.follow.and_then(|id| ids.iter().position(|i| *i == id)) {
+            Some(i) => ids.get(i + 1).copied(),
+            None => ids.first().copied(),
+        };
+    }
+
+    let now = playback.seek_to.unwrap_or(tick.0);
+    let target = if keys.just_pressed(KeyCode::ArrowRight) {
+        now + SEEK_TICKS
+    } else if keys.just_pressed(KeyCode::ArrowLeft) {
+        now.saturating_sub(SEEK_TICKS)
+    } else if keys.just_pressed(KeyCode::Home) {
+        0
+    } else {
+        return;
+    };
+    let target = target.min(playback.replay.last_tick());
+    //The past can't be undone, so going back plays the match again from the start up to there
+    if target < tick.0 {
+        next_state.set(GameState::Replay);
+    }
+    playback.seek_to = Some(target);
+}
+
+//Speed and pause are Time<Virtual>'s, the fixed ticks follow it. While seeking the ticks are run
+//by seek_forward instead, so virtual time stops.
+fn apply_playback_time(playback: Res<Playback>, mut time: ResMut<Time<Virtual>>) {
+    if !playback.is_changed() {
+        return;
+    }
+    time.set_relative_speed(SPEEDS[playback.speed]);
+    if playback.paused || playback.seek_to.is_some() {
+        time.pause();
+    } else {
+        time.unpause();
+    }
+}
+
+//Runs whole ticks straight away until the seek gets where it is going
+fn seek_forward(world: &mut World) {
+    let Some(target) = world.resource::<Playback>().seek_to else {
+        return;
+    };
+    for _ in 0..SEEK_TICKS_PER_FRAME {
+        if world.resource::<SimTick>().0 >= target {
+            break;
+        }
+        world.run_schedule(FixedMain);
+    }
+    if world.resource::<SimTick>().0 >= target {
+        world.resource_mut::<Playback>().seek_to = None;
+    }
+}
+
+fn replay_camera(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time<Real>>,
+    playback: Res<Playback>,
+    players: Query<(&NetControl, &Transform), (With<Player>, Without<GameCamera>)>,
+    mut camera: Query<&mut Transform, With<GameCamera>>,
+) {
+    let Ok(mut camera) = camera.single_mut() else {
+        return;
+    };
+    let followed = playback
+        .follow
+        .and_then(|id| players.iter().find(|(control, _)| control.player_id == id));
+    if let Some((_, transform)) = followed {
+        camera.translation.x = transform.translation.x;
+        camera.translation.y = transform.translation.y;
+        return;
+    }
+    //Real time, so it still moves while paused
+    let axis = |back, forward| keys.pressed(forward) as i32 as f32 - keys.pressed(back) as i32 as f32;
+    let dir = Vec2::new(axis(KeyCode::KeyA, KeyCode::KeyD), axis(KeyCode::KeyS, KeyCode::KeyW));
+    camera.translation += (dir.normalize_or_zero() * FREE_CAMERA_SPEED * time.delta_secs()).extend(0.);
+}
+
+#[derive(Component)]
+struct ReplayHud;
+
+fn setup_replay_hud(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        ReplayHud,
+        children![(
+            Text::new(""),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        )],
+    ));
+}
+
+fn cleanup_replay_hud(mut commands: Commands, hud: Query<Entity, With<ReplayHud>>) {
+    for entity in &hud {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn show_replay_hud(
+    tick: Res<SimTick>,
+    playback: Res<Playback>,
+    hud: Query<&Children, With<ReplayHud>>,
+    mut texts: Query<&mut Text>,
+) {
+    let last = playback.replay.last_tick();
+    let clock = |tick: u32| {
+        let secs = tick / TICK_RATE as u32;
+        format!("{}:{:02}", secs / 60, secs % 60)
+    };
+    let state = if playback.seek_to.is_some() {
+        "Seeking".to_string()
+    } else if playback.ended(tick.0) {
+        "End".to_string()
+    } else if playback.paused {
+        "Paused".to_string()
+    } else {
+        format!("x{}", SPEEDS[playback.speed])
+    };
+    let camera = match playback.follow.and_then(|id| playback.replay.players.iter().find(|p| p.player_id == id)) {
+        Some(player) => format!("following {}", player.name),
+        None => "free camera".to_string(),
+    };
+    let line = format!(
+        "Replay {}  {} / {}  tick {}  {}  {}\nSpace pause   Up/Down speed   Left/Right seek   Home restart   F follow   WASD move",
+        playback.replay.level_name,
+        clock(tick.0.min(last)),
+        clock(last),
+        tick.0.min(last),
+        state,
+        camera
+    );
+    for children in &hud {
+        for child in children {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                if text.0 != line {
+                    text.0 = line.clone();
+                }
+            }
+        }
+    }
+}
+
+//Leaving for the menu ends the replay, and time goes back to normal for whatever comes next
+fn stop_playback(mut commands: Commands, mut time: ResMut<Time<Virtual>>) {
+    commands.remove_resource::<Playback>();
+    time.set_relative_speed(1.);
+    time.unpause();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Extraction;
+
+    fn level() -> Level {
+        Level {
+            width: 2,
+            height: 1,
+            spawns: vec![(0, 0), (1, 0)],
+            extraction: Extraction { position: (32., 0.), radius: 64. },
+            tiles: vec![vec![1, 2]],
+            walls: vec![vec![0, 1]],
+            enemies: vec![vec![3, 0]],
+            items: vec![vec![0, 6]],
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        let players = vec![
+            ReplayPlayer { player_id: 0, host: true, name: "Amyia".to_string() },
+            ReplayPlayer { player_id: 1, host: false, name: "Gordon".to_string() },
+        ];
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, "test", &level(), &players).unwrap();
+        for tick in 1..=3 {
+            let record = TickRecord {
+                tick,
+                left: if tick == 3 { vec![1] } else { vec![] },
+                players: vec![
+                    TickInputs { player_id: 0, angle: 250, inputs: vec![128 | 2] },
+                    TickInputs { player_id: 1, angle: 12, inputs: vec![16; tick as usize - 1] },
+                ],
+            };
+            write_record(&mut bytes, &record);
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let replay = parse_replay(&sample()).unwrap();
+        assert_eq!(replay.level_name, "test");
+        assert_eq!(replay.level.walls, level().walls);
+        assert_eq!(replay.level.spawns, level().spawns);
+        assert_eq!(replay.players[1], ReplayPlayer { player_id: 1, host: false, name: "Gordon".to_string() });
+        assert_eq!(replay.last_tick(), 3);
+        assert_eq!(replay.ticks[0].players[0], TickInputs { player_id: 0, angle: 250, inputs: vec![130] });
+        assert!(replay.ticks[0].players[1].inputs.is_empty());
+        assert_eq!(replay.ticks[2].players[1].inputs, vec![16, 16]);
+        assert_eq!(replay.ticks[2].left, vec![1]);
+    }
+
+    #[test]
+    fn a_cut_off_tick_is_dropped() {
+        let bytes = sample();
+        let replay = parse_replay(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(replay.last_tick(), 2);
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(matches!(parse_replay(b"nonsense"), Err(ReplayError::NotAReplay)));
+        let mut bytes = sample();
+        bytes[4] = FORMAT_VERSION + 1;
+        assert!(matches!(parse_replay(&bytes), Err(ReplayError::WrongVersion(_))));
+        assert!(matches!(parse_replay(&sample()[..12]), Err(ReplayError::Truncated)));
+    }
+
+    #[test]
+    fn later_matches_get_numbered() {
+        assert_eq!(numbered(Path::new("out/match.replay"), 1), PathBuf::from("out/match.replay"));
+        assert_eq!(numbered(Path::new("out/match.replay"), 3), PathBuf::from("out/match-3.replay"));
+        assert_eq!(numbered(Path::new("match"), 2), PathBuf::from("match-2"));
+    }
+
+    #[test]
+    fn playback_finds_each_tick() {
+        let mut playback = Playback::new(parse_replay(&sample()).unwrap());
+        assert_eq!(playback.follow, Some(0));
+        assert!(playback.record(0).is_none());
+        assert_eq!(playback.record(2).unwrap().tick, 2);
+        assert!(!playback.ended(2));
+        assert_eq!(playback.record(3).unwrap().left, vec![1]);
+        assert!(playback.record(4).is_none());
+        assert!(playback.ended(4));
+    }
+}
@@ -1,10 +1,10 @@
 use bevy::prelude::*;
 use crate::{
-    net_control::{NetControl, PlayerType},
-    collectible::CollectibleType::Flashlight,
+    type_is_authority,
     player::Player,
-    components::Sanity,
+    components::{Dead, Sanity},
     events::DamagePlayerEvent,
+    sim::{SimSet, TICK_SECS},
 };
 
 pub struct SanityPlugin;
@@ -13,8 +13,11 @@ impl Plugin for SanityPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SanityTimers>()
             .add_event::<DamagePlayerEvent>()
-            .add_systems(Update, sanity_drain_system)
-            .add_systems(Update, sanity_death_system);
+            //Clients get every player's sanity from the host
+            .add_systems(
+                FixedUpdate,
+                (sanity_drain_system, sanity_death_system).chain().in_set(SimSet::Combat).run_if(type_is_authority),
+            );
     }
 }
 
@@ -24,20 +27,15 @@ pub struct SanityTimers {
 }
 
 pub fn sanity_drain_system(
-    time: Res<Time>,
     mut sanity_timers: ResMut<SanityTimers>,
-    mut players: Query<(Entity, &NetControl, &Player, &mut Sanity)>,
+    mut players: Query<(Entity, &Player, &mut Sanity)>,
 ) {
     const SANITY_DRAIN_RATE: f32 = 0.1; // Drain .1 sanity per second without flashlight
     const SANITY_REGEN_RATE: f32 = 10.0; // Regenerate 10 sanity per second with flashlight
     const MAX_SANITY: f32 = 100.0;
 
-    for (entity, control, player, mut sanity) in players.iter_mut() {
-        if control.get_type() != PlayerType::Local {
-            continue;
-        }
-
-        let delta = time.delta_secs();
+    for (entity, player, mut sanity) in players.iter_mut() {
+        let delta = TICK_SECS;
 
         if player.charge > 0 {
             sanity.current = (sanity.current + SANITY_REGEN_RATE * delta).min(MAX_SANITY);
@@ -54,12 +52,9 @@ pub fn sanity_drain_system(
 
 pub fn sanity_death_system(
     mut writer: EventWriter<DamagePlayerEvent>,
-    mut players: Query<(Entity, &NetControl, &mut Sanity)>,
+    mut players: Query<(Entity, &mut Sanity), Without<Dead>>,
 ) {
-    for (entity, control, mut sanity) in players.iter_mut() {
-        if control.get_type() != PlayerType::Local {
-            continue;
-        }
+    for (entity, mut sanity) in players.iter_mut() {
         if sanity.current <= 0.0 && sanity.draining {
             writer.send(DamagePlayerEvent::new(entity, 100));
             sanity.draining = false;
@@ -5,7 +5,7 @@ use crate::components::StaticCollider;
 use crate::enemy::Enemy;
 use crate::player::Player;
 use crate::player_material::PlayerBaseMaterial;
- use std::env;
+use crate::config::Config;
 
 // texture size for the shadow map
 const SDF_TEXTURE_SIZE: u32 = 512;
@@ -21,8 +21,8 @@ pub struct SdfShadowsPlugin;
 impl Plugin for SdfShadowsPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SdfTexture>()
-            .add_systems(Update, generate_sdf.run_if(sdf_disable))
-            .add_systems(Update, update_material_sdf_textures.run_if(sdf_disable));
+            .add_systems(Update, generate_sdf.run_if(sdf_enabled))
+            .add_systems(Update, update_material_sdf_textures.run_if(sdf_enabled));
     }
 }
 
@@ -168,9 +168,7 @@ fn update_material_sdf_textures(
     }
 }
 
-pub fn sdf_disable(
-
-) -> bool {
-    let args: Vec<String> = env::args().collect();
-    return !{args.len() > 2};
+//Run condition, shadows can be turned off with --no-shadows or the config file
+pub fn sdf_enabled(config: Res<Config>) -> bool {
+    return config.shadows;
 }
@@ -0,0 +1,301 @@
+use crate::protocol::{HEADER_LEN, Message, NONCE_LEN, ProtocolError, SEAL_OVERHEAD, is_open_type, peek_type};
+use bevy::prelude::*;
+use std::fmt;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Session security. The host makes up a short join code and players type it in. A client opens     ///
+///   with Hello, the host answers with Challenge, and both nonces plus the code become a key for each ///
+///   direction. Every datagram after that carries a sequence number and a BLAKE3 tag, and the body    ///
+///   is encrypted too when the host asks for it. Anyone without the code can't get a sealed Join      ///
+///   accepted, and forged or replayed datagrams are dropped before they are even parsed.              ///
+///   The code is short enough to type, so someone who records a handshake could still guess it       ///
+///   offline. It keeps strangers out of a game, it isn't meant to protect anything more than that.    ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub const JOIN_CODE_LEN: usize = 6;
+//Longest code accepted from the config, anything shorter than the generated ones is fine too
+pub const MAX_JOIN_CODE_LEN: usize = 16;
+//No 0/O or 1/I, they are too easy to mix up when reading a code out
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const SEQ_LEN: usize = 8;
+const TAG_LEN: usize = SEAL_OVERHEAD - SEQ_LEN;
+//How far behind the newest sequence number a datagram may arrive and still be let through
+const REPLAY_WINDOW: u64 = 64;
+
+//The code the host is taking joins with, shown in the lobby
+#[derive(Resource, Debug, Clone)]
+pub struct JoinCode(pub String);
+
+pub fn generate_join_code() -> String {
+    (0..JOIN_CODE_LEN)
+        .map(|_| CODE_ALPHABET[rand::random_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+//Codes are compared without case, spaces or dashes, so "abc-234" works for ABC234
+pub fn normalize_join_code(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Host,
+    Client,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    //Sealed message from an address we have no key for
+    NoKey,
+    TooShort,
+    BadTag,
+    Replayed,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::NoKey => write!(f, "no session key for this address"),
+            AuthError::TooShort => write!(f, "datagram is too short to be sealed"),
+            AuthError::BadTag => write!(f, "authentication tag does not match"),
+            AuthError::Replayed => write!(f, "sequence number was already used"),
+        }
+    }
+}
+
+//Why a datagram never made it to the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dropped {
+    Malformed(ProtocolError),
+    Unauthenticated(AuthError),
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dropped::Malformed(e) => e.fmt(f),
+            Dropped::Unauthenticated(e) => e.fmt(f),
+        }
+    }
+}
+
+//Keys for one direction, one for the tag and one for the keystream
+#[derive(Clone)]
+struct DirectionKeys {
+    mac: [u8; 32],
+    stream: [u8; 32],
+}
+
+impl DirectionKeys {
+    fn derive(material: &[u8], direction: &str) -> Self {
+        Self {
+            mac: blake3::derive_key(&format!("untitled-twinstick 2026-10 {} mac", direction), material),
+            stream: blake3::derive_key(&format!("untitled-twinstick 2026-10 {} stream", direction), material),
+        }
+    }
+
+    fn tag(&self, data: &[u8]) -> [u8; TAG_LEN] {
+        blake3::keyed_hash(&self.mac, data).as_bytes()[..TAG_LEN].try_into().unwrap()
+    }
+
+    //XORs the keystream for seq over data, which both encrypts and decrypts
+    fn apply_stream(&self, seq: u64, data: &mut [u8]) {
+        let mut stream = vec![0; data.len()];
+        blake3::Hasher::new_keyed(&self.stream).update(&seq.to_le_bytes()).finalize_xof().fill(&mut stream);
+        for (byte, key) in data.iter_mut().zip(stream) {
+            *byte ^= key;
+        }
+    }
+}
+
+//Sequence numbers already seen, as a bitmap going back from the newest
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplayWindow {
+    newest: Option<u64>,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn is_new(&self, seq: u64) -> bool {
+        match self.newest {
+            None => true,
+            Some(newest) if seq > newest => true,
+            Some(newest) => newest - seq < REPLAY_WINDOW && self.seen & (1 << (newest - seq)) == 0,
+        }
+    }
+
+    fn mark(&mut self, seq: u64) {
+        match self.newest {
+            Some(newest) if seq <= newest => self.seen |= 1 << (newest - seq),
+            Some(newest) => {
+                let shift = seq - newest;
+                self.seen = if shift >= REPLAY_WINDOW { 1 } else { self.seen << shift | 1 };
+                self.newest = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.newest = Some(seq);
+            }
+        }
+    }
+}
+
+//One side of an authenticated session. The header stays readable so the receiver can tell sealed
+//datagrams from open ones, the body is encrypted when the host asked for it, and the sequence number
+//and a tag over everything before it go on the end.
+#[derive(Clone)]
+pub struct SecureChannel {
+    send: DirectionKeys,
+    recv: DirectionKeys,
+    encrypted: bool,
+    next_seq: u64,
+    replay: ReplayWindow,
+}
+
+impl SecureChannel {
+    pub fn new(code: &str, client_nonce: &[u8; NONCE_LEN], host_nonce: &[u8; NONCE_LEN], encrypted: bool, side: Side) -> Self {
+        //The encryption flag is part of the keys, so flipping it in the Challenge breaks the session
+        //instead of quietly turning encryption off
+        let mut material = normalize_join_code(code).into_bytes();
+        material.extend_from_slice(client_nonce);
+        material.extend_from_slice(host_nonce);
+        material.push(encrypted as u8);
+        let to_host = DirectionKeys::derive(&material, "client to host");
+        let to_client = DirectionKeys::derive(&material, "host to client");
+        let (send, recv) = match side {
+            Side::Host => (to_client, to_host),
+            Side::Client => (to_host, to_client),
+        };
+        Self { send, recv, encrypted, next_seq: 0, replay: ReplayWindow::default() }
+    }
+
+    //datagram is a whole Message::to_bytes, header included
+    pub fn seal(&mut self, datagram: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut out = Vec::with_capacity(datagram.len() + SEAL_OVERHEAD);
+        out.extend_from_slice(datagram);
+        if self.encrypted {
+            self.send.apply_stream(seq, &mut out[HEADER_LEN..]);
+        }
+        out.extend_from_slice(&seq.to_le_bytes());
+        let tag = self.send.tag(&out);
+        out.extend_from_slice(&tag);
+        out
+    }
+
+    //Gives back the datagram as it was before seal, or why it can't be trusted
+    pub fn open(&mut self, sealed: &[u8]) -> Result<Vec<u8>, AuthError> {
+        if sealed.len() < HEADER_LEN + SEAL_OVERHEAD {
+            return Err(AuthError::TooShort);
+        }
+        let (signed, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+        if !same_tag(&self.recv.tag(signed), tag) {
+            return Err(AuthError::BadTag);
+        }
+        let (body, seq) = signed.split_at(signed.len() - SEQ_LEN);
+        let seq = u64::from_le_bytes(seq.try_into().unwrap());
+        if !self.replay.is_new(seq) {
+            return Err(AuthError::Replayed);
+        }
+        self.replay.mark(seq);
+        let mut datagram = body.to_vec();
+        if self.encrypted {
+            self.recv.apply_stream(seq, &mut datagram[HEADER_LEN..]);
+        }
+        Ok(datagram)
+    }
+}
+
+//Compares every byte so the time taken doesn't give away how much of a guess was right
+fn same_tag(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+//Parses a datagram, first checking its seal with channel unless it is one of the open messages
+pub fn open_datagram(channel: Option<&mut SecureChannel>, datagram: &[u8]) -> Result<Message, Dropped> {
+    let msg_type = peek_type(datagram).map_err(Dropped::Malformed)?;
+    if is_open_type(msg_type) {
+        return Message::from_bytes(datagram).map_err(Dropped::Malformed);
+    }
+    let channel = channel.ok_or(Dropped::Unauthenticated(AuthError::NoKey))?;
+    let datagram = channel.open(datagram).map_err(Dropped::Unauthenticated)?;
+    Message::from_bytes(&datagram).map_err(Dropped::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(host_code: &str, client_code: &str, encrypted: bool) -> (SecureChannel, SecureChannel) {
+        let (client_nonce, host_nonce) = ([1; NONCE_LEN], [2; NONCE_LEN]);
+        (
+            SecureChannel::new(host_code, &client_nonce, &host_nonce, encrypted, Side::Host),
+            SecureChannel::new(client_code, &client_nonce, &host_nonce, encrypted, Side::Client),
+        )
+    }
+
+    #[test]
+    fn sealed_datagrams_open_on_the_other_side() {
+        for encrypted in [false, true] {
+            let (mut host, mut client) = pair("ABC234", "abc-234", encrypted);
+            let msg = Message::Chat { player_id: 1, id: 7, text: "over here".to_string() };
+            let sealed = client.seal(&msg.to_bytes());
+            assert_eq!(sealed.len(), msg.to_bytes().len() + SEAL_OVERHEAD);
+            assert_eq!(open_datagram(Some(&mut host), &sealed), Ok(msg.clone()));
+            //The text is only readable on the wire without encryption
+            let readable = sealed.windows(9).any(|w| w == b"over here");
+            assert_eq!(readable, !encrypted);
+
+            let reply = host.seal(&Message::Heartbeat.to_bytes());
+            assert_eq!(open_datagram(Some(&mut client), &reply), Ok(Message::Heartbeat));
+        }
+    }
+
+    #[test]
+    fn forgeries_are_dropped() {
+        let (mut host, mut client) = pair("ABC234", "ABC235", false);
+        let sealed = client.seal(&Message::Join { name: "x".to_string(), token: 0 }.to_bytes());
+        //Wrong code
+        assert_eq!(host.open(&sealed), Err(AuthError::BadTag));
+
+        let (mut host, mut client) = pair("ABC234", "ABC234", true);
+        let mut sealed = client.seal(&Message::Ready { ready: true }.to_bytes());
+        sealed[HEADER_LEN] ^= 1;
+        assert_eq!(host.open(&sealed), Err(AuthError::BadTag));
+        //Our own datagrams don't open with our own keys either, so they can't be reflected back
+        let ours = host.seal(&Message::Heartbeat.to_bytes());
+        assert_eq!(host.open(&ours), Err(AuthError::BadTag));
+        assert_eq!(host.open(&ours[..HEADER_LEN + 3]), Err(AuthError::TooShort));
+
+        //Unsealed messages that need a key
+        let plain = Message::Ready { ready: true }.to_bytes();
+        assert!(matches!(open_datagram(Some(&mut host), &plain), Err(Dropped::Unauthenticated(_))));
+        assert_eq!(open_datagram(None, &plain), Err(Dropped::Unauthenticated(AuthError::NoKey)));
+        //while the handshake needs none
+        let hello = Message::Hello { nonce: [3; NONCE_LEN] };
+        assert_eq!(open_datagram(None, &hello.to_bytes()), Ok(hello));
+    }
+
+    #[test]
+    fn replays_are_dropped_but_reordering_is_fine() {
+        let (mut host, mut client) = pair("ABC234", "ABC234", false);
+        let sealed: Vec<Vec<u8>> = (0..70).map(|_| client.seal(&Message::Heartbeat.to_bytes())).collect();
+        assert!(host.open(&sealed[1]).is_ok());
+        assert!(host.open(&sealed[0]).is_ok());
+        assert_eq!(host.open(&sealed[1]), Err(AuthError::Replayed));
+        assert!(host.open(&sealed[69]).is_ok());
+        //Too far behind to tell whether it was seen
+        assert_eq!(host.open(&sealed[2]), Err(AuthError::Replayed));
+        assert!(host.open(&sealed[10]).is_ok());
+        assert_eq!(host.open(&sealed[10]), Err(AuthError::Replayed));
+    }
+
+    #[test]
+    fn codes_are_easy_to_type() {
+        let code = generate_join_code();
+        assert_eq!(code.len(), JOIN_CODE_LEN);
+        assert!(code.bytes().all(|c| CODE_ALPHABET.contains(&c)));
+        assert_eq!(normalize_join_code(" ab-c 234 "), "ABC234");
+    }
+}
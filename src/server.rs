@@ -1,51 +1,261 @@
 use crate::{
     AssignedType, GameState, LogicType, net_control::NetControl, net_control::PlayerType, net_control::Local, net_control::Network,
-    player::Player, player::Velocity, player, enemy::Enemy, enemy::Awake, collectible::PlayerInventory,
+    player::Player, player::Velocity, player, enemy::Enemy, collectible::PlayerInventory,
+    snapshot::{ClientSnapshots, EntityStates},
+    protocol::{
+        DoorState, EnemyHealth, EnemySpawn, HOST_ID, LobbyEntry, MAX_ENEMIES_PER_PACKET, Message,
+        NONCE_LEN, PlayerStatus, RejectReason, ResumeState, ServerInfo,
+    },
+    secure::{AuthError, Dropped, JoinCode, SecureChannel, Side, generate_join_code, open_datagram},
+    components::{Dead, Health, NetId, Sanity},
+    pickup_system::CollectibleTakenEvent,
+    prediction::InputQueue,
+    wall::Door,
+    level::CurrentLevel,
+    lobby::{LobbyRoster, PlayerInfo},
+    events::{EnemyDamagedEvent, EnemyKilledEvent, EnemySpawnedEvent},
+    dedicated::Dedicated,
+    config::Config,
+    netsim::{NetSimulator, NetSocket},
+    net_thread::{NetThread, Sealer},
+    netstats::NetStats,
+    sim::SimTick,
+    chat::{ChatItem, ChatReceived, ChatSent, ChatState, MarkerReceived, MarkerSent},
 };
 use bevy::input::mouse::MouseButton;
 use bevy::prelude::*;
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::env;
 
-const IP_CONST: &str = "0.0.0.0:2525";
-const MAX_PLAYER: u8 = 4;
+//How often the host pings every peer when there is nothing else to send
+const HEARTBEAT_INTERVAL: f32 = 1.0;
+//How long a peer can go without sending anything before they are dropped
+const PEER_TIMEOUT: f32 = 5.0;
+//Mid match a dropped peer's player is kept this long so they can rejoin with their session token
+const RECONNECT_WINDOW: f32 = 60.0;
+//Enemy spawn/damage/death, pickup, chat and marker messages go out this many ticks in a row to ride
+//out packet loss. Clients ignore the copies they have already applied.
+const EVENT_REPEATS: u8 = 3;
+//Every live enemy, enemy death and taken collectible go out again this often, for anything all the
+//repeats of an event missed. Clients skip what they already have.
+const EVENT_REFRESH_INTERVAL: f32 = 1.0;
+//Entities further than this from a client's player are left out of its snapshots
+const INTEREST_RADIUS: f32 = 1600.;
+//How often waiting clients get the lobby list
+const LOBBY_UPDATE_INTERVAL: f32 = 0.25;
+//How often every peer is pinged for the lobby list and the connection stats
+const PING_INTERVAL: f32 = 0.25;
+//Door states are sent whenever one flips, and this often anyway in case that packet was lost
+const DOOR_REFRESH_INTERVAL: f32 = 1.0;
+//Players who join mid match spectate, with ids from here up so they never collide with a player slot
+const FIRST_SPECTATOR_ID: u8 = 128;
+const MAX_SPECTATORS: u8 = 8;
+//Handshakes that haven't been finished with a sealed Join, the oldest makes room past this
+const MAX_PENDING_HANDSHAKES: usize = 32;
 
+//The socket itself lives on the network thread, see net_thread.rs
 #[derive(Resource)]
 pub struct SocketResource {
-    socket: UdpSocket,
+    net: NetThread,
+    secure: Arc<Mutex<HostSecurity>>,
+}
+impl SocketResource {
+    //Sealed and sent from the network thread
+    fn send(&self, msg: &Message, addr: SocketAddr) {
+        self.net.send(msg, addr);
+    }
+
+    //The peer left or moved, whatever shows up from that address now needs a new handshake
+    fn forget(&self, addr: SocketAddr) {
+        let mut secure = self.secure.lock().unwrap();
+        secure.channels.remove(&addr);
+        secure.pending.remove(&addr);
+    }
+}
+
+//The host's half of secure.rs: the join code, handshakes in progress and a session per address.
+//Both keep the two nonces they came from so a repeated Hello gets the same Challenge back.
+struct HostSecurity {
+    code: String,
+    encrypted: bool,
+    pending: HashMap<SocketAddr, Handshake>,
+    channels: HashMap<SocketAddr, Session>,
+    //Counts handshakes as they start, so the oldest one can be found
+    started: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Handshake {
+    client_nonce: [u8; NONCE_LEN],
+    host_nonce: [u8; NONCE_LEN],
+    started: u64,
+}
+
+struct Session {
+    client_nonce: [u8; NONCE_LEN],
+    host_nonce: [u8; NONCE_LEN],
+    channel: SecureChannel,
+}
+
+impl HostSecurity {
+    fn new(code: String, encrypted: bool) -> Self {
+        Self { code, encrypted, pending: HashMap::new(), channels: HashMap::new(), started: 0 }
+    }
+
+    //Answer to a Hello. Someone flooding us with Hellos only ever pushes out other unfinished handshakes.
+    //A Hello with a new nonce from an address we have a session with starts a handshake next to it,
+    //the old session is only replaced once a sealed Join proves the new one.
+    fn challenge(&mut self, src: SocketAddr, client_nonce: [u8; NONCE_LEN]) -> Message {
+        let in_session = self.channels.get(&src).filter(|s| s.client_nonce == client_nonce).map(|s| s.host_nonce);
+        let known = self.pending.get(&src).filter(|h| h.client_nonce == client_nonce).map(|h| h.host_nonce);
+        let host_nonce = match in_session.or(known) {
+            Some(host_nonce) => host_nonce,
+            None => {
+                if self.pending.len() >= MAX_PENDING_HANDSHAKES && !self.pending.contains_key(&src) {
+                    if let Some(oldest) = self.pending.iter().min_by_key(|(_, h)| h.started).map(|(addr, _)| *addr) {
+                        self.pending.remove(&oldest);
+                    }
+                }
+                let host_nonce = rand::random();
+                self.started += 1;
+                self.pending.insert(src, Handshake { client_nonce, host_nonce, started: self.started });
+                host_nonce
+            }
+        };
+        Message::Challenge { nonce: host_nonce, encrypted: self.encrypted }
+    }
+
+    fn open(&mut self, datagram: &[u8], src: SocketAddr) -> Result<Message, Dropped> {
+        let in_session = self.channels.get_mut(&src).map(|s| open_datagram(Some(&mut s.channel), datagram));
+        match in_session {
+            Some(Err(Dropped::Unauthenticated(AuthError::BadTag))) if self.pending.contains_key(&src) => {
+                //Not from the old session, it may be a rejoin from the same address finishing its handshake
+                self.finish_handshake(datagram, src)
+            }
+            Some(result) => result,
+            None => self.finish_handshake(datagram, src),
+        }
+    }
+
+    //The first sealed datagram of a handshake proves the client has the code
+    fn finish_handshake(&mut self, datagram: &[u8], src: SocketAddr) -> Result<Message, Dropped> {
+        let Some(handshake) = self.pending.get(&src).copied() else {
+            return open_datagram(None, datagram);
+        };
+        let mut channel = SecureChannel::new(&self.code, &handshake.client_nonce, &handshake.host_nonce, self.encrypted, Side::Host);
+        let msg = open_datagram(Some(&mut channel), datagram)?;
+        if !msg.is_open() {
+            self.pending.remove(&src);
+            self.channels.insert(
+                src,
+                Session { client_nonce: handshake.client_nonce, host_nonce: handshake.host_nonce, channel },
+            );
+        }
+        Ok(msg)
+    }
+
+    fn is_pending(&self, src: SocketAddr) -> bool {
+        self.pending.contains_key(&src)
+    }
+}
+
+impl Sealer for HostSecurity {
+    fn open(&mut self, datagram: &[u8], from: SocketAddr) -> Result<Message, Dropped> {
+        HostSecurity::open(self, datagram, from)
+    }
+
+    //Sealed with the peer's session key unless it is part of the handshake
+    fn seal(&mut self, msg: &Message, to: SocketAddr) -> Option<Vec<u8>> {
+        if msg.is_open() {
+            return Some(msg.to_bytes());
+        }
+        //Nobody we have a session with, so nobody who could read it
+        let session = self.channels.get_mut(&to)?;
+        Some(session.channel.seal(&msg.to_bytes()))
+    }
 }
 
 pub struct ServerPlugin;
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
+        app.init_resource::<LobbyRoster>()
+        .add_systems(
             OnEnter(GameState::Lobby),
-            (server_init.before(server_start), server_start),
+            server_init,
         )
         .add_systems(
             OnEnter(GameState::Playing),
-            (send_players).run_if(type_equals_host),
+            (send_players)
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_event::<PeerDisconnected>()
+        .add_event::<PeerRejoined>()
+        .add_systems(
+            Update,
+            (server_run, check_peer_timeouts, handle_disconnects, send_heartbeats, send_pings, send_lobby_state, collect_traffic)
+                .chain()
+                .run_if(in_state(GameState::Lobby))
+                .run_if(resource_exists::<SocketResource>),
         )
-        .add_systems(Update, server_run.run_if(in_state(GameState::Lobby)))
         .add_systems(
             Update,
-            server_run
+            (server_run, resume_peers, check_peer_timeouts, handle_disconnects, send_heartbeats, send_pings, collect_traffic)
+                .chain()
                 .run_if(in_state(GameState::Playing))
-                .run_if(type_equals_host),
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
         )
         .add_systems(
             FixedLast,
             send_player_update
                 .run_if(in_state(GameState::Playing))
-                .run_if(type_equals_host),
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            FixedLast,
+            send_door_states
+                .run_if(in_state(GameState::Playing))
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            FixedLast,
+            send_player_status
+                .run_if(in_state(GameState::Playing))
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
         )
-        //Debug only module
         .add_systems(
             FixedLast,
-            connection_health
+            send_snapshots
                 .run_if(in_state(GameState::Playing))
-                .run_if(type_equals_host),
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            Update,
+            (queue_enemy_events, queue_pickups, relay_chat)
+                .run_if(in_state(GameState::Playing))
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            FixedLast,
+            (send_events, refresh_events)
+                .chain()
+                .run_if(in_state(GameState::Playing))
+                .run_if(type_equals_host)
+                .run_if(resource_exists::<SocketResource>),
+        )
+        .add_systems(
+            Update,
+            apply_net_sim
+                .run_if(resource_exists::<SocketResource>)
+                .run_if(resource_changed::<NetSimulator>),
         )
         .add_systems(
             OnExit(GameState::Playing),
@@ -54,412 +264,1007 @@ impl Plugin for ServerPlugin {
     }
 }
 
-#[derive(Resource)]
-pub struct RollbackDetection {
-    pub is_rollback: bool,
-}
-impl Default for RollbackDetection {
-    fn default() -> Self {
-        Self {
-            is_rollback: false,
-        }
-    }
-}
-
 pub fn type_equals_host(game_type: Res<LogicType>) -> bool {
     return game_type.l_type == AssignedType::Host;
 }
 
 
-fn server_init(mut commands: Commands) {
-    let args: Vec<String> = env::args().collect();
-    let mut newIP = "".to_string();
-    if args.len() > 1 {
-        newIP = args[1].to_owned() + ":2525";
-    }
-    else
-    {
-        newIP = IP_CONST.to_string();
+fn server_init(
+    mut commands: Commands,
+    config: Res<Config>,
+    dedicated: Option<Res<Dedicated>>,
+    sim: Res<NetSimulator>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    let socket = match UdpSocket::bind(addr) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Could not host on {}: {}", addr, e);
+            //Nothing to fall back to without a window
+            if dedicated.is_some() {
+                exit.write(AppExit::error());
+            } else {
+                next_state.set(GameState::Menu);
+            }
+            return;
+        }
+    };
+    info!("Hosting on {}", addr);
+    let code = config.join_code.clone().unwrap_or_else(generate_join_code);
+    info!("Join code: {}{}", code, if config.encrypt { " (encrypted)" } else { "" });
+    let secure = Arc::new(Mutex::new(HostSecurity::new(code.clone(), config.encrypt)));
+    let net = match NetThread::spawn("host network", NetSocket::new(socket, sim.active()), secure.clone()) {
+        Ok(net) => net,
+        Err(e) => {
+            error!("Could not start the network thread: {}", e);
+            if dedicated.is_some() {
+                exit.write(AppExit::error());
+            } else {
+                next_state.set(GameState::Menu);
+            }
+            return;
+        }
+    };
+    commands.insert_resource(SocketResource { net, secure });
+    commands.insert_resource(JoinCode(code));
+    commands.insert_resource(EventReplication::default());
+    commands.insert_resource(SnapshotServer::default());
+    commands.insert_resource(NetStats::default());
+    commands.insert_resource(ChatState::default());
+    //A dedicated host has no player of its own, every slot goes to a remote client
+    if dedicated.is_some() {
+        commands.insert_resource(ServerMetrics {
+            player_count: 0,
+            ..default()
+        });
+        return;
     }
-    commands.insert_resource(SocketResource {
-        socket: UdpSocket::bind(newIP).expect("ERROR"),
-    });
     commands.insert_resource(ServerMetrics::default());
-    commands.spawn((NetControl::new(true, PlayerType::Local, 0, Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 2525,)),),
+    commands.spawn((NetControl::new(true, PlayerType::Local, 0, Some(addr)),
                     Local,
+                    //Whoever hosts decides when to start, so they never hold things up
+                    PlayerInfo::new(config.player_name.clone(), true),
     ),
 );
 }
 
-fn server_close(mut commands: Commands) {
+fn server_close(
+    mut commands: Commands,
+    socket: Option<Res<SocketResource>>,
+    p_net: Query<&NetControl>,
+) {
+    let Some(socket) = socket else {
+        return;
+    };
+    //Let everyone know the host is gone instead of having them wait for a timeout
+    for control in &p_net {
+        if control.get_type() == PlayerType::Network {
+            socket.send(&Message::Leave { player_id: HOST_ID }, control.get_addr().unwrap());
+        }
+    }
     commands.remove_resource::<SocketResource>();
 }
 
-fn server_start(socket: ResMut<SocketResource>) {
-    //This makes it so the game doesn't wait to receive a message, before going to the next frame
-    socket.socket.set_nonblocking(true);
+//F8 or the config changed the simulated network
+fn apply_net_sim(sim: Res<NetSimulator>, socket: Res<SocketResource>) {
+    socket.net.set_conditions(sim.active());
 }
 
 fn server_run(
     mut commands: Commands,
+    config: Res<Config>,
+    time: Res<Time>,
+    state: Res<State<GameState>>,
     socket: ResMut<'_, SocketResource>,
-    mut player: Query<(&mut NetControl, &mut InputHistory), With<NetControl>>,
+    mut player: Query<(Entity, &mut NetControl, Option<&mut PlayerInfo>, Option<&SessionToken>, Has<Disconnected>)>,
     mut sm: ResMut<ServerMetrics>,
-    mut roll: ResMut<RollbackDetection>,
+    mut disconnects: EventWriter<PeerDisconnected>,
+    mut rejoins: EventWriter<PeerRejoined>,
+    mut snapshots: ResMut<SnapshotServer>,
+    mut stats: ResMut<NetStats>,
+    real_time: Res<Time<Real>>,
+    mut chat: ResMut<ChatState>,
+    level: Option<Res<CurrentLevel>>,
 ) {
-    let mut buf = [0; 257];
-    for l in 1..20 {
-        match socket.socket.recv_from(&mut buf) {
-            Ok((amt, src)) => {
-                //Server Receives Join Packet
-                if (buf[0] == 255) {
-                    info!("Player joined from: {:?}", src);
-                    if sm.player_count < MAX_PLAYER {
-                        //Creates NetControl for connecting player
-                        let tempNet = commands
-                            .spawn(
-                                (NetControl::new(
-                                    true,
-                                    PlayerType::Network,
-                                    sm.player_count,
-                                    Some(src),
-                                ),
-                                Network,
-                            ),
-                            )
-                            .id();
-                        //Adds one to player count in ServerMetrics
-                        sm.player_count += 1;
+    //Everything the network thread has read since last frame
+    while let Some(received) = socket.net.try_recv() {
+        let (src, arrived) = (received.from, received.arrived(&real_time));
+        let msg = match received.msg {
+            Ok(msg) => msg,
+            Err(Dropped::Malformed(e)) => {
+                //Malformed or wrong version packets are dropped here
+                sm.rejected += 1;
+                warn!("Dropped packet from {:?}: {}", src, e);
+                continue;
+            }
+            Err(Dropped::Unauthenticated(e)) => {
+                //Forged, replayed, or sealed with the wrong join code. Only logged at debug
+                //level since anyone can send these as fast as they like.
+                if e == AuthError::Replayed {
+                    sm.replayed += 1;
+                } else {
+                    sm.forged += 1;
+                }
+                debug!("Dropped packet from {:?}: {}", src, e);
+                if e == AuthError::BadTag && socket.secure.lock().unwrap().is_pending(src) {
+                    socket.send(&Message::Reject(RejectReason::BadCode), src);
+                }
+                continue;
+            }
+        };
 
-                        //Clock Sync
-                        socket
-                            .socket
-                            .send_to(
-                                &[2, sm.player_count],
-                                src,
-                            )
-                            .expect("couldn't send data");
+        //Anything from a known peer counts as a heartbeat
+        let known = player
+            .iter()
+            .find(|(_, a, ..)| a.get_type() == PlayerType::Network && a.get_addr() == Some(src))
+            .map(|(e, a, _, token, disconnected)| (e, a.player_id, token.map_or(0, |t| t.0), disconnected));
+        let sender = known.map(|(_, pid, ..)| pid);
+        if let Some((entity, pid, _, disconnected)) = known {
+            sm.last_heard.insert(pid, time.elapsed_secs());
+            if disconnected {
+                //The connection came back on its own before the client gave up
+                info!("Player {} is back", pid);
+                commands.entity(entity).remove::<Disconnected>();
+            }
+        }
 
+        match msg {
+            //A client starting the handshake, see secure.rs
+            Message::Hello { nonce } => {
+                let challenge = socket.secure.lock().unwrap().challenge(src, nonce);
+                socket.send(&challenge, src);
+            }
 
+            //Server Receives Join Packet
+            Message::Join { name, token } => {
+                info!("Player joined from: {:?}", src);
+                //A new address with a token we handed out is a player coming back
+                let returning = known.or_else(|| {
+                    player
+                        .iter()
+                        .find(|(_, a, _, t, _)| {
+                            token != 0 && a.get_type() == PlayerType::Network && t.is_some_and(|t| t.0 == token)
+                        })
+                        .map(|(e, a, _, t, disconnected)| (e, a.player_id, t.map_or(0, |t| t.0), disconnected))
+                });
+                if let Some((entity, pid, token, _)) = returning {
+                    if let Ok((_, mut control, ..)) = player.get_mut(entity) {
+                        if let Some(old) = control.get_addr().filter(|old| *old != src) {
+                            socket.forget(old);
+                        }
+                        control.set_addr(src);
+                        //The client counts inputs from 1 again
+                        control.inputs = InputQueue::default();
+                    }
+                    commands.entity(entity).remove::<Disconnected>();
+                    sm.last_heard.insert(pid, time.elapsed_secs());
+                    if *state.get() == GameState::Lobby {
+                        //Our Accept got lost, send it again instead of handing out a second slot
+                        socket.send(&Message::Accept { player_id: pid, player_count: sm.player_count, token }, src);
+                    } else {
+                        info!("Player {} rejoined from {:?}", pid, src);
+                        rejoins.write(PeerRejoined { player_id: pid });
+                    }
+                } else if *state.get() != GameState::Lobby {
+                    //Too late to play, but they can watch. They get the world the same way a
+                    //rejoining player does, and keep asking until Start arrives.
+                    let taken: Vec<u8> = player.iter().map(|(_, a, ..)| a.player_id).collect();
+                    let free = (FIRST_SPECTATOR_ID..FIRST_SPECTATOR_ID + MAX_SPECTATORS)
+                        .find(|id| !taken.contains(id));
+                    if let Some(pid) = free {
+                        info!("Player joined from {:?} mid match, spectating as {}", src, pid);
+                        commands.spawn((
+                            NetControl::new(true, PlayerType::Network, pid, Some(src)),
+                            Network,
+                            Spectator,
+                        ));
+                        sm.last_heard.insert(pid, time.elapsed_secs());
+                        rejoins.write(PeerRejoined { player_id: pid });
                     } else {
-                        //Send Reject Message
+                        socket.send(&Message::Reject(RejectReason::InProgress), src);
+                    }
+                } else if sm.player_count < config.max_players {
+                    //Creates NetControl for connecting player
+                    let pid = sm.player_count;
+                    let token = rand::random::<u64>().max(1);
+                    let tempNet = commands
+                        .spawn(
+                            (NetControl::new(
+                                true,
+                                PlayerType::Network,
+                                pid,
+                                Some(src),
+                            ),
+                            Network,
+                            PlayerInfo::new(name, false),
+                            SessionToken(token),
+                        ),
+                        )
+                        .id();
+                    //Adds one to player count in ServerMetrics
+                    sm.player_count += 1;
+                    sm.last_heard.insert(pid, time.elapsed_secs());
+
+                    //Doubles as the clock sync
+                    socket.send(&Message::Accept { player_id: pid, player_count: sm.player_count, token }, src);
+                } else {
+                    socket.send(&Message::Reject(RejectReason::Full), src);
+                }
+            }
+
+            //Normal Input Packet
+            Message::Input { angle, seq, inputs } => {
+                for (_, mut a, ..) in player.iter_mut() {
+                    //Spectators have nothing to steer
+                    if a.get_addr().unwrap() == src && a.player_id < FIRST_SPECTATOR_ID {
+                        //Applied one per tick by player_movement
+                        a.inputs.receive(seq, &inputs);
+                        //info!("{:?}", angle);
+                        a.net_angle = angle;
+                        stats.peer(a.player_id).record_seq(seq);
                     }
                 }
+            }
+
+            //Client is quitting
+            Message::Leave { .. } => {
+                if let Some(pid) = sender {
+                    disconnects.write(PeerDisconnected { player_id: pid });
+                }
+            }
+
+            Message::Heartbeat => {}
+
+            //Client measuring its round trip, send its own clock straight back
+            Message::Ping { time_ms } => {
+                if sender.is_some() {
+                    socket.send(&Message::Pong { time_ms }, src);
+                }
+            }
 
-                //Input Byte
-                for (mut a, mut history) in player.iter_mut() {
-                    if a.get_addr().unwrap() == src {
-                        //Normal Input Packet
-                        if amt != 257 {
-                            sm.packets_rcvd += 1;
-                            a.net_input = buf[0];
-                            //info!("{:?}", buf[1]);
-                            a.net_angle = buf[1];
-
-                            //ServerMetric updates for testing
-                            sm.packets[a.player_id as usize] += 1;
-                            //info!("Amount: {:?} -> {:?}", amt, buf);
-                            sm.seq[a.player_id as usize] = buf[2];
+            //Server browser looking for games, anyone may ask
+            Message::DiscoveryQuery => {
+                let info = ServerInfo {
+                    name: config.player_name.clone(),
+                    player_count: sm.player_count,
+                    max_players: config.max_players,
+                    in_progress: *state.get() != GameState::Lobby,
+                    map: level.as_ref().map(|l| l.name.clone()).unwrap_or_default(),
+                };
+                socket.send(&Message::DiscoveryReply(info), src);
+            }
+
+            //Ready toggled in the lobby, too late to matter once the match is on
+            Message::Ready { ready } => {
+                if *state.get() != GameState::Lobby {
+                    continue;
+                }
+                for (_, a, info, ..) in player.iter_mut() {
+                    if Some(a.player_id) == sender && a.get_type() == PlayerType::Network {
+                        if let Some(mut info) = info {
+                            info.ready = ready;
                         }
-                        //Input History packet
-                        else {
-                            //Set up InputHistory attached to player
-                            //commands.insert_resource(InputHistory::new(a.player_id, buf, sm.last_conf_seq[a.player_id as usize], buf[256], sm.last_pos[a.player_id as usize]));
-                            history.set_all(a.player_id, buf, sm.last_conf_seq[a.player_id as usize], buf[256], sm.last_pos[a.player_id as usize]);
-                            
-                            //Call Player from vec once (make sure you set the pos of that player)
-                            
-                            //Triggers player.rs event
-                            roll.is_rollback = true;
-                            //world.run_system_once(player::player_movement_from_history);
-
-                            //a.rollback = false;
-                            
-                            //Remove resource
+                    }
+                }
+            }
 
+            //Answer to one of our pings, time_ms is our own clock from when it went out
+            Message::Pong { time_ms } => {
+                let ping = (arrived.as_millis() as u32).wrapping_sub(time_ms);
+                if let Some(pid) = sender {
+                    stats.peer(pid).record_rtt(ping as f32);
+                }
+                for (_, a, info, ..) in player.iter_mut() {
+                    if Some(a.player_id) == sender && a.get_type() == PlayerType::Network {
+                        if let Some(mut info) = info {
+                            info.ping_ms = ping.min(u16::MAX as u32) as u16;
                         }
                     }
                 }
             }
-            Err(e) => {
-                //info!("Nothing");
+
+            //Client has every fragment of a snapshot, so it can be used as a delta baseline
+            Message::SnapshotAck { tick } => {
+                if let Some(pid) = sender {
+                    snapshots.clients.entry(pid).or_default().ack(tick);
+                    stats.peer(pid).record_snapshot(arrived.as_secs_f64());
+                }
+            }
+
+            //A dead player or spectator picked someone to watch
+            Message::Spectate { player_id } => {
+                if let (Some((entity, ..)), GameState::Playing) = (known, state.get()) {
+                    commands.entity(entity).insert(Watching(player_id));
+                }
+            }
+
+            //Shown and relayed by relay_chat, once the match is on
+            Message::Chat { id, text, .. } => {
+                if let (Some(pid), GameState::Playing) = (sender, state.get()) {
+                    chat.receive(pid, id, ChatItem::Line(text));
+                }
+            }
+            Message::Marker { id, x, y, .. } => {
+                if let (Some(pid), GameState::Playing) = (sender, state.get()) {
+                    chat.receive(pid, id, ChatItem::Marker(Vec2::new(x as f32, y as f32)));
+                }
+            }
+
+            //Host -> Client messages have no business arriving here
+            _ => {
+                sm.rejected += 1;
             }
         }
     }
 }
 
-fn send_players(
-    socket: ResMut<'_, SocketResource>,
-    mut p_net: Query<&mut NetControl, With<NetControl>>,
-    mut sm: ResMut<ServerMetrics>,
+//A peer left or stopped talking to us
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PeerDisconnected {
+    pub player_id: u8,
+}
+
+//Issued on join and kept by the client, so it can take its slot back after losing the connection
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub struct SessionToken(pub u64);
+
+//A player whose connection dropped mid match. Their player stays in the world, standing still, until
+//they rejoin or the reconnect window runs out.
+#[derive(Component)]
+pub struct Disconnected {
+    since: f32,
+}
+
+//A peer that joined mid match and only watches. It has a NetControl but never a player.
+#[derive(Component)]
+pub struct Spectator;
+
+//Which player a dead player or spectator has their camera on, their snapshots are centered there
+#[derive(Component)]
+pub struct Watching(pub u8);
+
+//A client took back its slot mid match, or joined late to spectate, and needs to be brought up to date
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PeerRejoined {
+    pub player_id: u8,
+}
+
+fn check_peer_timeouts(
+    mut commands: Commands,
+    time: Res<Time>,
+    state: Res<State<GameState>>,
+    sm: Res<ServerMetrics>,
+    p_net: Query<(Entity, &NetControl, Option<&Disconnected>, Has<Spectator>)>,
+    mut disconnects: EventWriter<PeerDisconnected>,
 ) {
-    for i in p_net {
-        if i.get_type() == PlayerType::Network {
-            //info!{"{:?}", i.get_addr()};
-            socket
-                .socket
-                .send_to(
-                    &[0, sm.player_count, i.player_id],
-                    i.get_addr().unwrap(),
-                )
-                .expect("couldn't send data");
+    let now = time.elapsed_secs();
+    for (entity, control, disconnected, spectator) in &p_net {
+        if control.get_type() != PlayerType::Network {
+            continue;
+        }
+        match disconnected {
+            //Nobody has spawned yet in the lobby, so there is nothing to hold on to
+            None if now - sm.last_heard.get(&control.player_id).copied().unwrap_or(now) > PEER_TIMEOUT => {
+                //Spectators have no player to hold on to either
+                if *state.get() == GameState::Lobby || spectator {
+                    info!("Player {} timed out", control.player_id);
+                    disconnects.write(PeerDisconnected { player_id: control.player_id });
+                } else {
+                    info!("Player {} lost connection, holding their slot", control.player_id);
+                    commands.entity(entity).insert(Disconnected { since: now });
+                }
+            }
+            Some(disconnected) if now - disconnected.since > RECONNECT_WINDOW => {
+                info!("Player {} did not come back", control.player_id);
+                disconnects.write(PeerDisconnected { player_id: control.player_id });
+            }
+            _ => {}
         }
     }
 }
 
-fn send_player_update(
-    socket: ResMut<'_, SocketResource>,
-    mut p_net: Query<(&mut NetControl, &mut InputHistory, &mut PlayerInventory), With<NetControl>>,
-    enemy_list: Query<(&Enemy, &Transform), (With<Enemy>, With<Awake>)>,
-    mut sm: ResMut<ServerMetrics>,
+//Sends a rejoining client everything it needs to pick up where it left off. Start has it rebuild the
+//world, then its own player, the doors and every enemy follow. Spectators get the same minus a player.
+fn resume_peers(
+    socket: Res<SocketResource>,
+    sm: Res<ServerMetrics>,
+    mut events: EventReader<PeerRejoined>,
+    mut snapshots: ResMut<SnapshotServer>,
+    p_net: Query<&NetControl>,
+    players: Query<(&NetControl, &Transform, &Health, &Sanity, &Player, &PlayerInventory)>,
+    enemies: Query<(&Enemy, &Transform, &Health)>,
+    doors: Query<(&Door, &NetId)>,
+    mut replication: ResMut<EventReplication>,
+    level: Option<Res<CurrentLevel>>,
 ) {
-    let mut roll_check: [bool; 4] = [false; 4];
+    for event in events.read() {
+        let Some(control) =
+            p_net.iter().find(|c| c.player_id == event.player_id && c.get_type() == PlayerType::Network)
+        else {
+            continue;
+        };
+        let addr = control.get_addr().unwrap();
+        //Its old baseline is gone along with its old world, so the next snapshot is a full one
+        snapshots.clients.remove(&event.player_id);
+
+        let level_seed = level.as_ref().and_then(|l| l.seed);
+        socket.send(&Message::Start { player_count: sm.player_count, player_id: event.player_id, level_seed }, addr);
+        if let Some((_, transform, health, sanity, player, inventory)) =
+            players.iter().find(|(c, ..)| c.player_id == event.player_id)
+        {
+            let state = ResumeState {
+                x: transform.translation.x as i32,
+                y: transform.translation.y as i32,
+                health: health.current.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                sanity_tenths: (sanity.current * 10.).round().clamp(0., u16::MAX as f32) as u16,
+                inventory: inventory.to_state(player.charge),
+            };
+            socket.send(&Message::Resume(state), addr);
+        }
+
+        for chunk in door_states(&doors).chunks(MAX_ENEMIES_PER_PACKET) {
+            socket.send(&Message::DoorStates(chunk.to_vec()), addr);
+        }
+        //The world only comes as events, so these go out as often as the events do. The client
+        //stops asking once Start arrives and a lost one would never be sent again.
+        let taken: Vec<Message> =
+            replication.taken.chunks(MAX_ENEMIES_PER_PACKET).map(|chunk| Message::CollectiblesTaken(chunk.to_vec())).collect();
+        for msg in taken {
+            replication.direct.push((EVENT_REPEATS, addr, msg));
+        }
 
-    let mut counter = 1;
-    let mut en_out: [u8;321] = [0; 321];
-    en_out[0] = 4;
-    for (enemy, enemy_trans) in enemy_list {
-        if counter != 321 {
-            let out_x = (enemy_trans.translation.x as i16).to_ne_bytes();
-            let out_y = (enemy_trans.translation.y as i16).to_ne_bytes();
-            en_out[counter+0] = enemy.enemy_id;
-            en_out[(counter+1)..(counter+3)].copy_from_slice(&out_x);
-            en_out[(counter+3)..(counter+5)].copy_from_slice(&out_y);
-            //info!("{:?}", en_out);
-            counter += 5;
+        for chunk in live_enemies(&enemies).chunks(MAX_ENEMIES_PER_PACKET) {
+            replication.direct.push((EVENT_REPEATS, addr, Message::EnemySpawns(chunk.to_vec())));
         }
     }
+}
 
-    for (i, history, inv) in p_net.iter() {
-        if i.get_type() == PlayerType::Network {
-            sm.packets_sent += 1;
-            for (j, loc_history, inv) in p_net.iter() { 
+fn handle_disconnects(
+    mut commands: Commands,
+    mut events: EventReader<PeerDisconnected>,
+    state: Res<State<GameState>>,
+    socket: Res<SocketResource>,
+    mut sm: ResMut<ServerMetrics>,
+    mut stats: ResMut<NetStats>,
+    mut p_net: Query<(Entity, &mut NetControl, Option<&SessionToken>, Has<Spectator>)>,
+) {
+    //Highest id first so renumbering in the lobby can't shift a later event onto the wrong player
+    let mut leaving: Vec<u8> = events.read().map(|e| e.player_id).collect();
+    leaving.sort_unstable();
+    leaving.dedup();
 
-                /*if i.player_id == j.player_id {
-                    if sm.counter_schedule >= 60 {
-                        roll_check[j.player_id as usize] = true;
-                    } else {
-                        roll_check[j.player_id as usize] = false;
-                    }
-                } 
-
-                // If a rollback is decided, when you send the packet to that player, send with OP code 3 instead 
-                //INPUT HISTORY ROLLBACK DISABLED WHILE TESTING
-                if j.rollback && i.player_id == j.player_id && sm.loss[j.player_id as usize] > 10 && sm.loss[j.player_id as usize] < 254 &&false {
-                    let out = j.get_out_packet(3, j.player_id);
-                    socket
-                        .socket
-                        .send_to(&out, i.get_addr().unwrap())
-                        .expect("couldn't send data");
-                    //If the rollback flag is flipped mark it in the bool array
-                    roll_check[j.player_id as usize] = true;
-                }*/
-                //If packet loss was longer than the history window, just hard roll back
-                if j.rollback && i.player_id == j.player_id {
-                    let out = j.get_out_packet(1, j.player_id);
-                    socket
-                        .socket
-                        .send_to(&out, i.get_addr().unwrap())
-                        .expect("couldn't send data");
-                    roll_check[j.player_id as usize] = true;
+    for pid in leaving.into_iter().rev() {
+        let Some((entity, control, _, spectator)) = p_net
+            .iter()
+            .find(|(_, c, ..)| c.player_id == pid && c.get_type() == PlayerType::Network)
+        else {
+            continue;
+        };
+        if let Some(addr) = control.get_addr() {
+            socket.forget(addr);
+        }
+        commands.entity(entity).despawn();
+        info!("Player {} disconnected", pid);
+        sm.last_heard.remove(&pid);
+
+        if spectator {
+            //Nobody else ever knew about them
+            continue;
+        } else if *state.get() == GameState::Lobby {
+            //Nobody has spawned yet, so close the gap and tell everyone their new slot
+            sm.player_count -= 1;
+            sm.last_heard = sm
+                .last_heard
+                .drain()
+                .filter(|(id, _)| *id != pid)
+                .map(|(id, heard)| (if id > pid { id - 1 } else { id }, heard))
+                .collect();
+            //Everyone past the gap gets a new id, so their stats start over
+            stats.peers.retain(|id, _| *id < pid);
+            for (e, mut control, token, _) in p_net.iter_mut() {
+                if e == entity || control.get_type() != PlayerType::Network {
+                    continue;
                 }
-                else {
-                    let out = j.get_out_packet(1, j.player_id);
-                    socket
-                        .socket
-                        .send_to(&out, i.get_addr().unwrap())
-                        .expect("couldn't send data");
+                if control.player_id > pid {
+                    control.player_id -= 1;
+                }
+                socket.send(
+                    &Message::Accept { player_id: control.player_id, player_count: sm.player_count, token: token.map_or(0, |t| t.0) },
+                    control.get_addr().unwrap(),
+                );
+            }
+        } else {
+            //Mid match the slot stays taken, everyone else just removes that player
+            for (e, control, ..) in p_net.iter() {
+                if e != entity && control.get_type() == PlayerType::Network {
+                    socket.send(&Message::Leave { player_id: pid }, control.get_addr().unwrap());
                 }
-
             }
-
-            //Send enemy info
-            socket
-                .socket
-                .send_to(&en_out, i.get_addr().unwrap())
-                .expect("couldn't send data");
         }
     }
+}
 
-    if sm.counter_schedule >= 60 {
-        sm.counter_schedule = 0;
-    } else {
-        sm.counter_schedule += 1;
+fn send_heartbeats(
+    time: Res<Time>,
+    socket: Res<SocketResource>,
+    mut sm: ResMut<ServerMetrics>,
+    p_net: Query<&NetControl>,
+) {
+    if !sm.heartbeat.tick(time.delta()).just_finished() {
+        return;
     }
-
-    for (mut i, mut history, mut inv) in p_net.iter_mut() {
-        if i.get_type() == PlayerType::Network {
-            //Send player inventory  
-            let mut out = [0;4];
-            out[0] = 5;
-            out[1] = i.player_id;
-            let out_inv = inv.inv_to_bytes();
-            out[2] = out_inv[0];
-            out[3] = out_inv[1];
-            //info!("{:?}", i.get_addr().unwrap());
-            socket
-                .socket
-                .send_to(&out, i.get_addr().unwrap())
-                .expect("couldn't send data");
+    for control in &p_net {
+        if control.get_type() == PlayerType::Network {
+            socket.send(&Message::Heartbeat, control.get_addr().unwrap());
         }
+    }
+}
 
-        if roll_check[i.player_id as usize] {
-            //i.rollback = false;
-            history.usable = false;                
+//Pongs fill in the ping column of the lobby list and the round trip in the connection stats
+fn send_pings(
+    time: Res<Time<Real>>,
+    socket: Res<SocketResource>,
+    mut sm: ResMut<ServerMetrics>,
+    p_net: Query<&NetControl>,
+) {
+    if !sm.ping.tick(time.delta()).just_finished() {
+        return;
+    }
+    let now = time.elapsed().as_millis() as u32;
+    for control in &p_net {
+        if control.get_type() == PlayerType::Network {
+            socket.send(&Message::Ping { time_ms: now }, control.get_addr().unwrap());
         }
     }
 }
 
-#[derive(Component)]
-pub struct InputHistory {
-    pub usable: bool,
-    pub use_count: u16,
-    pub player: u8,
-    pub complete_history: [u8;257],
-    pub start: u8,
-    pub end: u8,
-    pub last_pos: Vec3,
-}
-impl Default for InputHistory {
-    fn default() -> Self {
-        Self {
-            usable: false,
-            use_count: 0,
-            player: 0,
-            complete_history: [0;257],
-            start: 0,
-            end: 0,
-            last_pos: Vec3::ZERO,
+//Hands what the socket counted for each peer's address over to that peer's stats
+fn collect_traffic(socket: Res<SocketResource>, mut stats: ResMut<NetStats>, p_net: Query<&NetControl>) {
+    for (addr, traffic) in socket.net.take_traffic() {
+        let peer = p_net
+            .iter()
+            .find(|c| c.get_type() == PlayerType::Network && c.get_addr() == Some(addr));
+        if let Some(control) = peer {
+            stats.peer(control.player_id).add_traffic(traffic);
         }
     }
 }
-impl InputHistory {
-    pub fn set_all(&mut self, pl: u8, history: [u8;257], st: u8, en: u8, lp: Vec3) {
-        self.usable = true;
-        self.player = pl;
-        self.complete_history = history;
-        self.start = st;
-        self.end = en;
-        self.last_pos = lp;
+
+//Keeps the host's lobby list current, and every so often sends it to the waiting clients so
+//everyone can see everyone's connection
+fn send_lobby_state(
+    time: Res<Time>,
+    socket: Res<SocketResource>,
+    mut sm: ResMut<ServerMetrics>,
+    mut roster: ResMut<LobbyRoster>,
+    players: Query<(&NetControl, &PlayerInfo)>,
+) {
+    let mut entries: Vec<LobbyEntry> = players
+        .iter()
+        .map(|(control, info)| LobbyEntry {
+            player_id: control.player_id,
+            ready: info.ready,
+            ping_ms: info.ping_ms,
+            name: info.name.clone(),
+        })
+        .collect();
+    entries.sort_by_key(|e| e.player_id);
+    //Only touch the roster when something changed, the lobby screen rebuilds on every change
+    if roster.players != entries {
+        roster.players = entries.clone();
     }
 
-    pub fn history_used(&mut self) {
-        self.usable = false;
-        self.use_count += 1;
-        //info!("Disabled. Count = {}", self.use_count);
+    if !sm.lobby_update.tick(time.delta()).just_finished() {
+        return;
+    }
+    for (control, _) in &players {
+        if control.get_type() == PlayerType::Network {
+            socket.send(&Message::LobbyState(entries.clone()), control.get_addr().unwrap());
+        }
     }
 }
 
-#[derive(Resource)]
-pub struct ServerMetrics {
-    pub player_count: u8,
-    pub packets_sent: u8,
-    pub packets_rcvd: u8,
-    pub packets: Vec<u8>,
-
-    pub seq: Vec<u8>,
-    pub last: Vec<u8>,
-
-    pub last_conf_seq: Vec<u8>,
-    pub last_pos: Vec<Vec3>,
+fn send_players(
+    socket: ResMut<'_, SocketResource>,
+    mut p_net: Query<&mut NetControl, With<NetControl>>,
+    mut sm: ResMut<ServerMetrics>,
+    level: Option<Res<CurrentLevel>>,
+) {
+    let level_seed = level.as_ref().and_then(|l| l.seed);
+    for i in p_net {
+        if i.get_type() == PlayerType::Network {
+            //info!{"{:?}", i.get_addr()};
+            socket.send(
+                &Message::Start { player_count: sm.player_count, player_id: i.player_id, level_seed },
+                i.get_addr().unwrap(),
+            );
+        }
+    }
+}
 
-    pub loss: Vec<u8>,
-    pub counter_schedule: u8,
-    
+//Enemy and pickup messages waiting to be sent, with how many more times each one goes out
+#[derive(Resource)]
+pub struct EventReplication {
+    pending: Vec<(u8, Message)>,
+    //The same, for one client only
+    direct: Vec<(u8, SocketAddr, Message)>,
+    //Every collectible taken this match, for clients that rejoin and for each refresh
+    taken: Vec<u16>,
+    //Every enemy killed this match, sent again with each refresh
+    killed: Vec<u16>,
+    refresh: Timer,
 }
-impl Default for ServerMetrics {
+impl Default for EventReplication {
     fn default() -> Self {
         Self {
-            player_count: 1,
-            packets_sent: 0,
-            packets_rcvd: 0,
-            packets: vec![0; 4],
+            pending: Vec::new(),
+            direct: Vec::new(),
+            taken: Vec::new(),
+            killed: Vec::new(),
+            refresh: Timer::from_seconds(EVENT_REFRESH_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
 
-            seq: vec![0; 4],
-            last: vec![0; 4],
+//What a client needs to spawn each enemy still alive
+fn live_enemies(enemies: &Query<(&Enemy, &Transform, &Health)>) -> Vec<EnemySpawn> {
+    enemies
+        .iter()
+        .map(|(enemy, trans, health)| EnemySpawn {
+            enemy_id: enemy.enemy_id,
+            kind: enemy.enemy_type().to_byte(),
+            x: trans.translation.x as i16,
+            y: trans.translation.y as i16,
+            health: health.current.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+        })
+        .collect()
+}
 
-            last_conf_seq: vec![0; 4],
-            last_pos: vec![Vec3::ZERO; 4],
+fn queue_enemy_events(
+    mut spawned: EventReader<EnemySpawnedEvent>,
+    mut damaged: EventReader<EnemyDamagedEvent>,
+    mut killed: EventReader<EnemyKilledEvent>,
+    mut replication: ResMut<EventReplication>,
+) {
+    let spawns: Vec<EnemySpawn> = spawned
+        .read()
+        .map(|e| EnemySpawn {
+            enemy_id: e.enemy_id,
+            kind: e.enemy_type.to_byte(),
+            x: e.position.x as i16,
+            y: e.position.y as i16,
+            health: e.health.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+        })
+        .collect();
+    let hits: Vec<EnemyHealth> = damaged
+        .read()
+        .map(|e| EnemyHealth {
+            enemy_id: e.enemy_id,
+            health: e.health.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+        })
+        .collect();
+    let deaths: Vec<u16> = killed.read().map(|e| e.enemy_id).collect();
 
-            loss: vec![0; 4],
-            counter_schedule: 0,
-        }
+    for chunk in spawns.chunks(MAX_ENEMIES_PER_PACKET) {
+        replication.pending.push((EVENT_REPEATS, Message::EnemySpawns(chunk.to_vec())));
     }
+    for chunk in hits.chunks(MAX_ENEMIES_PER_PACKET) {
+        replication.pending.push((EVENT_REPEATS, Message::EnemyDamage(chunk.to_vec())));
+    }
+    for chunk in deaths.chunks(MAX_ENEMIES_PER_PACKET) {
+        replication.pending.push((EVENT_REPEATS, Message::EnemyDeaths(chunk.to_vec())));
+    }
+    replication.killed.extend(deaths);
 }
-impl ServerMetrics {
 
+fn queue_pickups(mut events: EventReader<CollectibleTakenEvent>, mut replication: ResMut<EventReplication>) {
+    let ids: Vec<u16> = events.read().map(|e| e.collectible_id).collect();
+    for chunk in ids.chunks(MAX_ENEMIES_PER_PACKET) {
+        replication.pending.push((EVENT_REPEATS, Message::CollectiblesTaken(chunk.to_vec())));
+    }
+    replication.taken.extend(ids);
 }
 
-fn connection_health(
-    mut sm: ResMut<ServerMetrics>,
-    mut p_net: Query<(&mut NetControl, &mut Transform), With<NetControl>>,
+//Everyone's chat lines and markers, ours included, are shown here and sent to every client. The
+//player_id the client put in is ignored, it is always whoever the packet came from.
+fn relay_chat(
+    mut chat: ResMut<ChatState>,
+    mut replication: ResMut<EventReplication>,
+    mut sent: EventReader<ChatSent>,
+    mut markers_sent: EventReader<MarkerSent>,
+    mut lines: EventWriter<ChatReceived>,
+    mut markers: EventWriter<MarkerReceived>,
+    local: Query<&NetControl, With<Local>>,
 ) {
-    //info!("[Packets Sent: {}] [Packets Rcvd: {}]", sm.packets_sent, sm.packets_rcvd);
-    //info!("[P0: {}] [P1: {}] [P2: {}] [P3: {}]", sm.packets[0], sm.packets[1], sm.packets[2], sm.packets[3]);
-    //info!("[P0: {}] [P1: {}] [P2: {}] [P3: {}]", sm.seq[0], sm.seq[1], sm.seq[2], sm.seq[3]);
-
-    for i in 1..(sm.player_count as usize) {
-        if sm.seq[i] == sm.last[i] && sm.packets[i] != 0 {
-            //info!("Dupe Packet for {}: Seq {}", i, sm.seq[i]);
-        } else if sm.last[i] == 255 && sm.seq[i] == 0 { 
-
-        } else if (sm.last[i] == 255 && sm.seq[i] != 0) || (sm.last[i] + 1 != sm.seq[i]) {
-            let mut dif = 0;
-            if sm.seq[i] < sm.last[i] {
-                dif = ((sm.seq[i] as u16 + 256) - (sm.last[i] as u16)) as u8;
-            } else
-            {
-                dif = (sm.seq[i] - sm.last[i]) as u8;
+    let host_id = local.iter().next().map_or(0, |control| control.player_id);
+    let mut items = std::mem::take(&mut chat.incoming);
+    items.extend(sent.read().map(|e| (host_id, ChatItem::Line(e.text.clone()))));
+    items.extend(markers_sent.read().map(|e| (host_id, ChatItem::Marker(e.position))));
+    for (player_id, item) in items {
+        let id = chat.next_id();
+        let msg = match item {
+            ChatItem::Line(text) => {
+                lines.write(ChatReceived { player_id, text: text.clone() });
+                Message::Chat { player_id, id, text }
             }
-            //If we got enough packets to cover the gap
-            if sm.packets[i] >= dif as u8 {
+            ChatItem::Marker(position) => {
+                markers.write(MarkerReceived { player_id, position });
+                Message::Marker { player_id, id, x: position.x as i32, y: position.y as i32 }
+            }
+        };
+        replication.pending.push((EVENT_REPEATS, msg));
+    }
+}
 
-            } else {
-                //info!("Lost Packet(s) for {}: Last {}, Seq {}", i, sm.last[i], sm.seq[i]);
+//Every door's state whenever one of them flips, plus a refresh now and then
+fn send_door_states(
+    time: Res<Time>,
+    socket: Res<SocketResource>,
+    p_net: Query<&NetControl>,
+    doors: Query<(&Door, &NetId)>,
+    changed: Query<(), Changed<Door>>,
+    mut sm: ResMut<ServerMetrics>,
+) {
+    let refresh = sm.door_refresh.tick(time.delta()).just_finished();
+    if changed.is_empty() && !refresh {
+        return;
+    }
+    let states = door_states(&doors);
+    for chunk in states.chunks(MAX_ENEMIES_PER_PACKET) {
+        let out = Message::DoorStates(chunk.to_vec());
+        for control in &p_net {
+            if control.get_type() == PlayerType::Network {
+                socket.send(&out, control.get_addr().unwrap());
             }
-        } 
+        }
+    }
+}
+
+fn door_states(doors: &Query<(&Door, &NetId)>) -> Vec<DoorState> {
+    doors.iter().map(|(door, id)| DoorState { door_id: id.0, open: door.open }).collect()
+}
 
-        if sm.seq[i] == sm.last[i] {
-            if sm.loss[i] < 255 {
-                sm.loss[i] += 1;
+fn send_events(
+    socket: Res<SocketResource>,
+    p_net: Query<&NetControl>,
+    mut replication: ResMut<EventReplication>,
+) {
+    for (repeats, msg) in replication.pending.iter_mut() {
+        for control in &p_net {
+            if control.get_type() == PlayerType::Network {
+                socket.send(msg, control.get_addr().unwrap());
             }
-        } else {
-            sm.loss[i] = 0;
         }
+        *repeats -= 1;
+    }
+    replication.pending.retain(|(repeats, _)| *repeats > 0);
+    for (repeats, addr, msg) in replication.direct.iter_mut() {
+        socket.send(msg, *addr);
+        *repeats -= 1;
+    }
+    replication.direct.retain(|(repeats, ..)| *repeats > 0);
+}
 
-        /*if sm.loss[i] >= 10 {
-            info!("Player {} missed {} packets!", i, sm.loss[i]);
-            for (mut control, mut trans) in &mut p_net {
-                if control.player_id == i as u8 {
-                    //info!("sm.loss setting {}.rollback = true", i);
-                    sm.last_pos[i] = trans.translation;
-                    sm.last_conf_seq[i] = sm.last[i];
-                    control.rollback = true;
-                }
+//Snapshots leave out enemies far from a client and carry no collectibles, so they can't tell it an
+//enemy or a pickup is gone. An event that lost all its repeats is only made good here.
+fn refresh_events(
+    time: Res<Time>,
+    socket: Res<SocketResource>,
+    p_net: Query<&NetControl>,
+    enemies: Query<(&Enemy, &Transform, &Health)>,
+    mut replication: ResMut<EventReplication>,
+) {
+    if !replication.refresh.tick(time.delta()).just_finished() {
+        return;
+    }
+    let mut out: Vec<Message> =
+        live_enemies(&enemies).chunks(MAX_ENEMIES_PER_PACKET).map(|chunk| Message::EnemySpawns(chunk.to_vec())).collect();
+    out.extend(replication.killed.chunks(MAX_ENEMIES_PER_PACKET).map(|chunk| Message::EnemyDeaths(chunk.to_vec())));
+    out.extend(replication.taken.chunks(MAX_ENEMIES_PER_PACKET).map(|chunk| Message::CollectiblesTaken(chunk.to_vec())));
+    for msg in &out {
+        for control in &p_net {
+            if control.get_type() == PlayerType::Network {
+                socket.send(msg, control.get_addr().unwrap());
             }
-        }*/
-
-        if sm.counter_schedule >= 60 {
-            for (mut control, mut trans) in &mut p_net {
-                if control.player_id == i as u8 {
-                    sm.last_pos[i] = trans.translation;
-                    sm.last_conf_seq[i] = sm.last[i];
-                    control.rollback = true;
-                }
+        }
+    }
+}
+
+//What each client (by player_id) has been sent
+#[derive(Resource, Default)]
+pub struct SnapshotServer {
+    clients: HashMap<u8, ClientSnapshots>,
+}
+
+fn send_snapshots(
+    socket: Res<SocketResource>,
+    p_net: Query<(&NetControl, Option<&Transform>, Option<&Watching>)>,
+    enemies: Query<(&Enemy, &Transform)>,
+    mut snapshots: ResMut<SnapshotServer>,
+    sim_tick: Res<SimTick>,
+) {
+    //One snapshot per tick, stamped with it
+    let tick = sim_tick.0;
+    let position = |player_id: u8| {
+        p_net
+            .iter()
+            .find(|(c, ..)| c.player_id == player_id)
+            .and_then(|(_, trans, _)| trans)
+            .map(|trans| trans.translation.truncate())
+    };
+    for (control, player_trans, watching) in &p_net {
+        if control.get_type() != PlayerType::Network {
+            continue;
+        }
+        //Whoever they are watching, else their own player, else anyone so a spectator sees something
+        let Some(center) = watching
+            .and_then(|w| position(w.0))
+            .or(player_trans.map(|trans| trans.translation.truncate()))
+            .or_else(|| p_net.iter().find_map(|(_, trans, _)| trans).map(|trans| trans.translation.truncate()))
+        else {
+            continue;
+        };
+        //Nearest first, so if the snapshot has to be cut down the far away entities are dropped
+        let mut nearby: Vec<(f32, u16, (i16, i16))> = enemies
+            .iter()
+            .map(|(enemy, trans)| {
+                let pos = trans.translation.truncate();
+                (pos.distance(center), enemy.enemy_id, (pos.x as i16, pos.y as i16))
+            })
+            .filter(|(dist, _, _)| *dist <= INTEREST_RADIUS)
+            .collect();
+        nearby.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let priority: Vec<u16> = nearby.iter().map(|(_, id, _)| *id).collect();
+        let current: EntityStates = nearby.into_iter().map(|(_, id, pos)| (id, pos)).collect();
+
+        let client = snapshots.clients.entry(control.player_id).or_default();
+        for snapshot in client.build(tick, &current, &priority) {
+            socket.send(&Message::Snapshot(snapshot), control.get_addr().unwrap());
+        }
+    }
+}
+
+fn send_player_update(
+    socket: ResMut<'_, SocketResource>,
+    p_net: Query<(&NetControl, Has<Spectator>)>,
+    inventories: Query<(&NetControl, &PlayerInventory, &Player)>,
+) {
+    for (i, _) in p_net.iter() {
+        if i.get_type() == PlayerType::Network {
+            //Every player's state, each carrying the last input applied for that player. The
+            //receiving client only uses the ack on its own player, see prediction.rs
+            for (j, _) in p_net.iter().filter(|(_, spectator)| !spectator) {
+                let out = Message::PlayerState(j.get_out_state(j.player_id));
+                socket.send(&out, i.get_addr().unwrap());
             }
         }
+    }
 
-        sm.last[i] = sm.seq[i];
+    for (i, inv, player) in inventories.iter() {
+        if i.get_type() == PlayerType::Network {
+            //Send player inventory
+            let out = Message::Inventory { player_id: i.player_id, inventory: inv.to_state(player.charge) };
+            //info!("{:?}", i.get_addr().unwrap());
+            socket.send(&out, i.get_addr().unwrap());
+        }
     }
+}
 
-    /*//Status Report
-    info!("Status:\n");
-    for (mut control, mut trans) in &mut p_net {
-        info!("Player {}: {}\n", control.player_id, control.rollback);
-    }*/
+//Everyone's health, sanity, charge and death as the host has them, the only copy clients go by
+fn send_player_status(
+    socket: Res<SocketResource>,
+    players: Query<(&NetControl, &Health, &Sanity, &Player, &PlayerInventory, Has<Dead>)>,
+    p_net: Query<&NetControl>,
+) {
+    let statuses: Vec<PlayerStatus> = players
+        .iter()
+        .map(|(control, health, sanity, player, inventory, dead)| PlayerStatus {
+            player_id: control.player_id,
+            health: health.current.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+            sanity_tenths: (sanity.current * 10.).round().clamp(0., u16::MAX as f32) as u16,
+            charge: player.charge.clamp(0, u16::MAX as i32) as u16,
+            dead,
+            flashlight: inventory.has_flashlight,
+        })
+        .collect();
+    let out = Message::PlayerStatus(statuses);
+    //Spectators too, so they know who is still alive to watch
+    for control in &p_net {
+        if control.get_type() == PlayerType::Network {
+            socket.send(&out, control.get_addr().unwrap());
+        }
+    }
+}
 
-    //Reset
-    sm.packets_sent = 0;
-    sm.packets_rcvd = 0;
-    sm.packets = vec![0; 4];
+#[derive(Resource)]
+pub struct ServerMetrics {
+    pub player_count: u8,
+    //Datagrams dropped for being malformed or the wrong protocol version
+    pub rejected: u32,
+    //Datagrams dropped for a missing or bad seal, and for reusing a sequence number
+    pub forged: u32,
+    pub replayed: u32,
+    //Time.elapsed_secs() of the last datagram from each peer, by player_id
+    pub last_heard: HashMap<u8, f32>,
+    pub heartbeat: Timer,
+    pub lobby_update: Timer,
+    pub ping: Timer,
+    pub door_refresh: Timer,
+}
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        Self {
+            player_count: 1,
+            rejected: 0,
+            forged: 0,
+            replayed: 0,
+            last_heard: HashMap::new(),
+            heartbeat: Timer::from_seconds(HEARTBEAT_INTERVAL, TimerMode::Repeating),
+            lobby_update: Timer::from_seconds(LOBBY_UPDATE_INTERVAL, TimerMode::Repeating),
+            ping: Timer::from_seconds(PING_INTERVAL, TimerMode::Repeating),
+            door_refresh: Timer::from_seconds(DOOR_REFRESH_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "ABC234";
+
+    fn addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 25001))
+    }
+
+    //Hello, Challenge and a sealed Join from a client at addr with this nonce
+    fn join(host: &mut HostSecurity, client_nonce: [u8; NONCE_LEN], token: u64) -> (SecureChannel, Result<Message, Dropped>) {
+        let Message::Challenge { nonce: host_nonce, encrypted } = host.challenge(addr(), client_nonce) else {
+            panic!("expected a challenge");
+        };
+        let mut client = SecureChannel::new(CODE, &client_nonce, &host_nonce, encrypted, Side::Client);
+        let sealed = client.seal(&Message::Join { name: "Gordon".to_string(), token }.to_bytes());
+        let opened = host.open(&sealed, addr());
+        (client, opened)
+    }
+
+    #[test]
+    fn repeated_hello_in_session_gets_the_same_challenge() {
+        let mut host = HostSecurity::new(CODE.to_string(), false);
+        let (first, second) = (host.challenge(addr(), [1; NONCE_LEN]), host.challenge(addr(), [1; NONCE_LEN]));
+        assert_eq!(first, second);
+        let (mut client, opened) = join(&mut host, [1; NONCE_LEN], 0);
+        assert!(opened.is_ok());
+        //A late copy of the Hello after the session is up, the client keeps its keys and they still work
+        assert_eq!(host.challenge(addr(), [1; NONCE_LEN]), first);
+        let sealed = client.seal(&Message::Heartbeat.to_bytes());
+        assert_eq!(host.open(&sealed, addr()), Ok(Message::Heartbeat));
+    }
+
+    #[test]
+    fn rejoin_from_the_same_address() {
+        let mut host = HostSecurity::new(CODE.to_string(), true);
+        let (_, opened) = join(&mut host, [1; NONCE_LEN], 0);
+        assert!(opened.is_ok());
+        //The old session is still there, the new handshake has to replace it
+        let (mut client, opened) = join(&mut host, [2; NONCE_LEN], 77);
+        assert_eq!(opened, Ok(Message::Join { name: "Gordon".to_string(), token: 77 }));
+        assert!(!host.is_pending(addr()));
+        let sealed = client.seal(&Message::Heartbeat.to_bytes());
+        assert_eq!(host.open(&sealed, addr()), Ok(Message::Heartbeat));
+    }
+
+    #[test]
+    fn oldest_handshake_makes_room() {
+        let mut host = HostSecurity::new(CODE.to_string(), false);
+        let first = SocketAddr::from(([10, 0, 0, 1], 1));
+        host.challenge(first, [1; NONCE_LEN]);
+        for port in 0..MAX_PENDING_HANDSHAKES as u16 {
+            host.challenge(SocketAddr::from(([10, 0, 0, 2], port)), [1; NONCE_LEN]);
+        }
+        assert_eq!(host.pending.len(), MAX_PENDING_HANDSHAKES);
+        assert!(!host.is_pending(first));
+    }
 }
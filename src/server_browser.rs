@@ -0,0 +1,382 @@
+use crate::{
+    AssignedType, GameState, LogicType,
+    config::Config,
+    discovery::{DiscoveryClient, ServerList, parse_address},
+    secure::MAX_JOIN_CODE_LEN,
+};
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use std::net::SocketAddr;
+
+//How often the browser asks the network for hosts
+const QUERY_INTERVAL: f32 = 1.0;
+//Longest address the field accepts
+const MAX_ADDRESS_LEN: usize = 64;
+
+//Join screen: hosts found on the local network plus a field to type any address
+pub struct ServerBrowserPlugin;
+impl Plugin for ServerBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerList>()
+            .add_systems(OnEnter(GameState::ServerBrowser), (open_browser, display_browser).chain())
+            .add_systems(OnExit(GameState::ServerBrowser), close_browser)
+            .add_systems(
+                Update,
+                (search_for_servers, update_server_list, type_address, browser_buttons)
+                    .chain()
+                    .run_if(in_state(GameState::ServerBrowser)),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct Browser {
+    //None when no socket could be opened, the address field still works then
+    discovery: Option<DiscoveryClient>,
+    timer: Timer,
+    address: String,
+    code: String,
+    //Tab switches typing between the address and the join code
+    typing_code: bool,
+}
+
+#[derive(Component)]
+enum BrowserButton {
+    Server(SocketAddr),
+    Manual,
+    Refresh,
+    Back,
+}
+
+// tags UI elements for cleanup
+#[derive(Component)]
+struct BrowserUI;
+
+#[derive(Component)]
+struct ServerListNode;
+
+#[derive(Component)]
+struct AddressText;
+
+#[derive(Component)]
+struct CodeText;
+
+fn open_browser(mut commands: Commands, config: Res<Config>, mut list: ResMut<ServerList>) {
+    let discovery = match DiscoveryClient::bind() {
+        Ok(discovery) => Some(discovery),
+        Err(e) => {
+            warn!("Could not open discovery socket: {}", e);
+            None
+        }
+    };
+    list.servers.clear();
+    let mut timer = Timer::from_seconds(QUERY_INTERVAL, TimerMode::Repeating);
+    //Ask straight away instead of after the first interval
+    timer.tick(timer.duration());
+    commands.insert_resource(Browser {
+        discovery,
+        timer,
+        //Whatever was given on the command line, so the old way of joining is still one key press
+        address: config.server_endpoint(),
+        code: config.join_code.clone().unwrap_or_default(),
+        typing_code: false,
+    });
+}
+
+fn close_browser(mut commands: Commands, query: Query<Entity, With<BrowserUI>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<Browser>();
+}
+
+fn button(parent: &mut ChildSpawnerCommands, label: &str, width: f32, action: BrowserButton) {
+    parent
+        .spawn((
+            Button,
+            Node {
+                width: Val::Px(width),
+                height: Val::Px(50.0),
+                border: UiRect::all(Val::Px(2.0)),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BorderColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
+            action,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(label),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+        });
+}
+
+fn display_browser(mut commands: Commands, query: Query<Entity, With<Camera>>, browser: Res<Browser>) {
+    if query.is_empty() {
+        commands.spawn((Camera2d, BrowserUI));
+    }
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+            BrowserUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Join a Game"),
+                TextFont {
+                    font_size: 64.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+
+            // filled in by update_server_list
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.0),
+                    min_height: Val::Px(120.0),
+                    ..default()
+                },
+                ServerListNode,
+            ));
+
+            // address and join code fields, typed into from anywhere on the screen
+            field(parent, address_label(&browser), AddressText);
+            field(parent, code_label(&browser), CodeText);
+
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    column_gap: Val::Px(20.0),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    button(parent, "JOIN ADDRESS", 200.0, BrowserButton::Manual);
+                    button(parent, "REFRESH", 200.0, BrowserButton::Refresh);
+                    button(parent, "BACK", 200.0, BrowserButton::Back);
+                });
+        });
+}
+
+fn field(parent: &mut ChildSpawnerCommands, label: String, marker: impl Component) {
+    parent
+        .spawn((
+            Node {
+                width: Val::Px(620.0),
+                height: Val::Px(50.0),
+                border: UiRect::all(Val::Px(2.0)),
+                align_items: AlignItems::Center,
+                padding: UiRect::horizontal(Val::Px(10.0)),
+                ..default()
+            },
+            BorderColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.05, 0.05, 0.05)),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(label),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                marker,
+            ));
+        });
+}
+
+//The field being typed into has the cursor
+fn address_label(browser: &Browser) -> String {
+    format!("Address: {}{}", browser.address, if browser.typing_code { "" } else { "_" })
+}
+
+fn code_label(browser: &Browser) -> String {
+    format!("Join code (Tab): {}{}", browser.code, if browser.typing_code { "_" } else { "" })
+}
+
+fn search_for_servers(time: Res<Time<Real>>, config: Res<Config>, mut browser: ResMut<Browser>, mut list: ResMut<ServerList>) {
+    let now = time.elapsed_secs_f64();
+    let Some(discovery) = browser.discovery.as_ref() else {
+        return;
+    };
+    let mut changed = false;
+    for (addr, info) in discovery.receive() {
+        changed |= list.bypass_change_detection().update(addr, info, now);
+    }
+    changed |= list.bypass_change_detection().expire(now);
+    if changed {
+        list.set_changed();
+    }
+
+    if browser.timer.tick(time.delta()).just_finished() {
+        if let Some(discovery) = browser.discovery.as_ref() {
+            discovery.query(config.port);
+        }
+    }
+}
+
+fn update_server_list(
+    mut commands: Commands,
+    list: Res<ServerList>,
+    browser: Res<Browser>,
+    node: Query<Entity, With<ServerListNode>>,
+) {
+    let Ok(node) = node.single() else {
+        return;
+    };
+    //The node is spawned on the frame we enter, so the first pass always draws
+    if !list.is_changed() && !browser.is_added() {
+        return;
+    }
+    commands.entity(node).despawn_related::<Children>();
+    commands.entity(node).with_children(|parent| {
+        if list.servers.is_empty() {
+            let text = if browser.discovery.is_some() {
+                "Searching for games on the local network..."
+            } else {
+                "Can't search the local network, type an address below"
+            };
+            parent.spawn((
+                Text::new(text),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.7, 0.7, 0.7)),
+            ));
+        }
+        for server in &list.servers {
+            let info = &server.info;
+            let label = format!(
+                "{}   {}/{}   {}   {}{}",
+                info.name,
+                info.player_count,
+                info.max_players,
+                info.map,
+                server.addr,
+                if info.in_progress { "   (in progress, spectate)" } else { "" },
+            );
+            button(parent, &label, 620.0, BrowserButton::Server(server.addr));
+        }
+    });
+}
+
+fn type_address(
+    mut keys: EventReader<KeyboardInput>,
+    mut browser: ResMut<Browser>,
+    mut address_text: Query<&mut Text, (With<AddressText>, Without<CodeText>)>,
+    mut code_text: Query<&mut Text, With<CodeText>>,
+) {
+    let mut changed = false;
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        let browser = &mut *browser;
+        let (typed, max_len) = if browser.typing_code {
+            (&mut browser.code, MAX_JOIN_CODE_LEN)
+        } else {
+            (&mut browser.address, MAX_ADDRESS_LEN)
+        };
+        match &key.logical_key {
+            Key::Tab => {
+                browser.typing_code = !browser.typing_code;
+                changed = true;
+            }
+            Key::Backspace => {
+                changed |= typed.pop().is_some();
+            }
+            Key::Character(chars) => {
+                for c in chars.chars() {
+                    let allowed = c.is_ascii_alphanumeric() || (!browser.typing_code && ".:-[]".contains(c));
+                    if allowed && typed.len() < max_len {
+                        typed.push(if browser.typing_code { c.to_ascii_uppercase() } else { c });
+                        changed = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if changed {
+        if let Ok(mut text) = address_text.single_mut() {
+            text.0 = address_label(&browser);
+        }
+        if let Ok(mut text) = code_text.single_mut() {
+            text.0 = code_label(&browser);
+        }
+    }
+}
+
+fn browser_buttons(
+    interaction_query: Query<(&Interaction, &BrowserButton), (Changed<Interaction>, With<Button>)>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut config: ResMut<Config>,
+    browser: Res<Browser>,
+    mut list: ResMut<ServerList>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut game_type: ResMut<LogicType>,
+) {
+    let mut pressed: Vec<&BrowserButton> = interaction_query
+        .iter()
+        .filter(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, button)| button)
+        .collect();
+    if keys.just_pressed(KeyCode::Enter) {
+        pressed.push(&BrowserButton::Manual);
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        pressed.push(&BrowserButton::Back);
+    }
+
+    let target = match pressed.first() {
+        Some(BrowserButton::Server(addr)) => (addr.ip().to_string(), addr.port()),
+        Some(BrowserButton::Manual) => match parse_address(&browser.address, config.port) {
+            Some(target) => target,
+            None => {
+                warn!("'{}' is not an address", browser.address);
+                return;
+            }
+        },
+        Some(BrowserButton::Refresh) => {
+            list.servers.clear();
+            if let Some(discovery) = browser.discovery.as_ref() {
+                discovery.query(config.port);
+            }
+            return;
+        }
+        Some(BrowserButton::Back) => {
+            next_state.set(GameState::Menu);
+            return;
+        }
+        None => return,
+    };
+
+    //The client reads the address and code from the config when it connects
+    info!("Joining {}:{}", target.0, target.1);
+    config.server_address = target.0;
+    config.port = target.1;
+    config.join_code = Some(browser.code.clone()).filter(|code| !code.is_empty());
+    game_type.l_type = AssignedType::Client;
+    next_state.set(GameState::Joining);
+}
@@ -0,0 +1,57 @@
+use crate::GameState;
+use bevy::prelude::*;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Fixed tick simulation. Everything that changes the game world runs in FixedUpdate at TICK_RATE,  ///
+///   in the order of SimSet, and steps by TICK_SECS instead of reading the frame time. The same       ///
+///   inputs from the same state then land on the same result, however fast the frames come.           ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Ticks per second. Host and clients have to agree, inputs and snapshots are one per tick.
+pub const TICK_RATE: f64 = 64.;
+//A power of two fraction of a second, so it is exact in an f32
+pub const TICK_SECS: f32 = 1. / TICK_RATE as f32;
+
+//Ticks since the match started, the first one is 1. The host stamps its snapshots with it.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimTick(pub u32);
+
+//One tick of gameplay runs these in order. Systems in the same set don't touch each other's state.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimSet {
+    //Players move from their inputs and shoot
+    Players,
+    //Enemies chase the players where they now are
+    Enemies,
+    Projectiles,
+    //Walls push things back out, stop bullets and open doors
+    Collisions,
+    //Enemy attacks, hits on enemies, sanity and battery, which all decide who gets hurt
+    Combat,
+    //DamagePlayerEvents from Combat are applied
+    Damage,
+}
+
+pub struct SimPlugin;
+impl Plugin for SimPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(TICK_RATE))
+            .init_resource::<SimTick>()
+            .configure_sets(
+                FixedUpdate,
+                (SimSet::Players, SimSet::Enemies, SimSet::Projectiles, SimSet::Collisions, SimSet::Combat, SimSet::Damage)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnEnter(GameState::Playing), reset_tick)
+            .add_systems(FixedFirst, advance_tick.run_if(in_state(GameState::Playing)));
+    }
+}
+
+fn reset_tick(mut tick: ResMut<SimTick>) {
+    *tick = SimTick::default();
+}
+
+fn advance_tick(mut tick: ResMut<SimTick>) {
+    tick.0 += 1;
+}
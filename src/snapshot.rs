@@ -0,0 +1,277 @@
+use crate::protocol::{EntityDelta, HEADER_LEN, MAX_DATAGRAM, SEAL_OVERHEAD, SNAPSHOT_HEADER_LEN, Snapshot};
+use std::collections::{BTreeMap, VecDeque};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   World snapshots. The host sends each client the entities near its player, delta-encoded against    ///
+///   the last snapshot that client acknowledged. Both sides rebuild the full state for a tick the same  ///
+///   way (baseline + deltas), so the host always knows exactly what the client has.                   ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+//Tick 0 is never used, a snapshot with baseline 0 is a full snapshot
+pub const NO_BASELINE: u32 = 0;
+//How many past snapshots either side keeps around to delta against
+pub const SNAPSHOT_HISTORY: usize = 64;
+//A snapshot bigger than this many datagrams is cut down to the highest priority entities
+pub const MAX_FRAGMENTS: usize = 4;
+
+//Quantized position of every entity one client knows about, keyed by network id
+pub type EntityStates = BTreeMap<u16, (i16, i16)>;
+
+//What changed between two states. Removals come first since they are the cheapest and a client
+//holding on to an entity it should have dropped is the worst kind of stale.
+pub fn diff(baseline: &EntityStates, current: &EntityStates) -> Vec<EntityDelta> {
+    let mut out: Vec<EntityDelta> = baseline
+        .keys()
+        .filter(|id| !current.contains_key(id))
+        .map(|&net_id| EntityDelta::removed(net_id))
+        .collect();
+    for (&net_id, &(x, y)) in current {
+        let old = baseline.get(&net_id);
+        let delta = EntityDelta {
+            net_id,
+            x: (old.map(|o| o.0) != Some(x)).then_some(x),
+            y: (old.map(|o| o.1) != Some(y)).then_some(y),
+            removed: false,
+        };
+        if delta.x.is_some() || delta.y.is_some() {
+            out.push(delta);
+        }
+    }
+    out
+}
+
+pub fn apply(baseline: &EntityStates, deltas: &[EntityDelta]) -> EntityStates {
+    let mut state = baseline.clone();
+    for delta in deltas {
+        if delta.removed {
+            state.remove(&delta.net_id);
+            continue;
+        }
+        //A delta for an entity the baseline doesn't have carries both fields
+        let entry = state.entry(delta.net_id).or_insert((0, 0));
+        if let Some(x) = delta.x {
+            entry.0 = x;
+        }
+        if let Some(y) = delta.y {
+            entry.1 = y;
+        }
+    }
+    state
+}
+
+//Splits deltas, already sorted by priority, into datagram sized pieces. Whatever doesn't fit in
+//MAX_FRAGMENTS is left out and will be picked up by a later snapshot.
+pub fn fragment(deltas: &[EntityDelta]) -> Vec<Vec<EntityDelta>> {
+    let budget = MAX_DATAGRAM - HEADER_LEN - SNAPSHOT_HEADER_LEN - SEAL_OVERHEAD;
+    let mut fragments: Vec<Vec<EntityDelta>> = vec![Vec::new()];
+    let mut used = 0;
+    for delta in deltas {
+        let len = delta.encoded_len();
+        if used + len > budget {
+            if fragments.len() == MAX_FRAGMENTS {
+                break;
+            }
+            fragments.push(Vec::new());
+            used = 0;
+        }
+        fragments.last_mut().unwrap().push(*delta);
+        used += len;
+    }
+    fragments
+}
+
+//Host side record of what was sent to one client
+#[derive(Default)]
+pub struct ClientSnapshots {
+    acked: u32,
+    sent: VecDeque<(u32, EntityStates)>,
+}
+
+impl ClientSnapshots {
+    pub fn ack(&mut self, tick: u32) {
+        self.acked = self.acked.max(tick);
+    }
+
+    //The newest acknowledged state still in history, or an empty one for a full snapshot
+    fn baseline(&self) -> (u32, EntityStates) {
+        self.sent
+            .iter()
+            .find(|(tick, _)| *tick == self.acked)
+            .map(|(tick, state)| (*tick, state.clone()))
+            .unwrap_or((NO_BASELINE, EntityStates::new()))
+    }
+
+    //Builds the datagrams for this tick. current should already be limited to what the client
+    //is interested in and sorted so the most important entities come first in priority.
+    pub fn build(&mut self, tick: u32, current: &EntityStates, priority: &[u16]) -> Vec<Snapshot> {
+        let (baseline_tick, baseline) = self.baseline();
+        let mut deltas = diff(&baseline, current);
+        //Removals stay at the front, everything else follows the priority order
+        deltas.sort_by_key(|d| {
+            if d.removed {
+                0
+            } else {
+                1 + priority.iter().position(|id| *id == d.net_id).unwrap_or(priority.len())
+            }
+        });
+        let fragments = fragment(&deltas);
+
+        //Record what the client will have once every fragment arrives, not what we wanted to send
+        let included: Vec<EntityDelta> = fragments.iter().flatten().copied().collect();
+        self.sent.push_back((tick, apply(&baseline, &included)));
+        while self.sent.len() > SNAPSHOT_HISTORY {
+            self.sent.pop_front();
+        }
+
+        let fragment_count = fragments.len() as u8;
+        fragments
+            .into_iter()
+            .enumerate()
+            .map(|(i, entities)| Snapshot {
+                tick,
+                baseline: baseline_tick,
+                fragment: i as u8,
+                fragment_count,
+                entities,
+            })
+            .collect()
+    }
+}
+
+//Client side reassembly and delta decoding
+#[derive(Default)]
+pub struct SnapshotReceiver {
+    received: VecDeque<(u32, EntityStates)>,
+    //tick being reassembled, its baseline and the fragments seen so far
+    partial: Option<(u32, u32, Vec<Option<Vec<EntityDelta>>>)>,
+}
+
+impl SnapshotReceiver {
+    pub fn latest_tick(&self) -> u32 {
+        self.received.back().map(|(tick, _)| *tick).unwrap_or(NO_BASELINE)
+    }
+
+    //Returns the tick and full state once every fragment of a snapshot is in. Old, duplicate or
+    //undecodable snapshots return None.
+    pub fn receive(&mut self, snapshot: Snapshot) -> Option<(u32, &EntityStates)> {
+        if snapshot.tick <= self.latest_tick() || snapshot.fragment >= snapshot.fragment_count {
+            return None;
+        }
+        let same_tick = matches!(&self.partial, Some((tick, _, _)) if *tick == snapshot.tick);
+        if !same_tick {
+            //A newer tick replaces whatever was half assembled, an older one is dropped
+            if matches!(&self.partial, Some((tick, _, _)) if *tick > snapshot.tick) {
+                return None;
+            }
+            self.partial = Some((
+                snapshot.tick,
+                snapshot.baseline,
+                vec![None; snapshot.fragment_count as usize],
+            ));
+        }
+        let (tick, baseline_tick, fragments) = self.partial.as_mut().unwrap();
+        if let Some(slot) = fragments.get_mut(snapshot.fragment as usize) {
+            *slot = Some(snapshot.entities);
+        }
+        if fragments.iter().any(|f| f.is_none()) {
+            return None;
+        }
+        let (tick, baseline_tick) = (*tick, *baseline_tick);
+        let deltas: Vec<EntityDelta> = self.partial.take().unwrap().2.into_iter().flatten().flatten().collect();
+
+        let baseline = if baseline_tick == NO_BASELINE {
+            EntityStates::new()
+        } else {
+            //Without the baseline there is nothing to apply the deltas to, wait for a newer one
+            self.received.iter().find(|(t, _)| *t == baseline_tick)?.1.clone()
+        };
+        self.received.push_back((tick, apply(&baseline, &deltas)));
+        while self.received.len() > SNAPSHOT_HISTORY {
+            self.received.pop_front();
+        }
+        self.received.back().map(|(tick, state)| (*tick, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(entries: &[(u16, i16, i16)]) -> EntityStates {
+        entries.iter().map(|&(id, x, y)| (id, (x, y))).collect()
+    }
+
+    #[test]
+    fn diff_then_apply_rebuilds_current() {
+        let baseline = states(&[(1, 0, 0), (2, 10, 10), (300, 5, 5)]);
+        let current = states(&[(2, 10, 11), (300, 5, 5), (60_000, -3, 4)]);
+        let deltas = diff(&baseline, &current);
+        assert_eq!(apply(&baseline, &deltas), current);
+        //Unchanged entity 300 isn't sent, entity 1 is removed and only y changed on 2
+        assert_eq!(deltas.len(), 3);
+        assert!(deltas[0].removed && deltas[0].net_id == 1);
+        assert_eq!(deltas[1], EntityDelta { net_id: 2, x: None, y: Some(11), removed: false });
+    }
+
+    #[test]
+    fn big_snapshots_are_fragmented_and_capped() {
+        let current: EntityStates = (0..2000u16).map(|id| (id, (id as i16, -(id as i16)))).collect();
+        let deltas = diff(&EntityStates::new(), &current);
+        let fragments = fragment(&deltas);
+        assert_eq!(fragments.len(), MAX_FRAGMENTS);
+        for f in &fragments {
+            let bytes = Snapshot { tick: 1, baseline: 0, fragment: 0, fragment_count: 1, entities: f.clone() };
+            assert!(crate::protocol::Message::Snapshot(bytes).to_bytes().len() + SEAL_OVERHEAD <= MAX_DATAGRAM);
+        }
+        //Highest priority entities made it in
+        assert_eq!(fragments[0][0].net_id, 0);
+    }
+
+    #[test]
+    fn host_and_client_agree_across_acks_and_loss() {
+        let mut host = ClientSnapshots::default();
+        let mut client = SnapshotReceiver::default();
+
+        let first = states(&[(1, 0, 0), (2, 64, 64)]);
+        let snaps = host.build(1, &first, &[1, 2]);
+        assert_eq!(snaps[0].baseline, NO_BASELINE);
+        let (tick, state) = client.receive(snaps[0].clone()).unwrap();
+        assert_eq!((tick, state), (1, &first));
+        host.ack(tick);
+
+        //Tick 2 is lost entirely, tick 3 is still a delta against the acked tick 1
+        let second = states(&[(1, 5, 0), (2, 64, 64)]);
+        host.build(2, &second, &[1, 2]);
+        let third = states(&[(1, 9, 0), (3, 1, 1)]);
+        let snaps = host.build(3, &third, &[1, 3]);
+        assert_eq!(snaps[0].baseline, 1);
+        assert_eq!(client.receive(snaps[0].clone()).unwrap().1, &third);
+    }
+
+    #[test]
+    fn fragments_reassemble_in_any_order() {
+        let mut host = ClientSnapshots::default();
+        let mut client = SnapshotReceiver::default();
+        let current: EntityStates = (0..400u16).map(|id| (id, (id as i16, 7))).collect();
+        let priority: Vec<u16> = current.keys().copied().collect();
+        let mut snaps = host.build(1, &current, &priority);
+        assert!(snaps.len() > 1);
+        snaps.reverse();
+        let last = snaps.pop().unwrap();
+        for snap in snaps {
+            assert!(client.receive(snap).is_none());
+        }
+        assert_eq!(client.receive(last).unwrap().1, &current);
+    }
+
+    #[test]
+    fn stale_snapshots_are_ignored() {
+        let mut host = ClientSnapshots::default();
+        let mut client = SnapshotReceiver::default();
+        let old = host.build(1, &states(&[(1, 1, 1)]), &[1]);
+        let new = host.build(2, &states(&[(1, 2, 2)]), &[1]);
+        assert!(client.receive(new[0].clone()).is_some());
+        assert!(client.receive(old[0].clone()).is_none());
+        assert_eq!(client.latest_tick(), 2);
+    }
+}
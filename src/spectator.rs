@@ -0,0 +1,177 @@
+use crate::{
+    GameState,
+    chat::player_name,
+    components::Dead,
+    lobby::LobbyRoster,
+    net_control::{NetControl, PlayerType},
+    player::Player,
+    replay::Playback,
+};
+use bevy::prelude::*;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+///   Spectating. Once our own player is dead, or when we joined too late to have one, the camera      ///
+///   follows one of the living players instead. Tab or the arrow keys move between them. A client     ///
+///   tells the host who it is watching so its snapshots are centered there.                          ///
+////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub const NEXT_KEYS: [KeyCode; 2] = [KeyCode::Tab, KeyCode::ArrowRight];
+pub const PREVIOUS_KEY: KeyCode = KeyCode::ArrowLeft;
+
+//Whether we are spectating, and the player_id of who the camera is on
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spectating {
+    pub active: bool,
+    pub target: Option<u8>,
+}
+
+pub struct SpectatorPlugin;
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Spectating>()
+            .add_systems(OnEnter(GameState::Playing), setup_spectator_hud)
+            .add_systems(OnExit(GameState::Playing), cleanup_spectator_hud)
+            .add_systems(
+                Update,
+                (pick_target, show_spectator_hud)
+                    .chain()
+                    .run_if(in_state(GameState::Playing))
+                    //The arrow keys seek in a replay, which has its own camera
+                    .run_if(not(resource_exists::<Playback>)),
+            );
+    }
+}
+
+#[derive(Component)]
+struct SpectatorHud;
+
+//Who to watch out of the living player ids (sorted), stepping forward or back from the current one.
+//If the current one is gone the next id up takes over.
+pub fn cycle(living: &[u8], current: Option<u8>, step: i32) -> Option<u8> {
+    if living.is_empty() {
+        return None;
+    }
+    let Some(current) = current else {
+        return Some(living[0]);
+    };
+    match living.iter().position(|id| *id == current) {
+        Some(i) => Some(living[(i as i32 + step).rem_euclid(living.len() as i32) as usize]),
+        None => Some(living.iter().copied().find(|id| *id > current).unwrap_or(living[0])),
+    }
+}
+
+fn setup_spectator_hud(mut commands: Commands, mut spectating: ResMut<Spectating>) {
+    *spectating = Spectating::default();
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        Visibility::Hidden,
+        SpectatorHud,
+        children![(
+            Text::new(""),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        )],
+    ));
+}
+
+fn cleanup_spectator_hud(mut commands: Commands, hud: Query<Entity, With<SpectatorHud>>) {
+    for entity in &hud {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn pick_target(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut spectating: ResMut<Spectating>,
+    players: Query<(&NetControl, Has<Player>, Has<Dead>)>,
+) {
+    //A late joiner has no NetControl of its own at all
+    let active = players
+        .iter()
+        .find(|(control, ..)| control.get_type() == PlayerType::Local)
+        .is_none_or(|(_, _, dead)| dead);
+    if !active {
+        if *spectating != Spectating::default() {
+            *spectating = Spectating::default();
+        }
+        return;
+    }
+
+    let mut living: Vec<u8> = players
+        .iter()
+        .filter(|(_, player, dead)| *player && !dead)
+        .map(|(control, ..)| control.player_id)
+        .collect();
+    living.sort_unstable();
+    let step = if keys.any_just_pressed(NEXT_KEYS) {
+        1
+    } else if keys.just_pressed(PREVIOUS_KEY) {
+        -1
+    } else {
+        0
+    };
+    let next = Spectating { active, target: cycle(&living, spectating.target, step) };
+    if *spectating != next {
+        *spectating = next;
+    }
+}
+
+fn show_spectator_hud(
+    spectating: Res<Spectating>,
+    roster: Res<LobbyRoster>,
+    mut hud: Query<(&mut Visibility, &Children), With<SpectatorHud>>,
+    mut texts: Query<&mut Text>,
+) {
+    if !spectating.is_changed() {
+        return;
+    }
+    for (mut visibility, children) in &mut hud {
+        *visibility = if spectating.active { Visibility::Visible } else { Visibility::Hidden };
+        let line = match spectating.target {
+            Some(id) => format!("Spectating {}   (Tab / arrows to switch)", player_name(&roster, id)),
+            None => "Nobody left to spectate".to_string(),
+        };
+        for child in children {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.0 = line.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_on_the_first_living_player() {
+        assert_eq!(cycle(&[], None, 0), None);
+        assert_eq!(cycle(&[], Some(2), 1), None);
+        assert_eq!(cycle(&[1, 3], None, 0), Some(1));
+    }
+
+    #[test]
+    fn steps_wrap_around() {
+        let living = [0, 2, 3];
+        assert_eq!(cycle(&living, Some(2), 0), Some(2));
+        assert_eq!(cycle(&living, Some(2), 1), Some(3));
+        assert_eq!(cycle(&living, Some(3), 1), Some(0));
+        assert_eq!(cycle(&living, Some(0), -1), Some(3));
+    }
+
+    #[test]
+    fn moves_on_when_the_target_dies() {
+        assert_eq!(cycle(&[0, 3], Some(2), 0), Some(3));
+        assert_eq!(cycle(&[0, 1], Some(2), 0), Some(0));
+    }
+}
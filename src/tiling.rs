@@ -1,5 +1,6 @@
 use crate::camera::MapBounds;
 use crate::{GameState, WIN_H, WIN_W};
+use crate::level::{CurrentLevel, Level};
 use bevy::prelude::*;
 
 const TILE_SIZE: u32 = 64;
@@ -18,155 +19,44 @@ pub fn setup_tiling(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlasLayout>>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
 ) {
+    let Some(level) = levels.get(&current_level.handle) else {
+        error!("Level is not loaded, no tiles will be placed");
+        return;
+    };
+
     let tile1 = asset_server.load("textures/tile_sheet.png");
     let ground_layout = TextureAtlasLayout::from_grid(UVec2::splat(TILE_SIZE), 8, 2, None, None);
     let ground_layout_len = ground_layout.textures.len();
     let ground_layout_handle = texture_atlases.add(ground_layout);
 
-    // Map is 100, 64px tiles by 100, 64px tiles
-    let map_width = (TILE_SIZE as f32) * 100.0;
-    let map_height = (TILE_SIZE as f32) * 100.0;
-
-    //We currently only tile over the 1280x720 window, we can adjust this to instead work with level size
-    let x_bound = map_width / 2.0 - (TILE_SIZE as f32) / 2.0;
-    let y_bound = map_height / 2.0 - (TILE_SIZE as f32) / 2.0;
-
-    let mut x = 0;
-    let mut y = 0;
-    let mut t = Vec3::new(-x_bound, -y_bound, -10.);
-
-    let tilemap = [
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,10,10,10,10,10,10,10,10,10,10,10,10,10,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,2,2,2,2,1,2,2,2,2,2,2,2,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,10,10,10,10,10,10,10,10,10,10,10,10,10,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,14,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,10,10,10,10,10,10,10,10,10,10,10,10,10,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,14,14,14,14,14,14,14,14,14,14,1,4,4,4,1,1,9,9,9,1,1,4,4,4,4,10,10,10,10,10,10,10,10,10,10,10,10,10,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,2,2,2,2,1,2,2,2,2,2,2,2,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,14,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,2,2,2,2,2,2,2,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,14,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,2,2,2,2,4,4,4,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,2,2,2,2,4,4,4,2,2,2,2,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,2,2,2,2,4,4,4,2,2,2,2,1,10,10,10,10,10,10,10],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,14,14,1,1,4,4,4,1,1,1,1,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,6,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,2,2,2,2,4,4,4,2,2,2,2,1,10,10,10,10,10,10,10],
-[1,8,8,1,8,8,1,8,8,1,8,8,1,14,14,14,1,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,1,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,6,6,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,1,1,2,2,4,4,4,2,2,1,1,1,6,6,6,10,10,10,10],
-[1,8,8,1,8,8,1,8,8,1,8,8,1,14,14,14,1,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,6,6,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,6,6,6,10,10,10,10],
-[1,8,8,1,8,8,1,8,8,1,8,8,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,4,4,4,1,9,9,9,9,9,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,6,6,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,6,6,6,10,10,10,10],
-[1,8,8,1,8,8,1,8,8,1,8,8,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,4,4,4,1,1,1,1,1,1,1,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,6,6,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,1,1,5,5,4,4,4,5,5,1,1,1,6,6,6,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,14,14,14,14,14,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,6,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,14,14,14,14,14,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,1,5,5,5,5,5,5,5,5,1,14,14,14,1,14,14,14,14,14,14,14,14,14,1,4,4,4,4,4,4,4,4,4,4,4,4,4,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,4,4,4,4,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,1,1,1,1,1,1,1,1,1,1,1,1,12,12,1,1,1,1,1,1,1,1,1,1,1,1,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,5,5,5,5,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,10,10,10,10,10,10,10],
-[3,3,3,3,3,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,1,4,4,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,12,12,12,12,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,1,15,15,15,15,12,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,1,15,15,15,15,12,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,6,6,6,6,6,6,6,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,5,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,15,15,15,15,15,1,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10],
-[1,5,5,5,5,5,5,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,15,15,15,15,15,1,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],
-[1,5,5,5,5,5,5,1,12,12,12,1,5,5,1,1,5,5,1,12,12,12,1,5,5,1,15,15,15,15,1,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,1,5,5,5,5,5,5,5,5,1,4,4,4,1,4,4,4,4,4,4,4,5,5,5,5,5,5,5,5,1],
-[1,5,5,5,5,5,5,1,12,12,12,1,5,5,5,5,5,5,1,12,12,12,1,5,5,1,15,15,15,15,1,12,12,12,12,12,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,1,5,5,5,5,5,5,5,5,1,4,4,4,1,4,4,4,4,4,4,4,5,5,5,5,5,5,5,5,1],
-[1,5,5,5,5,1,1,1,12,12,12,1,5,5,5,5,5,5,1,12,12,12,1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,6,6,6,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,6,6,10,10,1,5,5,5,5,5,1,5,5,1,4,4,4,1,4,4,4,4,4,4,4,5,5,5,5,5,5,5,5,1],
-[1,5,5,5,5,5,5,1,12,12,12,1,5,5,5,5,5,5,1,12,12,12,1,5,5,1,15,15,15,15,1,9,9,9,9,9,1,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,1,5,5,5,5,5,1,5,5,1,4,4,4,1,4,4,4,4,4,4,4,5,5,5,5,5,5,5,5,1],
-[1,5,5,5,5,5,5,1,12,12,12,1,1,5,5,5,5,1,1,12,12,12,1,5,5,1,15,15,15,15,1,9,9,9,9,9,1,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,1,5,5,5,5,5,1,5,5,1,4,4,4,1,4,4,4,4,4,4,4,1,5,5,5,1,1,1,1,1],
-[1,5,5,5,5,5,5,1,12,12,12,12,1,1,1,1,1,1,12,12,12,12,1,5,5,15,15,15,15,15,1,9,9,9,9,9,1,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,1,5,5,1,1,1,1,5,5,1,4,4,4,1,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,5,5,5,5,5,5,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,15,15,15,15,15,9,9,9,9,9,9,1,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,6,10,10,1,5,5,5,5,5,5,5,5,4,4,4,4,1,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,5,5,5,5,5,5,1,12,12,12,12,12,12,12,12,12,12,12,12,12,12,1,5,5,1,15,15,15,15,9,9,9,9,9,9,1,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,5,5,5,5,5,5,5,5,4,4,4,4,1,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,5,5,1,1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,10,1,1,1,1,1,1,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,1,15,15,1,1,1,1,1,15,15,1,1,1,1,1,15,15,1,1,1,1,1,15,15,1,1,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,1,1,1,1,1,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,1,8,8,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,1,1,1,1,1,1,1,1,4,4,4,1,4,4,4,1,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,8,8,8,1,8,8,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,4,4,4,4,4,4,4,4,4,4,4,1,4,4,4,1,4,4,1,1,1,8,8,8,8,8,8,8,1],
-[1,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,1,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,15,15,15,15,15,15,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,1,1,1,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,5,5,5,5,1,1,1,5,5,5,5,5,5,5,5,8,8,8,1,8,8,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,1,8,8,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,1,8,8,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,4,4,1,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1,4,4,4,4,4,4,4,4,4,4,4,4,4,1,4,4,4,4,4,4,1,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,3,3,3,1,5,5,5,5,5,5,5,5,1,1,1,1,5,5,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,16,16,16,16,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,6,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,5,5,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,6,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,5,5,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,6,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,5,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,6,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,5,1,5,5,5,5,5,5,5,5,5,1,4,4,4,1,1,1,1,1,1,1,1,4,4,4,1,16,16,1,1,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,3,1,5,5,5,5,5,5,5,5,1,1,1,1,5,5,1,1,1,1,5,5,5,5,5,5,5,5,5,1,4,4,4,1,1,1,1,1,1,1,1,4,4,4,1,16,16,16,16,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,3,1,5,5,5,5,5,5,5,5,1,6,6,6,6,6,6,6,6,6,5,5,5,5,5,5,5,5,5,1,4,4,4,1,1,1,1,1,1,1,1,4,4,4,1,16,16,16,16,1,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,8,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,3,1,1,1,1,1,1,1,1,1,1,6,6,6,6,6,6,6,6,6,5,5,5,5,5,5,5,5,5,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,3,1,1,1,1,1,1,1,1,1,1,6,6,6,6,6,6,6,6,6,5,5,5,5,5,5,5,5,5,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,5,5,5,5,5,5,5,5,5,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,16,16,16,16,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1,8,8,8,1],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,1,1,1,1,1,1,1,1,1,1,1,1,1,5,5,1,1,1,1,1,1,1,5,5,1,1,1,1,1,5,5,1,1,1,16,16,1,1,1,8,8,8,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,1,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,1,1,1,5,5,5,5,5,5,5,5,5,5,5,5,5,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,2,2,2,1,2,2,2,1,7,7,7,7],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,1,1,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,1,1,1,1,5,5,5,5,5,5,5,5,5,5,5,5,5,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,2,2,2,1,2,2,2,1,7,7,7,7],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,5,5,1,1,1,1,1,1,1,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,2,2,2,2,2,2,2,2,2,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,2,2,2,2,2,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,16,16,16,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,2,2,2,2,2,2,2,2,2,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,1,1,4,4,1,6,6,6,6,6,6,6,6,6,6,6,2,2,2,2,2,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,16,16,16,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,2,2,2,1,2,2,2,1,7,7,7,7],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,1,1,4,4,4,6,6,6,6,6,6,6,6,6,6,6,2,2,2,2,2,2,2,2,2,1,1,1,1,1,1,5,5,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,2,2,2,1,2,2,2,1,7,7,7,7],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,2,2,2,2,2,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,1,1,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,2,2,2,2,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,1,1,1,1,1,1,1,1,1,6,6,6,6,6,6,6,6,6,6,6,1,1,1,1,1,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,16,16,16,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,5,5,5,5,5,5,5,1,16,16,16,16,16,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,1,1,5,5,5,1,1,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,3,4,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,6,6,6,4,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,1,16,16,1,1,1,16,16,1,14,14,14,14,14,14,14,14,14,14,14,14,14,1,7,7,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,6,6,6,4,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,1,16,16,16,16,16,16,16,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,7,7,1,1,1,1,1,1,7,7,7,7],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,3,3,3,4,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,1,1,2,2,1,11,11,11,11,11,11,11,1,16,16,16,16,16,16,16,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,1,1,1,16,16,16,1,1,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,1,13,13,13,13,3,3,13,13,13,13,1,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,1,13,13,13,1,3,3,1,13,13,13,1,3,3,3,3,3,3,3,3,3,3,3,1,1,1,1,1,1,1,1,1,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,1,1,1,1,1,3,3,1,1,1,1,1,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,4,6,6,6,6,6,6,6,6,6,6,6,14,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,6,6,6,6,6,6,6,6,6,6,6,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,4,4,4,4,4,1,1,1,1,1,1,1,1,1,1,1,1,1,14,14,14,1,2,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,4,4,4,1,1,1,1,1,1,2,2,2,2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,2,2,2,1,11,11,11,11,11,11,11,11,11,11,11,11,11,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,1,1,1,1,2,2,1,1,1,1,2,2,2,2,1,1,2,1,1,1,1,2,2,1,2,2,2,1,11,11,1,1,4,4,1,1,1,1,1,1,1,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,1,2,2,2,2,2,2,2,1,2,2,2,1,11,11,1,4,4,4,4,4,4,4,4,4,1,11,11,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,7,7,1,1,1,1],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,2,2,2,1,11,11,1,4,4,4,4,4,4,4,4,4,1,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,1,2,2,1,2,2,1,2,2,1,2,2,2,2,1,2,2,2,2,2,2,2,2,1,2,2,2,1,11,11,1,4,4,4,4,1,4,4,4,4,1,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,1,2,2,1,2,2,1,2,2,1,2,2,2,2,1,2,2,2,2,2,2,2,2,2,2,2,2,1,11,11,1,4,1,1,1,1,4,4,1,1,1,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,1,2,2,1,2,2,1,2,2,1,2,2,2,2,1,2,2,2,2,1,1,2,2,2,2,2,2,1,11,11,1,4,4,4,4,4,4,4,1,1,1,11,11,1,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,1,2,2,1,2,2,1,2,2,1,2,2,2,2,1,2,2,2,2,1,1,2,2,2,2,2,2,1,11,11,1,4,4,4,4,4,4,4,4,4,1,11,11,1,7,7,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,12,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,11,11,1,4,4,4,1,1,4,4,4,4,1,11,11,1,7,7,12,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,12,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,11,11,1,4,4,4,1,1,4,4,4,4,1,11,11,1,7,7,12,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,12,7,7],
-[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,11,11,1,1,1,1,1,1,1,1,1,1,1,11,11,1,7,7,12,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,9,12,7,7],
-        ];
+    // Map is level.width, 64px tiles by level.height, 64px tiles
+    let map_width = level.map_width();
+    let map_height = level.map_height();
 
     // Tile the entire map area
-    while (y as f32) * (TILE_SIZE as f32) < map_height {
-        while (x as f32) * (TILE_SIZE as f32) < map_width {
+    for y in 0..level.height {
+        for x in 0..level.width {
+            let pos = level.tile_to_world(x, y);
             commands.spawn((
                 Sprite::from_atlas_image(
                     tile1.clone(),
                     TextureAtlas {
                         layout: ground_layout_handle.clone(),
 
-                        //This will randomly select a tile from the map, to add variety
-                        index: (tilemap[99-y][x]-1), //rand::random_range(0..ground_layout_len),
+                        //Tile ids in the level file are 1 based
+                        index: level.tiles[y][x].saturating_sub(1).min(ground_layout_len - 1),
                     },
                 ),
                 Transform {
-                    translation: t,
+                    translation: pos.extend(-10.),
                     ..default()
                 },
                 Tile,
             ));
-
-            x += 1;
-            t += Vec3::new(TILE_SIZE as f32, 0., 0.);
         }
-        x = 0;
-        t = Vec3::new(-x_bound, -y_bound, -10.);
-
-        y += 1;
-        t += Vec3::new(0., (y as f32) * (TILE_SIZE as f32), 0.);
     }
 
     // Insert map bounds resource
@@ -1,4 +1,4 @@
-use crate::{GameState, player::Player};
+use crate::{GameState, player::Player, type_is_authority};
 use crate::inventory_ui::{setup_revive_ui, update_revive_ui};
 use crate::{
     collectible::PlayerInventory, components::Health, components::Sanity, events::DamagePlayerEvent,
@@ -11,13 +11,15 @@ impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Playing), setup_ui)
             .add_systems(OnExit(GameState::Playing), cleanup_ui)
-            .add_systems(Update, update_health_ui.run_if(in_state(GameState::Playing)))
-            .add_systems(Update, update_sanity_ui.run_if(in_state(GameState::Playing)))
-            .add_systems(Update, update_battery_ui.run_if(in_state(GameState::Playing)))
+            //A spectator that joined late has no player of its own
+            .add_systems(Update, update_health_ui.run_if(in_state(GameState::Playing)).run_if(resource_exists::<LocalPlayer>))
+            .add_systems(Update, update_sanity_ui.run_if(in_state(GameState::Playing)).run_if(resource_exists::<LocalPlayer>))
+            .add_systems(Update, update_battery_ui.run_if(in_state(GameState::Playing)).run_if(resource_exists::<LocalPlayer>))
             //.add_systems(Update, player_damage.run_if(in_state(GameState::Playing)))
             .add_systems(OnEnter(GameState::Playing), setup_revive_ui)
             .add_systems(Update, update_revive_ui.run_if(in_state(GameState::Playing)))
-            .add_systems(Update, (player_damage, update_ammo_ui).run_if(in_state(GameState::Playing)));
+            .add_systems(Update, player_damage.run_if(in_state(GameState::Playing)).run_if(type_is_authority))
+            .add_systems(Update, update_ammo_ui.run_if(in_state(GameState::Playing)).run_if(resource_exists::<LocalPlayer>));
     }
 }
 
@@ -1,11 +1,12 @@
-use crate::{GameState, components::Dead, player::Player};
+use crate::{GameState, components::Dead, player::Player, level::CurrentLevel, level::Level, replay::Playback};
 use bevy::prelude::*;
 
 pub struct WinLossPlugin;
 impl Plugin for WinLossPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, check_for_win.run_if(in_state(GameState::Playing)))
-            .add_systems(Update, check_for_lose.run_if(in_state(GameState::Playing)));
+        //A replay stops on its last tick instead
+        app.add_systems(Update, check_for_win.run_if(in_state(GameState::Playing)).run_if(not(resource_exists::<Playback>)))
+            .add_systems(Update, check_for_lose.run_if(in_state(GameState::Playing)).run_if(not(resource_exists::<Playback>)));
     }
 }
 
@@ -16,12 +17,17 @@ fn check_for_win(
         (&Transform),
         (With<Player>, Without<Dead>),
     >,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
 ) {
+    let Some(level) = levels.get(&current_level.handle) else {
+        return;
+    };
     if !player_loc.is_empty() {
         let mut flag = true;
         for trans in player_loc {
-            let extract_range = (Vec3::new(2350., -3100., trans.translation.z) - trans.translation).length();
-            if extract_range > 250. {
+            let extract_range = (level.extraction_position().extend(trans.translation.z) - trans.translation).length();
+            if extract_range > level.extraction.radius {
                 flag = false;
             } 
         }